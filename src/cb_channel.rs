@@ -1,4 +1,4 @@
-use async_std::channel::{self, Receiver, RecvError, SendError, Sender};
+use async_std::channel::{self, Receiver, RecvError, SendError, Sender, TryRecvError};
 
 #[derive(Debug)]
 pub struct Replier<R> {
@@ -61,6 +61,10 @@ impl<T, R> ReturningReceiver<T, R> {
     pub async fn recv(&self) -> Result<(T, Replier<R>), RecvError> {
         self.receiver.recv().await
     }
+
+    pub fn try_recv(&self) -> Result<(T, Replier<R>), TryRecvError> {
+        self.receiver.try_recv()
+    }
 }
 
 impl<R> Replier<R> {
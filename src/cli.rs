@@ -0,0 +1,493 @@
+//! Command-line parsing and remote commands
+//!
+//! `jackctl` without a subcommand starts the control panel.  All
+//! subcommands talk to the instance that is already running, over its
+//! `net.jackctl.Control` D-Bus interface.
+
+use crate::model::settings::{self, Settings};
+use crate::model::{
+    events::{CardInfo, ControlCmd, ControlReply, MuteCmd, VolumeCmd},
+    graph,
+    port::{Port, PortDirection, PortType},
+};
+use crate::rts::control::bus::{BUS_NAME, INTERFACE, OBJECT_PATH};
+use dbus::blocking::Connection;
+use serde_json::json;
+use std::{fs, path::PathBuf, time::Duration};
+
+const USAGE: &str = "Usage: jackctl [--headless]
+       jackctl [--json] <command> [args...]
+
+Commands (sent to the running jackctl):
+    ports                              List all ports
+    connections                        List all connections
+    connect <output> <input>           Connect two ports
+    disconnect <output> <input>        Disconnect two ports
+    cards                              List sound cards
+    volume <card> <channel> <value>    Set the volume of a mixer channel
+    mute <card> <channel> <on|off>     Mute or unmute a mixer channel
+    quit                               Shut jackctl down
+
+Commands (work with or without a running jackctl):
+    export [file]                      Export all settings as TOML
+    import <file>                      Import settings exported before
+
+Ports are given as 'client:port', channels as 'Name' or 'Name,index'.";
+
+/// How long to wait for the running instance to answer
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the user asked for on the command line
+#[derive(Debug)]
+pub enum Invocation {
+    /// Start the control panel
+    Run { headless: bool },
+    /// Send a command to the running instance
+    Remote { cmd: ControlCmd, json: bool },
+    /// Write all settings to a file, or stdout
+    Export { path: Option<PathBuf> },
+    /// Load settings from a file
+    Import { path: PathBuf },
+    /// Print usage information
+    Help,
+}
+
+/// Parse the arguments, without the program name
+pub fn parse(args: &[String]) -> Result<Invocation, String> {
+    let mut headless = false;
+    let mut json = false;
+    let mut words = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--headless" => headless = true,
+            "--json" => json = true,
+            "-h" | "--help" => return Ok(Invocation::Help),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            word => words.push(word),
+        }
+    }
+
+    let cmd = match words.as_slice() {
+        [] => return Ok(Invocation::Run { headless }),
+        ["ports"] => ControlCmd::ListPorts,
+        ["connections"] => ControlCmd::ListConnections,
+        ["cards"] => ControlCmd::ListCards,
+        ["quit"] => ControlCmd::Shutdown,
+        ["export"] => return Ok(Invocation::Export { path: None }),
+        ["export", path] => {
+            return Ok(Invocation::Export {
+                path: Some(path.into()),
+            })
+        }
+        ["import", path] => return Ok(Invocation::Import { path: path.into() }),
+        ["connect", output, input] => ControlCmd::Connect {
+            output: output.to_string(),
+            input: input.to_string(),
+        },
+        ["disconnect", output, input] => ControlCmd::Disconnect {
+            output: output.to_string(),
+            input: input.to_string(),
+        },
+        ["volume", card, channel, volume] => ControlCmd::SetVolume(VolumeCmd {
+            card: number(card, "card")?,
+            channel: channel_id(channel)?,
+            volume: number(volume, "volume")?,
+        }),
+        ["mute", card, channel, mute] => ControlCmd::SetMute(MuteCmd {
+            card: number(card, "card")?,
+            channel: channel_id(channel)?,
+            mute: match *mute {
+                "on" | "true" | "1" => true,
+                "off" | "false" | "0" => false,
+                m => return Err(format!("Expected 'on' or 'off', got '{}'", m)),
+            },
+        }),
+        [cmd, ..] => return Err(format!("Unknown command or wrong arguments: {}", cmd)),
+    };
+
+    Ok(Invocation::Remote { cmd, json })
+}
+
+fn number<T: std::str::FromStr>(s: &str, what: &str) -> Result<T, String> {
+    s.parse()
+        .map_err(|_| format!("Invalid {} '{}', expected a number", what, s))
+}
+
+/// Parse `Name` or `Name,index` (like amixer does)
+fn channel_id(s: &str) -> Result<(u32, String), String> {
+    match s.rfind(',') {
+        Some(i) => Ok((number(&s[i + 1..], "channel index")?, s[..i].into())),
+        None => Ok((0, s.into())),
+    }
+}
+
+pub fn usage() -> &'static str {
+    USAGE
+}
+
+/// Run a command against the running instance, returning the exit code
+pub fn run_remote(cmd: ControlCmd, json: bool) -> i32 {
+    let reply = match Connection::new_session() {
+        Ok(conn) => call(&conn, &cmd),
+        Err(e) => Err(format!("Can't connect to the session bus: {}", e)),
+    };
+
+    match reply {
+        Ok(ControlReply::Error(e)) | Err(e) => {
+            eprintln!("jackctl: {}", e);
+            1
+        }
+        Ok(reply) => {
+            print_reply(&reply, json);
+            0
+        }
+    }
+}
+
+/// Export all settings, from the running instance if there is one
+pub fn export(path: Option<PathBuf>) -> i32 {
+    let doc = match Connection::new_session() {
+        Ok(ref conn) if is_running(conn) => match call(conn, &ControlCmd::ExportSettings) {
+            Ok(ControlReply::Exported(doc)) => Ok(doc),
+            Ok(ControlReply::Error(e)) | Err(e) => Err(e),
+            Ok(reply) => Err(format!("Unexpected reply: {:?}", reply)),
+        },
+        _ => local_settings().and_then(|s| s.export().map_err(|e| e.to_string())),
+    };
+
+    let written = doc.and_then(|doc| match path {
+        Some(ref path) => fs::write(path, doc).map_err(|e| e.to_string()),
+        None => {
+            print!("{}", doc);
+            Ok(())
+        }
+    });
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("jackctl: Can't export settings: {}", e);
+            1
+        }
+    }
+}
+
+/// Import settings, into the running instance if there is one
+pub fn import(path: PathBuf) -> i32 {
+    let doc = match fs::read_to_string(&path) {
+        Ok(doc) => doc,
+        Err(e) => {
+            eprintln!("jackctl: Can't read {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+
+    let imported = match Connection::new_session() {
+        Ok(ref conn) if is_running(conn) => match call(conn, &ControlCmd::ImportSettings(doc)) {
+            Ok(ControlReply::Error(e)) | Err(e) => Err(e),
+            Ok(_) => Ok(()),
+        },
+        _ => local_settings().and_then(|s| s.import(&doc).map_err(|e| e.to_string())),
+    };
+
+    match imported {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("jackctl: Can't import settings: {}", e);
+            1
+        }
+    }
+}
+
+/// Open the settings files directly, when no instance is running
+fn local_settings() -> Result<std::sync::Arc<Settings>, String> {
+    let dir = settings::scaffold();
+    Settings::init(dir.config_dir()).map_err(|e| e.to_string())
+}
+
+fn is_running(conn: &Connection) -> bool {
+    let proxy = conn.with_proxy(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        CALL_TIMEOUT,
+    );
+    proxy
+        .method_call("org.freedesktop.DBus", "NameHasOwner", (BUS_NAME,))
+        .map(|(owned,): (bool,)| owned)
+        .unwrap_or(false)
+}
+
+/// Perform a control command over D-Bus
+fn call(conn: &Connection, cmd: &ControlCmd) -> Result<ControlReply, String> {
+    let proxy = conn.with_proxy(BUS_NAME, OBJECT_PATH, CALL_TIMEOUT);
+    let err = |e: dbus::Error| match e.name() {
+        Some("org.freedesktop.DBus.Error.ServiceUnknown") => "jackctl is not running".to_owned(),
+        _ => e.message().unwrap_or("unknown error").to_owned(),
+    };
+
+    let reply = match cmd {
+        ControlCmd::ListPorts => {
+            let (ports,): (Vec<(u32, String, String, String)>,) =
+                proxy.method_call(INTERFACE, "ListPorts", ()).map_err(err)?;
+            ControlReply::Ports(ports.into_iter().filter_map(port).collect())
+        }
+        ControlCmd::ListConnections => {
+            let (connections,): (Vec<(String, String)>,) = proxy
+                .method_call(INTERFACE, "ListConnections", ())
+                .map_err(err)?;
+            ControlReply::Connections(connections)
+        }
+        ControlCmd::ListCards => {
+            let (cards,): (Vec<(i32, String, bool)>,) =
+                proxy.method_call(INTERFACE, "ListCards", ()).map_err(err)?;
+            ControlReply::Cards(
+                cards
+                    .into_iter()
+                    .map(|(id, name, active)| CardInfo { id, name, active })
+                    .collect(),
+            )
+        }
+        ControlCmd::Connect { output, input } => {
+            let () = proxy
+                .method_call(INTERFACE, "Connect", (output.as_str(), input.as_str()))
+                .map_err(err)?;
+            ControlReply::Ok
+        }
+        ControlCmd::Disconnect { output, input } => {
+            let () = proxy
+                .method_call(INTERFACE, "Disconnect", (output.as_str(), input.as_str()))
+                .map_err(err)?;
+            ControlReply::Ok
+        }
+        ControlCmd::SetVolume(v) => {
+            let args = (v.card, v.channel.0, v.channel.1.as_str(), v.volume);
+            let () = proxy
+                .method_call(INTERFACE, "SetVolume", args)
+                .map_err(err)?;
+            ControlReply::Ok
+        }
+        ControlCmd::SetMute(m) => {
+            let args = (m.card, m.channel.0, m.channel.1.as_str(), m.mute);
+            let () = proxy.method_call(INTERFACE, "SetMute", args).map_err(err)?;
+            ControlReply::Ok
+        }
+        ControlCmd::Shutdown => {
+            let () = proxy.method_call(INTERFACE, "Shutdown", ()).map_err(err)?;
+            ControlReply::Ok
+        }
+        ControlCmd::ExportSettings => {
+            let (doc,): (String,) = proxy
+                .method_call(INTERFACE, "ExportSettings", ())
+                .map_err(err)?;
+            ControlReply::Exported(doc)
+        }
+        ControlCmd::ImportSettings(doc) => {
+            let () = proxy
+                .method_call(INTERFACE, "ImportSettings", (doc.as_str(),))
+                .map_err(err)?;
+            ControlReply::Ok
+        }
+        cmd => return Err(format!("{:?} is not available over D-Bus", cmd)),
+    };
+
+    Ok(reply)
+}
+
+/// Turn a port from the bus back into a `Port`
+fn port((id, name, tt, dir): (u32, String, String, String)) -> Option<Port> {
+    let (client, port_name) = graph::split_name(&name)?;
+    let tt = match tt.as_str() {
+        "audio" => PortType::Audio,
+        "midi" => PortType::Midi,
+        _ => PortType::Unknown,
+    };
+    let dir = match dir.as_str() {
+        "input" => PortDirection::Input,
+        _ => PortDirection::Output,
+    };
+    Some(Port::new(
+        client.into(),
+        port_name.into(),
+        id,
+        tt,
+        dir,
+        false,
+    ))
+}
+
+fn type_name(tt: &PortType) -> &'static str {
+    match tt {
+        PortType::Audio => "audio",
+        PortType::Midi => "midi",
+        PortType::Unknown => "unknown",
+    }
+}
+
+fn direction_name(dir: &PortDirection) -> &'static str {
+    match dir {
+        PortDirection::Input => "input",
+        PortDirection::Output => "output",
+    }
+}
+
+fn print_reply(reply: &ControlReply, json: bool) {
+    match reply {
+        ControlReply::Ok | ControlReply::Error(_) => {}
+        ControlReply::Exported(doc) => print!("{}", doc),
+        ControlReply::Ports(ports) if json => {
+            let ports: Vec<_> = ports
+                .iter()
+                .map(|p| {
+                    json!({
+                        "id": p.id,
+                        "name": p.full_name(),
+                        "type": type_name(&p.tt),
+                        "direction": direction_name(&p.dir),
+                    })
+                })
+                .collect();
+            println!("{}", json!(ports));
+        }
+        ControlReply::Ports(ports) => {
+            for p in ports {
+                println!(
+                    "{:>5}  {:<5}  {:<6}  {}",
+                    p.id,
+                    type_name(&p.tt),
+                    direction_name(&p.dir),
+                    p.full_name()
+                );
+            }
+        }
+        ControlReply::Connections(connections) if json => {
+            let connections: Vec<_> = connections
+                .iter()
+                .map(|(output, input)| json!({ "output": output, "input": input }))
+                .collect();
+            println!("{}", json!(connections));
+        }
+        ControlReply::Connections(connections) => {
+            for (output, input) in connections {
+                println!("{} -> {}", output, input);
+            }
+        }
+        ControlReply::Cards(cards) if json => {
+            let cards: Vec<_> = cards
+                .iter()
+                .map(|c| json!({ "id": c.id, "name": c.name, "active": c.active }))
+                .collect();
+            println!("{}", json!(cards));
+        }
+        ControlReply::Cards(cards) => {
+            for c in cards {
+                let state = if c.active { "active" } else { "inactive" };
+                println!("{:>3}  {:<8}  {}", c.id, state, c.name);
+            }
+        }
+        ControlReply::Stats { settings, xruns } => match settings {
+            Some(s) if json => println!(
+                "{}",
+                json!({
+                    "cpu_percentage": s.cpu_percentage,
+                    "sample_rate": s.sample_rate,
+                    "buffer_size": s.buffer_size,
+                    "latency": s.latency,
+                    "xruns": xruns,
+                })
+            ),
+            None if json => println!("{}", json!({ "xruns": xruns })),
+            Some(s) => println!(
+                "{} Hz, {} frames, {:.1} ms latency, {:.1}% DSP, {} xruns",
+                s.sample_rate, s.buffer_size, s.latency, s.cpu_percentage, xruns
+            ),
+            None => println!("{} xruns", xruns),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(args: &str) -> Result<Invocation, String> {
+        let args: Vec<String> = args.split_whitespace().map(Into::into).collect();
+        parse(&args)
+    }
+
+    #[test]
+    fn no_subcommand_runs_panel() {
+        assert!(matches!(
+            parse_str(""),
+            Ok(Invocation::Run { headless: false })
+        ));
+        assert!(matches!(
+            parse_str("--headless"),
+            Ok(Invocation::Run { headless: true })
+        ));
+    }
+
+    #[test]
+    fn subcommands() {
+        assert!(matches!(
+            parse_str("--json ports"),
+            Ok(Invocation::Remote {
+                cmd: ControlCmd::ListPorts,
+                json: true
+            })
+        ));
+        assert!(matches!(
+            parse_str("connect synth:out system:playback_1"),
+            Ok(Invocation::Remote {
+                cmd: ControlCmd::Connect { output, input },
+                json: false
+            }) if output == "synth:out" && input == "system:playback_1"
+        ));
+        assert!(matches!(
+            parse_str("volume 1 PCM,1 80"),
+            Ok(Invocation::Remote {
+                cmd: ControlCmd::SetVolume(VolumeCmd { card: 1, channel, volume: 80 }),
+                ..
+            }) if channel == (1, "PCM".to_owned())
+        ));
+        assert!(matches!(
+            parse_str("mute 0 Master on"),
+            Ok(Invocation::Remote {
+                cmd: ControlCmd::SetMute(MuteCmd { mute: true, .. }),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn quit_shuts_down() {
+        assert!(matches!(
+            parse_str("quit"),
+            Ok(Invocation::Remote {
+                cmd: ControlCmd::Shutdown,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn import_and_export() {
+        assert!(matches!(
+            parse_str("export"),
+            Ok(Invocation::Export { path: None })
+        ));
+        assert!(matches!(
+            parse_str("import jackctl.toml"),
+            Ok(Invocation::Import { path }) if path == PathBuf::from("jackctl.toml")
+        ));
+        assert!(parse_str("import").is_err());
+    }
+
+    #[test]
+    fn bad_arguments() {
+        assert!(parse_str("connect synth:out").is_err());
+        assert!(parse_str("volume one Master 10").is_err());
+        assert!(parse_str("mute 0 Master maybe").is_err());
+        assert!(parse_str("--frobnicate").is_err());
+        assert!(parse_str("dance").is_err());
+    }
+}
@@ -1,7 +1,25 @@
+use std::fmt;
+
 #[derive(Debug)]
 pub enum SettingsError {
     Io(std::io::Error),
     Json(serde_json::Error),
+    TomlRead(toml::de::Error),
+    TomlWrite(toml::ser::Error),
+    /// A stored layout that can't be upgraded
+    Migration(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Json(e) => write!(f, "invalid JSON: {}", e),
+            Self::TomlRead(e) => write!(f, "invalid TOML: {}", e),
+            Self::TomlWrite(e) => write!(f, "can't write TOML: {}", e),
+            Self::Migration(e) => write!(f, "{}", e),
+        }
+    }
 }
 
 impl From<std::io::Error> for SettingsError {
@@ -15,3 +33,15 @@ impl From<serde_json::Error> for SettingsError {
         Self::Json(e)
     }
 }
+
+impl From<toml::de::Error> for SettingsError {
+    fn from(e: toml::de::Error) -> Self {
+        Self::TomlRead(e)
+    }
+}
+
+impl From<toml::ser::Error> for SettingsError {
+    fn from(e: toml::ser::Error) -> Self {
+        Self::TomlWrite(e)
+    }
+}
@@ -6,33 +6,92 @@
 extern crate tracing;
 
 mod cb_channel;
+mod cli;
 mod error;
 mod log;
 mod model;
 mod rts;
+#[cfg(test)]
+mod test_dir;
 mod ui;
 
+use directories::ProjectDirs;
 use gio::prelude::*;
 use model::{
     settings::{self, Settings},
     Model,
 };
-use std::{env::args, fs::File};
+use std::{env::args, fs::File, sync::Arc};
+use ui::UiHandle;
 
 fn main() {
-    log::parse_log_level();
+    let argv: Vec<String> = args().collect();
+    let headless = match cli::parse(&argv[1..]) {
+        Ok(cli::Invocation::Run { headless }) => headless,
+        Ok(cli::Invocation::Remote { cmd, json }) => std::process::exit(cli::run_remote(cmd, json)),
+        Ok(cli::Invocation::Export { path }) => std::process::exit(cli::export(path)),
+        Ok(cli::Invocation::Import { path }) => std::process::exit(cli::import(path)),
+        Ok(cli::Invocation::Help) => {
+            println!("{}", cli::usage());
+            return;
+        }
+        Err(e) => {
+            eprintln!("jackctl: {}\n\n{}", e, cli::usage());
+            std::process::exit(2);
+        }
+    };
 
-    // Load and initialise settings first
+    log::parse_log_level();
     let dir = settings::scaffold();
-    let set = Settings::init(dir.config_dir()).unwrap();
 
-    let jack_if = rts::jack::JackRuntime::start(set.clone()).unwrap();
+    // Claim the instance before the settings are loaded, which may
+    // write them.  They belong to the running jackctl otherwise.
+    if headless {
+        info!("Running headless, no UI will be shown");
+        let app = match ui::claim_headless() {
+            Some(app) => app,
+            None => return already_running(),
+        };
+        let set = Settings::init(dir.config_dir()).unwrap();
+        let (headless, ui_if) = ui::create_headless(app, set.clone());
+        start_model(&dir, ui_if, set);
+        headless.wait();
+    } else {
+        let app = match ui::claim_ui() {
+            Some(app) => app,
+            None => return already_running(),
+        };
+        let set = Settings::init(dir.config_dir()).unwrap();
+        let (_win, ui_if, _tray) = ui::create_ui(&app, set.clone());
+        start_model(&dir, ui_if, set);
+        // Arguments were handled above, GTK only needs the program name
+        app.run(&argv[..1]);
+    }
+
+    info!("Jackctl Exiting, Goodbye");
+}
+
+/// Start all runtimes and hand them to a new model
+fn start_model(dir: &ProjectDirs, ui_if: UiHandle, set: Arc<Settings>) {
+    let jack_if = rts::jack::JackRuntime::start(set.clone(), dir.runtime_dir());
     let card_if = rts::hardware::HardwareHandle::new();
-    let (_win, app, ui_if, _tray) = ui::create_ui(set.clone());
+    let launch_if = rts::launcher::LauncherHandle::new();
+    let (control_if, control_client) = rts::control::channel();
+    match dir.runtime_dir() {
+        Some(run_dir) => rts::control::socket::start(control_client.clone(), run_dir),
+        None => warn!("No XDG_RUNTIME_DIR, not opening the JSON-RPC control socket"),
+    }
+    rts::control::bus::start(control_client);
+    let reserve_if =
+        rts::reserve::ReserveHandle::start(dbus::blocking::LocalConnection::new_session);
 
-    Model::start(jack_if, ui_if, card_if, set);
+    let watch_if = rts::watcher::SettingsWatcher::start(dir.config_dir());
 
-    app.run(&args().collect::<Vec<_>>());
+    Model::start(
+        jack_if, ui_if, card_if, launch_if, control_if, reserve_if, watch_if, set,
+    );
+}
 
-    info!("Jackctl Exiting, Goodbye");
+fn already_running() {
+    info!("jackctl is already running, handing over to it");
 }
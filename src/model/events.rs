@@ -1,17 +1,22 @@
 use crate::{
     model::card::{Card, CardConfig, CardId, ChannelId, MixerChannel, Volume},
+    model::history::HistoryEntry,
     model::port::{JackPortType, Port},
+    model::settings::{Client, Rule, SoundCard},
 };
 use jack::InternalClientID;
+use std::{fmt, time::Duration};
 
 /// A general jack action
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum JackCmd {
     ConnectPorts {
         input: JackPortType,
         output: JackPortType,
         connect: bool,
     },
+    /// Restart the server with the current settings
+    Restart,
     Shutdown,
 }
 
@@ -20,17 +25,20 @@ pub enum JackCmd {
 pub enum JackCardAction {
     StartCard {
         id: String,
+        /// Name of the card's client in jack
         name: String,
         rate: u32,
         in_ports: u32,
         out_ports: u32,
+        n_periods: u32,
+        resample_q: u32,
     },
     StopCard {
         id: InternalClientID,
     },
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct JackSettings {
     pub cpu_percentage: f32,
     pub sample_rate: u64,
@@ -53,6 +61,87 @@ pub enum JackEvent {
     AddConnection(JackPortType, JackPortType),
     /// Delete a connection between ports
     DelConnection(JackPortType, JackPortType),
+    /// The server is going down for a restart, all ports are gone
+    ServerStopped,
+    /// A server is up, its ports follow
+    ServerStarted,
+    /// What the runtime is doing to get a server
+    ServerStatus(ServerStatus),
+}
+
+/// Progress of finding or starting a jack server
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerStatus {
+    /// Waiting for another program to start a server
+    Waiting,
+    /// Stopping other servers to start our own
+    Replacing,
+    /// Starting jackd
+    Starting,
+    /// Connected to the server jackctl started
+    Running,
+    /// Connected to a server another program started
+    External {
+        sample_rate: u32,
+        period_size: u32,
+        /// Rate and period size from the settings, if they differ
+        wanted: Option<(u32, u32)>,
+    },
+    /// The settings can't be applied to a server another program started
+    NotRestarted,
+    /// The server went away, the next attempt to get it back is due
+    Recovering {
+        attempt: u32,
+        max: u32,
+        delay: Duration,
+    },
+    /// No server could be reached
+    Failed(String),
+}
+
+impl fmt::Display for ServerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Waiting => write!(f, "Waiting for a JACK server to start"),
+            Self::Replacing => write!(f, "Stopping other JACK servers"),
+            Self::Starting => write!(f, "Starting JACK"),
+            Self::Running => write!(f, "JACK is running"),
+            Self::External {
+                sample_rate,
+                period_size,
+                wanted: None,
+            } => write!(
+                f,
+                "Using a running JACK server ({}Hz, {}w)",
+                sample_rate, period_size
+            ),
+            Self::External {
+                sample_rate,
+                period_size,
+                wanted: Some((rate, frames)),
+            } => write!(
+                f,
+                "Using a running JACK server at {}Hz, {}w instead of {}Hz, {}w",
+                sample_rate, period_size, rate, frames
+            ),
+            Self::NotRestarted => write!(
+                f,
+                "JACK was started by another program, restart it to apply the settings"
+            ),
+            Self::Recovering {
+                attempt,
+                max,
+                delay,
+            } => write!(
+                f,
+                "JACK stopped, retrying in {}s (attempt {} of {})",
+                delay.as_secs(),
+                attempt,
+                max
+            ),
+            Self::Failed(e) => write!(f, "No JACK server: {}", e),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -100,6 +189,26 @@ pub enum UiEvent {
     SetConnection(JackPortType, JackPortType, bool),
     /// The user has updated the app settings
     UpdateSettings(UiSettingsUpdate),
+    /// Restart jack with the stored settings
+    RestartJack,
+    /// Take back the last connection change
+    Undo,
+    /// Apply the last undone connection change again
+    Redo,
+    /// Save the current connections as a named scene
+    SaveScene(String),
+    /// Restore the connections of a named scene
+    RecallScene(String),
+    /// Forget a named scene
+    DeleteScene(String),
+    /// The user changed how a client is launched
+    UpdateClient(Client),
+    /// The user edited the auto-connection rules
+    SetRules(Vec<Rule>),
+    /// The user changed the settings of a sound card
+    UpdateCard(SoundCard),
+    /// The user chose the card jackd runs on, `None` for no card
+    SetMasterCard(Option<String>),
     /// The user has requested the program to end
     Shutdown,
 }
@@ -119,6 +228,8 @@ pub enum UiCmd {
     IncrementXRun,
     /// Update jack settings
     JackSettings(JackSettings),
+    /// Show what is happening to the jack server
+    ServerStatus(ServerStatus),
     /// Add a connection between ports
     AddConnection(JackPortType, JackPortType),
     /// Delete a connection between ports
@@ -129,6 +240,33 @@ pub enum UiCmd {
     DelCard(CardId),
     /// Ask the user about their sound card
     AskCard(Card),
+    /// The list of saved scenes has changed
+    ScenesChanged,
+    /// A scene was recalled, but some of its ports were missing
+    SceneRecalled {
+        name: String,
+        missing: Vec<(String, String)>,
+    },
+    /// The list of known clients has changed
+    ClientsChanged,
+    /// The connection history has changed
+    History {
+        entries: Vec<HistoryEntry>,
+        can_undo: bool,
+        can_redo: bool,
+    },
+    /// App settings were changed on disk and reloaded
+    SettingsChanged,
+    /// A settings file was edited on disk, but can't be loaded
+    SettingsError { file: String, error: String },
+    /// A settings file couldn't be loaded at startup and was replaced
+    SettingsRecovered {
+        file: String,
+        error: String,
+        restored: Option<String>,
+    },
+    /// Changed settings couldn't be written to disk
+    SettingsUnsaved { error: String },
     /// The Model Has finished a shutdown request the main loop must be terminated immediately
     YouDontHaveToGoHomeButYouCantStayHere,
 }
@@ -161,3 +299,89 @@ pub enum HardwareEvent {
 
 #[derive(Clone, Debug)]
 pub enum HardwareCardAction {}
+
+/// Requests from remote control front-ends
+#[derive(Clone, Debug)]
+pub enum ControlCmd {
+    ListCards,
+    ListPorts,
+    ListConnections,
+    /// Connect two ports, by their `client:port` names
+    Connect {
+        output: String,
+        input: String,
+    },
+    /// Disconnect two ports, by their `client:port` names
+    Disconnect {
+        output: String,
+        input: String,
+    },
+    SetVolume(VolumeCmd),
+    SetMute(MuteCmd),
+    /// Start or stop a card, optionally remembering the choice
+    CardUsage {
+        card: CardId,
+        usage: bool,
+        store: bool,
+    },
+    /// Latest jack server statistics
+    Stats,
+    /// The whole settings tree as a TOML document
+    ExportSettings,
+    /// Replace settings with an exported TOML document
+    ImportSettings(String),
+    Shutdown,
+}
+
+/// Answers to a `ControlCmd`
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlReply {
+    Ok,
+    Error(String),
+    Cards(Vec<CardInfo>),
+    Ports(Vec<Port>),
+    /// Connections as `(output, input)` port names
+    Connections(Vec<(String, String)>),
+    /// Settings are `None` until jack first reports them
+    Stats {
+        settings: Option<JackSettings>,
+        xruns: u64,
+    },
+    /// Settings as an exported TOML document
+    Exported(String),
+}
+
+/// A short description of a sound card
+#[derive(Clone, Debug, PartialEq)]
+pub struct CardInfo {
+    pub id: CardId,
+    pub name: String,
+    /// Whether the card was started in jack
+    pub active: bool,
+}
+
+/// Events broadcast to remote control front-ends
+#[derive(Clone, Debug)]
+pub enum ControlEvent {
+    XRun,
+    JackSettings(JackSettings),
+    AddPort(Port),
+    DelPort(JackPortType),
+    /// A connection between two ports, by their names
+    AddConnection {
+        output: String,
+        input: String,
+    },
+    /// A connection was removed, by port names
+    DelConnection {
+        output: String,
+        input: String,
+    },
+    AddCard {
+        id: CardId,
+        name: String,
+    },
+    DelCard(CardId),
+    VolumeChange(VolumeCmd),
+    MuteChange(MuteCmd),
+}
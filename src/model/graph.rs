@@ -0,0 +1,202 @@
+//! The model's copy of the jack port graph
+//!
+//! Every port and connection reported by the jack runtime is tracked
+//! here, so other parts of the model can query the graph without
+//! asking the UI.
+
+use crate::model::port::{JackPortType, Port, PortDirection};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A connection from an output (source) port to an input
+/// (destination) port
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Connection {
+    pub output: JackPortType,
+    pub input: JackPortType,
+}
+
+#[derive(Debug, Default)]
+pub struct Graph {
+    /// All known ports
+    ports: BTreeMap<JackPortType, Port>,
+    /// Port IDs grouped by their client name
+    clients: BTreeMap<String, BTreeSet<JackPortType>>,
+    /// All connections between known ports
+    connections: BTreeSet<Connection>,
+}
+
+impl Graph {
+    /// Add a new port to the graph
+    pub fn add_port(&mut self, port: Port) {
+        self.clients
+            .entry(port.client_name.clone())
+            .or_default()
+            .insert(port.id);
+        self.ports.insert(port.id, port);
+    }
+
+    /// Remove a port and all of its connections from the graph
+    pub fn del_port(&mut self, id: JackPortType) -> Option<Port> {
+        let port = self.ports.remove(&id)?;
+
+        if let Some(set) = self.clients.get_mut(&port.client_name) {
+            set.remove(&id);
+            if set.is_empty() {
+                self.clients.remove(&port.client_name);
+            }
+        }
+
+        self.connections.retain(|c| c.output != id && c.input != id);
+        Some(port)
+    }
+
+    /// Build a connection from the port pair of a jack event
+    ///
+    /// Jack reports connections as `(destination, source)`.  Where the
+    /// directions of both ports are known they are used instead.
+    pub fn connection(&self, a: JackPortType, b: JackPortType) -> Connection {
+        match (self.ports.get(&a), self.ports.get(&b)) {
+            (Some(pa), Some(pb))
+                if pa.dir == PortDirection::Output && pb.dir == PortDirection::Input =>
+            {
+                Connection {
+                    output: a,
+                    input: b,
+                }
+            }
+            _ => Connection {
+                output: b,
+                input: a,
+            },
+        }
+    }
+
+    /// Record a connection from a jack event
+    pub fn add_connection(&mut self, a: JackPortType, b: JackPortType) -> Connection {
+        let c = self.connection(a, b);
+        self.connections.insert(c);
+        c
+    }
+
+    /// Forget a connection from a jack event
+    pub fn del_connection(&mut self, a: JackPortType, b: JackPortType) -> Connection {
+        let c = self.connection(a, b);
+        self.connections.remove(&c);
+        c
+    }
+
+    /// Remove all ports and connections
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Get a port by its ID
+    pub fn port(&self, id: JackPortType) -> Option<&Port> {
+        self.ports.get(&id)
+    }
+
+    /// Get a port by its full `client:port` name
+    pub fn port_by_name(&self, name: &str) -> Option<&Port> {
+        let (client, port) = split_name(name)?;
+        self.client_ports(client).find(|p| p.port_name == port)
+    }
+
+    /// Iterate over all ports
+    pub fn ports(&self) -> impl Iterator<Item = &Port> {
+        self.ports.values()
+    }
+
+    /// Iterate over all client names
+    pub fn clients(&self) -> impl Iterator<Item = &String> {
+        self.clients.keys()
+    }
+
+    /// Iterate over all ports of a single client
+    pub fn client_ports<'g>(&'g self, client: &str) -> impl Iterator<Item = &'g Port> {
+        self.clients
+            .get(client)
+            .into_iter()
+            .flat_map(move |set| set.iter().filter_map(move |id| self.ports.get(id)))
+    }
+
+    /// Iterate over all connections
+    pub fn connections(&self) -> impl Iterator<Item = &Connection> {
+        self.connections.iter()
+    }
+
+    /// Iterate over all connections a single port is part of
+    pub fn connections_of(&self, id: JackPortType) -> impl Iterator<Item = &Connection> {
+        self.connections
+            .iter()
+            .filter(move |c| c.output == id || c.input == id)
+    }
+
+    /// Check whether an output port is connected to an input port
+    pub fn is_connected(&self, output: JackPortType, input: JackPortType) -> bool {
+        self.connections.contains(&Connection { output, input })
+    }
+}
+
+/// Split a full port name into its client and port names
+pub fn split_name(name: &str) -> Option<(&str, &str)> {
+    let mut split = name.splitn(2, ':');
+    Some((split.next()?, split.next()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::port::PortType;
+
+    fn port(id: JackPortType, client: &str, name: &str, dir: PortDirection) -> Port {
+        Port::new(client.into(), name.into(), id, PortType::Audio, dir, false)
+    }
+
+    fn graph() -> Graph {
+        let mut g = Graph::default();
+        g.add_port(port(1, "synth", "out_l", PortDirection::Output));
+        g.add_port(port(2, "synth", "out_r", PortDirection::Output));
+        g.add_port(port(3, "system", "playback_1", PortDirection::Input));
+        g.add_port(port(4, "system", "playback_2", PortDirection::Input));
+        g
+    }
+
+    #[test]
+    fn ports_are_grouped_by_client() {
+        let g = graph();
+        assert_eq!(g.clients().collect::<Vec<_>>(), vec!["synth", "system"]);
+        assert_eq!(g.client_ports("synth").count(), 2);
+        assert_eq!(g.port_by_name("system:playback_2").unwrap().id, 4);
+        assert!(g.port_by_name("system:capture_1").is_none());
+    }
+
+    #[test]
+    fn connections_are_normalised() {
+        let mut g = graph();
+        // (destination, source) like jack reports it
+        g.add_connection(3, 1);
+        // (source, destination) works too since the directions are known
+        g.add_connection(2, 4);
+
+        assert!(g.is_connected(1, 3));
+        assert!(g.is_connected(2, 4));
+        assert!(!g.is_connected(3, 1));
+        assert_eq!(g.connections().count(), 2);
+    }
+
+    #[test]
+    fn deleting_ports_drops_connections() {
+        let mut g = graph();
+        g.add_connection(3, 1);
+        g.add_connection(4, 1);
+        g.add_connection(4, 2);
+
+        assert_eq!(g.connections_of(1).count(), 2);
+        g.del_port(1);
+        assert_eq!(g.connections().count(), 1);
+        assert!(g.is_connected(2, 4));
+
+        g.del_port(2);
+        assert_eq!(g.clients().collect::<Vec<_>>(), vec!["system"]);
+    }
+}
@@ -0,0 +1,209 @@
+//! Undo and redo history for connection changes
+//!
+//! Changes the user makes through jackctl can be undone and redone.
+//! Changes that other JACK clients make are recorded too, so the
+//! history is complete, but they can't be undone.
+
+use crate::model::{graph::Connection, port::JackPortType};
+use std::time::{Duration, Instant};
+
+/// Maximum number of entries kept in the history
+const MAX_ENTRIES: usize = 256;
+
+/// Changes jack hasn't reported by now were rejected
+const PENDING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A single change to the connection graph
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    /// The connection that was changed
+    pub connection: Connection,
+    /// Whether the ports were connected or disconnected
+    pub connect: bool,
+    /// Only changes made through jackctl can be undone
+    pub undoable: bool,
+    /// Human readable description of the change
+    pub label: String,
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    /// Changes in the order they happened
+    done: Vec<HistoryEntry>,
+    /// Changes that were undone and can be redone
+    undone: Vec<HistoryEntry>,
+    /// Changes we asked jack for, but which it hasn't reported yet
+    pending: Vec<(Connection, bool, Instant)>,
+}
+
+impl History {
+    /// Record a change the user just asked for
+    pub fn record(&mut self, connection: Connection, connect: bool, label: String) {
+        self.undone.clear();
+        self.push(HistoryEntry {
+            connection,
+            connect,
+            undoable: true,
+            label,
+        });
+        self.expect(connection, connect);
+    }
+
+    /// Record a change reported by jack
+    ///
+    /// Changes we caused ourselves are already in the history, so
+    /// only external changes are added.  Returns whether the history
+    /// changed.
+    pub fn observe(&mut self, connection: Connection, connect: bool, label: String) -> bool {
+        self.pending
+            .retain(|(_, _, asked)| asked.elapsed() < PENDING_TIMEOUT);
+        match self.position(connection, connect) {
+            Some(idx) => {
+                self.pending.remove(idx);
+                false
+            }
+            None => {
+                self.push(HistoryEntry {
+                    connection,
+                    connect,
+                    undoable: false,
+                    label,
+                });
+                true
+            }
+        }
+    }
+
+    /// Take back the latest undoable change
+    ///
+    /// Returns the connection change needed to undo it.
+    pub fn undo(&mut self) -> Option<(Connection, bool)> {
+        let idx = self.done.iter().rposition(|e| e.undoable)?;
+        let entry = self.done.remove(idx);
+        let change = (entry.connection, !entry.connect);
+        self.undone.push(entry);
+        self.expect(change.0, change.1);
+        Some(change)
+    }
+
+    /// Apply the latest undone change again
+    ///
+    /// Returns the connection change needed to redo it.
+    pub fn redo(&mut self) -> Option<(Connection, bool)> {
+        let entry = self.undone.pop()?;
+        let change = (entry.connection, entry.connect);
+        self.push(entry);
+        self.expect(change.0, change.1);
+        Some(change)
+    }
+
+    /// Forget a change we asked for that jack will never report
+    pub fn cancel(&mut self, connection: Connection, connect: bool) {
+        if let Some(idx) = self.position(connection, connect) {
+            self.pending.remove(idx);
+        }
+    }
+
+    /// Forget the changes we asked for on a port that went away
+    ///
+    /// Its ID may be reused by a new port.
+    pub fn port_gone(&mut self, id: JackPortType) {
+        self.pending
+            .retain(|(c, _, _)| c.output != id && c.input != id);
+    }
+
+    /// Get all recorded changes, oldest first
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.done
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.done.iter().any(|e| e.undoable)
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    fn expect(&mut self, connection: Connection, connect: bool) {
+        self.pending.push((connection, connect, Instant::now()));
+    }
+
+    fn position(&self, connection: Connection, connect: bool) -> Option<usize> {
+        self.pending
+            .iter()
+            .position(|(c, cn, _)| (*c, *cn) == (connection, connect))
+    }
+
+    fn push(&mut self, entry: HistoryEntry) {
+        self.done.push(entry);
+        if self.done.len() > MAX_ENTRIES {
+            self.done.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conn(output: u32, input: u32) -> Connection {
+        Connection { output, input }
+    }
+
+    #[test]
+    fn own_changes_are_not_recorded_twice() {
+        let mut h = History::default();
+        h.record(conn(1, 2), true, "a".into());
+        assert!(!h.observe(conn(1, 2), true, "a".into()));
+        assert!(h.observe(conn(3, 4), true, "b".into()));
+
+        assert_eq!(h.entries().len(), 2);
+        assert!(h.entries()[0].undoable);
+        assert!(!h.entries()[1].undoable);
+    }
+
+    #[test]
+    fn undo_skips_external_changes() {
+        let mut h = History::default();
+        h.record(conn(1, 2), true, "a".into());
+        h.observe(conn(1, 2), true, "a".into());
+        h.observe(conn(3, 4), true, "b".into());
+
+        assert_eq!(h.undo(), Some((conn(1, 2), false)));
+        assert_eq!(h.undo(), None);
+        assert!(!h.can_undo());
+
+        // The undo itself is reported back by jack, but not recorded
+        assert!(!h.observe(conn(1, 2), false, "a".into()));
+        assert_eq!(h.entries().len(), 1);
+    }
+
+    #[test]
+    fn redo_is_cleared_by_new_changes() {
+        let mut h = History::default();
+        h.record(conn(1, 2), true, "a".into());
+        h.undo();
+        assert!(h.can_redo());
+        assert_eq!(h.redo(), Some((conn(1, 2), true)));
+
+        h.undo();
+        h.record(conn(5, 6), true, "c".into());
+        assert!(!h.can_redo());
+        assert_eq!(h.redo(), None);
+    }
+
+    #[test]
+    fn unanswered_changes_are_dropped() {
+        let mut h = History::default();
+        h.record(conn(1, 2), true, "a".into());
+        h.record(conn(3, 4), true, "b".into());
+
+        // The port went away, and jack rejected the other change
+        h.port_gone(2);
+        h.pending[0].2 = Instant::now() - PENDING_TIMEOUT;
+        assert!(h.observe(conn(1, 2), true, "a".into()));
+        assert!(h.observe(conn(3, 4), true, "b".into()));
+        assert_eq!(h.entries().len(), 4);
+    }
+}
@@ -2,52 +2,222 @@
 
 pub mod card;
 pub mod events;
+pub mod graph;
+pub mod history;
 pub mod port;
+pub mod rules;
+pub mod scene;
 pub mod settings;
 
 use self::card::{Card, CardId, CardStatus, CardUsage};
 use self::events::{
-    HardwareCmd, HardwareEvent, JackCardAction, JackCmd, JackEvent, UiCmd, UiEvent,
+    CardInfo, ControlCmd, ControlEvent, ControlReply, HardwareCmd, HardwareEvent, JackCardAction,
+    JackCmd, JackEvent, MuteCmd, UiCmd, UiEvent, VolumeCmd,
+};
+use self::graph::{Connection, Graph};
+use self::history::History;
+use self::port::{JackPortType, Port, PortDirection};
+use self::rules::Rules;
+use crate::cb_channel::Replier;
+use crate::rts::{
+    control::ControlHandle,
+    hardware::HardwareBackend,
+    jack::JackBackend,
+    launcher::{LauncherCmd, LauncherEvent, LauncherHandle},
+    reserve::{ReserveEvent, ReserveHandle},
+    watcher::SettingsWatcher,
 };
-use crate::rts::{hardware::HardwareHandle, jack::JackHandle};
 use crate::ui::UiHandle;
 use async_std::{channel, task};
 use futures::FutureExt;
-use settings::Settings;
-use std::collections::HashMap;
+use settings::{Client, Section, Settings, SoundCard};
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
 use std::{collections::BTreeMap, sync::Arc};
 
+/// How long a scene waits for the clients it launched
+const SCENE_LAUNCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long to wait for ports to come back after jack restarted
+const RESTORE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Give up re-spawning a client after this many crashes in a row
+const MAX_RESPAWNS: u32 = 5;
+
+/// A client that ran this long is no longer crashing in a loop
+const RESPAWN_RESET: Duration = Duration::from_secs(30);
+
+/// Collect remembered clients and connections this long before writing them
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+
+/// Timers the model sets for itself
+#[derive(Debug)]
+enum Timer {
+    /// Stop waiting for the clients of a scene
+    SceneLaunch(String),
+    /// Stop restoring the connections from before a jack restart
+    Restore(u32),
+    /// Write the remembered clients and connections
+    Save,
+}
+
+/// Remembered clients and connections that weren't written yet
+///
+/// They're applied again when the clients are edited on disk meanwhile.
+#[derive(Debug, Default)]
+struct Unsaved {
+    clients: BTreeSet<String>,
+    remembered: BTreeSet<(String, String)>,
+    forgotten: BTreeSet<(String, String)>,
+}
+
+impl Unsaved {
+    fn apply(&self, clients: &mut settings::ClientSettings) {
+        for name in &self.clients {
+            if clients.find(name).is_none() {
+                clients.update(Client::new(name.clone()));
+            }
+        }
+        for (output, input) in &self.remembered {
+            clients.remember(output.clone(), input.clone());
+        }
+        for (output, input) in &self.forgotten {
+            clients.forget(output.clone(), input.clone());
+        }
+    }
+}
+
+/// A scene that is waiting for its clients to start
+#[derive(Debug)]
+struct PendingScene {
+    name: String,
+    /// Clients that were launched but don't have all their ports yet
+    waiting: BTreeSet<String>,
+}
+
 #[derive(Debug)]
-pub struct Model {
-    jack_handle: JackHandle,
+pub struct Model<J: JackBackend, H: HardwareBackend> {
+    jack_handle: J,
     ui_handle: UiHandle,
-    hw_handle: HardwareHandle,
+    hw_handle: H,
+    launcher: LauncherHandle,
+    control: ControlHandle,
+    reserve: ReserveHandle,
+    watcher: SettingsWatcher,
     settings: Arc<Settings>,
 
     /// Card data and state map
     cards: BTreeMap<CardId, Card>,
 
+    /// Ports and connections currently in the jack graph
+    graph: Graph,
+
+    /// Undo and redo history of connection changes
+    history: History,
+    /// Auto-connection rules from the settings, compiled
+    rules: Rules,
+
+    /// Scene recall waiting for launched clients
+    pending_scene: Option<PendingScene>,
+
+    /// Crashes in a row for every re-spawned client
+    respawns: BTreeMap<String, u32>,
+
+    /// Times jack was restarted
+    restarts: u32,
+    /// Cards that ran before jack restarted
+    restart_cards: BTreeSet<CardId>,
+    /// Connections to bring back once jack restarted, by port name
+    restore: BTreeSet<(String, String)>,
+
+    /// Remembered clients or connections changed since the last write
+    unsaved: Option<Unsaved>,
+    /// Connections taken away by other programs, by port name
+    ///
+    /// They are forgotten on the next write, unless one of the ports
+    /// went away in the meantime.
+    disconnected: BTreeSet<(String, String)>,
+
+    /// Last statistics reported by jack
+    jack_stats: Option<events::JackSettings>,
+    /// Overruns since startup
+    xruns: u64,
+
+    timer_tx: channel::Sender<Timer>,
+    timer_rx: channel::Receiver<Timer>,
+
     /// exit condtion bit
     done: bool,
 }
 
-impl Model {
+impl<J: JackBackend, H: HardwareBackend> Model<J, H> {
     /// Initialise a new model tree
     pub fn start(
-        jack_handle: JackHandle,
+        jack_handle: J,
         ui_handle: UiHandle,
-        hw_handle: HardwareHandle,
+        hw_handle: H,
+        launcher: LauncherHandle,
+        control: ControlHandle,
+        reserve: ReserveHandle,
+        watcher: SettingsWatcher,
         settings: Arc<Settings>,
     ) {
+        Self::new(
+            jack_handle,
+            ui_handle,
+            hw_handle,
+            launcher,
+            control,
+            reserve,
+            watcher,
+            settings,
+        )
+        .dispatch()
+    }
+
+    fn new(
+        jack_handle: J,
+        ui_handle: UiHandle,
+        hw_handle: H,
+        launcher: LauncherHandle,
+        control: ControlHandle,
+        reserve: ReserveHandle,
+        watcher: SettingsWatcher,
+        settings: Arc<Settings>,
+    ) -> Self {
+        let (timer_tx, timer_rx) = channel::unbounded();
+        let rules = Rules::compile(&settings.r().app().rules);
         Self {
             jack_handle,
             ui_handle,
             hw_handle,
+            launcher,
+            control,
+            reserve,
+            watcher,
             settings,
             cards: Default::default(),
+            graph: Default::default(),
+            history: Default::default(),
+            rules,
+            pending_scene: None,
+            respawns: Default::default(),
+            restarts: 0,
+            restart_cards: Default::default(),
+            restore: Default::default(),
+            unsaved: None,
+            disconnected: Default::default(),
+            jack_stats: None,
+            xruns: 0,
+            timer_tx,
+            timer_rx,
             done: false,
         }
-        .dispatch()
+    }
+
+    /// Get the current jack port graph
+    pub fn graph(&self) -> &Graph {
+        &self.graph
     }
 
     fn dispatch(self) {
@@ -63,10 +233,15 @@ async fn next_ctrlc(h: &channel::Receiver<()>) -> Option<()> {
 }
 
 #[instrument(skip(m), level = "debug")]
-async fn run(mut m: Model) {
+async fn run<J: JackBackend, H: HardwareBackend>(mut m: Model<J, H>) {
     let jack_handle = m.jack_handle.clone();
     let ui_handle = m.ui_handle.clone();
     let hw_handle = m.hw_handle.clone();
+    let launcher = m.launcher.clone();
+    let control = m.control.clone();
+    let reserve = m.reserve.clone();
+    let watcher = m.watcher.clone();
+    let timers = m.timer_rx.clone();
     let (tx, ctrlc_handle_rx) = channel::bounded::<()>(1);
 
     let _ = ctrlc::set_handler(move || {
@@ -75,10 +250,17 @@ async fn run(mut m: Model) {
         });
     });
 
+    report_recovered_settings(&mut m).await;
+
     while !m.done {
         let mut jack_event_poll = Box::pin(jack_handle.next_event().fuse());
         let mut ui_event_poll = Box::pin(ui_handle.next_event().fuse());
         let mut hw_event_poll = Box::pin(hw_handle.next_event().fuse());
+        let mut launch_event_poll = Box::pin(launcher.next_event().fuse());
+        let mut control_poll = Box::pin(control.next_request().fuse());
+        let mut reserve_event_poll = Box::pin(reserve.next_event().fuse());
+        let mut settings_poll = Box::pin(watcher.next_event().fuse());
+        let mut timer_poll = Box::pin(timers.recv().fuse());
         let mut ctlc_event_poll = Box::pin(next_ctrlc(&ctrlc_handle_rx).fuse());
 
         futures::select! {
@@ -94,6 +276,21 @@ async fn run(mut m: Model) {
                 Some(ev) => handle_hw_ev(&mut m, ev).await,
                 None => return,
             },
+            ev = launch_event_poll  => match ev {
+                Some(ev) => handle_launcher_ev(&mut m, ev).await,
+                None => return,
+            },
+            ev = reserve_event_poll => match ev {
+                Some(ev) => handle_reserve_ev(&mut m, ev).await,
+                None => return,
+            },
+            file = settings_poll => handle_settings_ev(&mut m, file).await,
+            req = control_poll => if let Some((cmd, reply)) = req {
+                handle_control(&mut m, cmd, reply).await
+            },
+            t = timer_poll => if let Ok(t) = t {
+                handle_timer(&mut m, t).await
+            },
             _ = ctlc_event_poll => {
                 info!("=== Recieved Ctrl-C, Shutting Down ===");
                 end_program(&mut m).await;
@@ -103,21 +300,163 @@ async fn run(mut m: Model) {
 }
 
 /// Events from the jack runtime
-async fn handle_jack_ev(m: &mut Model, ev: JackEvent) {
+async fn handle_jack_ev<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, ev: JackEvent) {
     debug!("Handling jack event: {:?}", ev);
+    broadcast_jack_ev(m, &ev);
     use JackEvent::*;
     match ev {
-        XRun => m.ui_handle.send_cmd(UiCmd::IncrementXRun).await,
-        JackSettings(settings) => m.ui_handle.send_cmd(UiCmd::JackSettings(settings)).await,
-        AddPort(port) => m.ui_handle.send_cmd(UiCmd::AddPort(port)).await,
-        DelPort(id) => m.ui_handle.send_cmd(UiCmd::DelPort(id)).await,
-        AddConnection(a, b) => m.ui_handle.send_cmd(UiCmd::AddConnection(a, b)).await,
-        DelConnection(a, b) => m.ui_handle.send_cmd(UiCmd::DelConnection(a, b)).await,
+        XRun => {
+            m.xruns += 1;
+            m.ui_handle.send_cmd(UiCmd::IncrementXRun).await
+        }
+        JackSettings(settings) => {
+            m.jack_stats = Some(settings.clone());
+            m.ui_handle.send_cmd(UiCmd::JackSettings(settings)).await
+        }
+        AddPort(port) => {
+            m.graph.add_port(port.clone());
+            remember_client(m, &port.client_name);
+            reconnect_port(m, &port).await;
+            apply_rules(m, &port).await;
+            m.ui_handle.send_cmd(UiCmd::AddPort(port)).await;
+            check_pending_scene(m).await;
+            restore_connections(m).await;
+        }
+        DelPort(id) => {
+            // Its connections went away with it, they're not disconnected
+            if let Some(name) = m.graph.port(id).map(|p| p.full_name()) {
+                m.disconnected
+                    .retain(|(output, input)| *output != name && *input != name);
+            }
+            m.graph.del_port(id);
+            m.history.port_gone(id);
+            m.ui_handle.send_cmd(UiCmd::DelPort(id)).await
+        }
+        AddConnection(a, b) => {
+            let c = m.graph.add_connection(a, b);
+            remember_connection(m, c);
+            m.ui_handle.send_cmd(UiCmd::AddConnection(a, b)).await;
+            if m.history.observe(c, true, connection_label(m, c)) {
+                send_history(m).await;
+            }
+        }
+        DelConnection(a, b) => {
+            let c = m.graph.del_connection(a, b);
+            if let Some(names) = connection_names(m, c) {
+                m.disconnected.insert(names);
+                save_later(m);
+            }
+            m.ui_handle.send_cmd(UiCmd::DelConnection(a, b)).await;
+            if m.history.observe(c, false, connection_label(m, c)) {
+                send_history(m).await;
+            }
+        }
+        ServerStopped => server_stopped(m).await,
+        ServerStarted => server_started(m).await,
+        ServerStatus(status) => m.ui_handle.send_cmd(UiCmd::ServerStatus(status)).await,
+    }
+}
+
+/// Forget everything that belonged to the jack server that went away
+///
+/// The connections and running cards are remembered, to bring them
+/// back on the new server.
+async fn server_stopped<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) {
+    m.restore = m
+        .graph
+        .connections()
+        .filter_map(|c| connection_names(m, *c))
+        .collect();
+
+    // Port ids are meaningless on the new server
+    let connections: Vec<Connection> = m.graph.connections().cloned().collect();
+    let ports: Vec<JackPortType> = m.graph.ports().map(|p| p.id).collect();
+    m.graph.clear();
+    for c in connections {
+        m.ui_handle
+            .send_cmd(UiCmd::DelConnection(c.input, c.output))
+            .await;
+    }
+    for id in ports {
+        m.ui_handle.send_cmd(UiCmd::DelPort(id)).await;
+        m.control.broadcast(ControlEvent::DelPort(id));
+    }
+    m.history = History::default();
+    send_history(m).await;
+
+    // Adapters die with the server
+    let running: Vec<(CardId, bool)> = m
+        .cards
+        .values_mut()
+        .filter(|c| c.client_handle.is_some() || c.state == CardStatus::Active)
+        .map(|c| {
+            c.state = CardStatus::New;
+            (c.id, c.client_handle.take().is_some())
+        })
+        .collect();
+    for (id, reserved) in running {
+        m.restart_cards.insert(id);
+        if reserved {
+            m.reserve.release(id).await;
+        }
+        m.ui_handle.send_cmd(UiCmd::DelCard(id)).await;
+    }
+}
+
+/// Start the cards and restore the connections from before a restart
+async fn server_started<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) {
+    info!(
+        "jack is running, starting {} cards and restoring {} connections",
+        m.restart_cards.len(),
+        m.restore.len()
+    );
+    m.restarts += 1;
+
+    let master = m.jack_handle.master_card();
+    let cards: Vec<Card> = m
+        .cards
+        .values()
+        .filter(|c| m.restart_cards.contains(&c.id) || master.as_ref() == Some(&c.name))
+        .cloned()
+        .collect();
+    m.restart_cards.clear();
+    for card in cards {
+        signal_jack_card(card, m).await;
+    }
+
+    let timer_tx = m.timer_tx.clone();
+    let timer = Timer::Restore(m.restarts);
+    task::spawn(async move {
+        task::sleep(RESTORE_TIMEOUT).await;
+        let _ = timer_tx.send(timer).await;
+    });
+}
+
+/// Connect the remembered connections whose ports are back
+async fn restore_connections<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) {
+    let ready: Vec<_> = m
+        .restore
+        .iter()
+        .filter_map(|(output, input)| {
+            let c = Connection {
+                output: m.graph.port_by_name(output)?.id,
+                input: m.graph.port_by_name(input)?.id,
+            };
+            Some(((output.clone(), input.clone()), c))
+        })
+        .collect();
+
+    for (names, c) in ready {
+        m.restore.remove(&names);
+        if !m.graph.is_connected(c.output, c.input) {
+            debug!("Restoring connection {} → {}", names.0, names.1);
+            set_connection(m, c, true).await;
+        }
     }
 }
 
 /// Events from the UI runtime
-async fn handle_ui_ev(m: &mut Model, ev: UiEvent) {
+async fn handle_ui_ev<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, ev: UiEvent) {
     debug!("Handling UI event: {:?}", ev);
     use UiEvent::*;
     match ev {
@@ -130,7 +469,7 @@ async fn handle_ui_ev(m: &mut Model, ev: UiEvent) {
         CardUsage { card, usage, store } if usage && store => {
             debug!("Use and store card {}", card.name);
             m.settings.w().cards().set_card_usage(&card.name, true);
-            m.settings.sync();
+            save_settings(m).await;
             signal_jack_card(card, m).await;
         }
         CardUsage { card, usage, .. } if usage => {
@@ -140,19 +479,81 @@ async fn handle_ui_ev(m: &mut Model, ev: UiEvent) {
         CardUsage { card, store, .. } if store => {
             debug!("Done use and store card {}", card.name);
             m.settings.w().cards().set_card_usage(&card.name, false);
-            m.settings.sync();
+            save_settings(m).await;
         }
         CardUsage { card, .. } => {
             debug!("User doesn't want to use or store card {}", card.name);
         }
-        SetConnection(input, output, connect) => {
-            m.jack_handle
-                .send_cmd(JackCmd::ConnectPorts {
-                    input,
-                    output,
-                    connect,
-                })
-                .await
+        SetConnection(a, b, connect) => {
+            // The UI gives us (source, destination)
+            let c = m.graph.connection(b, a);
+            change_connection(m, c, connect).await;
+            send_history(m).await;
+        }
+        Undo => match m.history.undo() {
+            Some((c, connect)) => apply_history(m, c, connect).await,
+            None => debug!("Nothing to undo"),
+        },
+        Redo => match m.history.redo() {
+            Some((c, connect)) => apply_history(m, c, connect).await,
+            None => debug!("Nothing to redo"),
+        },
+        SaveScene(name) => {
+            let scene = scene::capture(&m.graph);
+            info!(
+                "Saving scene '{}' with {} connections",
+                name,
+                scene.connections.len()
+            );
+            m.settings.w().scenes().scenes.insert(name, scene);
+            save_settings(m).await;
+            m.ui_handle.send_cmd(UiCmd::ScenesChanged).await;
+        }
+        RecallScene(name) => recall_scene(m, &name).await,
+        DeleteScene(name) => {
+            info!("Deleting scene '{}'", name);
+            m.settings.w().scenes().scenes.remove(&name);
+            save_settings(m).await;
+            m.ui_handle.send_cmd(UiCmd::ScenesChanged).await;
+        }
+        UpdateClient(client) => {
+            info!("Updating launch settings for client {}", client.name);
+            m.settings.w().clients().update(client);
+            save_settings(m).await;
+            m.ui_handle.send_cmd(UiCmd::ClientsChanged).await;
+        }
+        SetRules(rules) => {
+            info!("Saving {} auto-connection rules", rules.len());
+            m.rules = Rules::compile(&rules);
+            m.settings.w().app().rules = rules;
+            save_settings(m).await;
+        }
+        SetMasterCard(name) => {
+            info!(
+                "jackd will run on {} after it's restarted",
+                name.as_deref().unwrap_or("the dummy driver")
+            );
+            m.settings.w().cards().set_master(name);
+            save_settings(m).await;
+        }
+        UpdateCard(card) => {
+            info!("Saving settings of card {}", card.name);
+            let old = m.settings.r().cards().card(&card.name).cloned();
+            m.settings.w().cards().update_card(card.clone());
+            save_settings(m).await;
+
+            // A running adapter only picks up new settings when restarted
+            let running = m
+                .cards
+                .values()
+                .find(|c| c.name == card.name)
+                .and_then(|c| Some((c.id, c.client_handle?)));
+            if let Some((id, handle)) = running {
+                if old.as_ref() != Some(&card) {
+                    stop_card(m, id, handle).await;
+                }
+            }
+            apply_card_usage(m).await;
         }
         UpdateSettings(settings) => {
             info!("Saving User settings update");
@@ -166,7 +567,11 @@ async fn handle_ui_ev(m: &mut Model, ev: UiEvent) {
                 jack_settings.resample_q = settings.resample_q;
             }
             // jack settings must be fully out of scope before calling sync()
-            m.settings.sync();
+            save_settings(m).await;
+        }
+        RestartJack => {
+            info!("Restarting jack to apply the settings");
+            m.jack_handle.send_cmd(JackCmd::Restart).await;
         }
         Shutdown => {
             info!("=== Recieved Shutdown Event ===");
@@ -175,11 +580,573 @@ async fn handle_ui_ev(m: &mut Model, ev: UiEvent) {
     }
 }
 
-async fn end_program(m: &mut Model) {
+/// Ask jack to change a single connection
+async fn set_connection<J: JackBackend, H: HardwareBackend>(
+    m: &mut Model<J, H>,
+    c: Connection,
+    connect: bool,
+) {
+    if !connect {
+        forget_connection(m, c);
+    }
+
+    // `ConnectPorts` takes the source port as its `input`
+    m.jack_handle
+        .send_cmd(JackCmd::ConnectPorts {
+            input: c.output,
+            output: c.input,
+            connect,
+        })
+        .await
+}
+
+/// Change a connection on behalf of the user and record it in the history
+async fn change_connection<J: JackBackend, H: HardwareBackend>(
+    m: &mut Model<J, H>,
+    c: Connection,
+    connect: bool,
+) {
+    if m.graph.is_connected(c.output, c.input) != connect {
+        m.history.record(c, connect, connection_label(m, c));
+        set_connection(m, c, connect).await;
+    }
+}
+
+/// Recall a saved scene, launching the clients it needs first
+async fn recall_scene<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, name: &str) {
+    let scene = match m.settings.r().scenes().scenes.get(name).cloned() {
+        Some(scene) => scene,
+        None => {
+            error!("No scene named '{}'", name);
+            return;
+        }
+    };
+
+    let launch: Vec<Client> = {
+        let clients = m.settings.r().clients();
+        scene::clients(&scene)
+            .into_iter()
+            .filter(|name| m.graph.client_ports(name).next().is_none())
+            .filter_map(|name| clients.find(name).filter(|c| c.launchable()).cloned())
+            .collect()
+    };
+
+    if launch.is_empty() {
+        return apply_scene(m, name).await;
+    }
+
+    info!(
+        "Scene '{}' launches {} clients before connecting",
+        name,
+        launch.len()
+    );
+    m.pending_scene = Some(PendingScene {
+        name: name.into(),
+        waiting: launch.iter().map(|c| c.name.clone()).collect(),
+    });
+    for client in launch {
+        m.launcher.send_cmd(LauncherCmd::Launch(client)).await;
+    }
+
+    let timer_tx = m.timer_tx.clone();
+    let timer = Timer::SceneLaunch(name.into());
+    task::spawn(async move {
+        task::sleep(SCENE_LAUNCH_TIMEOUT).await;
+        let _ = timer_tx.send(timer).await;
+    });
+}
+
+/// Apply a pending scene once all its launched clients have their ports
+async fn check_pending_scene<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) {
+    let ready = match m.pending_scene {
+        Some(ref pending) => match m.settings.r().scenes().scenes.get(&pending.name) {
+            Some(scene) => scene::clients_ready(scene, &m.graph, &pending.waiting),
+            None => true,
+        },
+        None => return,
+    };
+
+    if ready {
+        let pending = m.pending_scene.take().unwrap();
+        apply_scene(m, &pending.name).await;
+    }
+}
+
+/// Bring the connection graph into the state of a saved scene
+async fn apply_scene<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, name: &str) {
+    let scene = match m.settings.r().scenes().scenes.get(name).cloned() {
+        Some(scene) => scene,
+        None => {
+            error!("No scene named '{}'", name);
+            return;
+        }
+    };
+
+    let diff = scene::diff(&scene, &m.graph);
+    info!(
+        "Recalling scene '{}': {} new connections, {} removed",
+        name,
+        diff.connect.len(),
+        diff.disconnect.len()
+    );
+
+    for c in diff.disconnect {
+        change_connection(m, c, false).await;
+    }
+    for c in diff.connect {
+        change_connection(m, c, true).await;
+    }
+    send_history(m).await;
+
+    for (output, input) in diff.missing.iter() {
+        warn!(
+            "Scene '{}': can't connect {} → {}, a port is missing",
+            name, output, input
+        );
+    }
+    m.ui_handle
+        .send_cmd(UiCmd::SceneRecalled {
+            name: name.into(),
+            missing: diff.missing,
+        })
+        .await;
+}
+
+/// Apply a connection change from the undo history
+async fn apply_history<J: JackBackend, H: HardwareBackend>(
+    m: &mut Model<J, H>,
+    c: Connection,
+    connect: bool,
+) {
+    if m.graph.port(c.output).is_some() && m.graph.port(c.input).is_some() {
+        set_connection(m, c, connect).await;
+    } else {
+        warn!("Can't change connection {:?}, one of the ports is gone", c);
+        m.history.cancel(c, connect);
+    }
+    send_history(m).await;
+}
+
+/// Send the current undo history to the UI
+async fn send_history<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) {
+    m.ui_handle
+        .send_cmd(UiCmd::History {
+            entries: m.history.entries().to_vec(),
+            can_undo: m.history.can_undo(),
+            can_redo: m.history.can_redo(),
+        })
+        .await;
+}
+
+/// Describe a connection with the names of its ports
+fn connection_label<J: JackBackend, H: HardwareBackend>(m: &Model<J, H>, c: Connection) -> String {
+    let name = |id| match m.graph.port(id) {
+        Some(p) => p.full_name(),
+        None => format!("port {}", id),
+    };
+    format!("{} → {}", name(c.output), name(c.input))
+}
+
+/// Names of both ports of a connection
+fn connection_names<J: JackBackend, H: HardwareBackend>(
+    m: &Model<J, H>,
+    c: Connection,
+) -> Option<(String, String)> {
+    Some((
+        m.graph.port(c.output)?.full_name(),
+        m.graph.port(c.input)?.full_name(),
+    ))
+}
+
+/// Remember a connection so it can be restored when its ports come back
+fn remember_connection<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, c: Connection) {
+    if let Some((output, input)) = connection_names(m, c) {
+        let new = m
+            .settings
+            .w()
+            .clients()
+            .remember(output.clone(), input.clone());
+        if new {
+            let unsaved = save_later(m);
+            unsaved.forgotten.remove(&(output.clone(), input.clone()));
+            unsaved.remembered.insert((output, input));
+        }
+    }
+}
+
+/// Forget a connection the user took away
+fn forget_connection<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, c: Connection) {
+    if let Some((output, input)) = connection_names(m, c) {
+        let known = m
+            .settings
+            .w()
+            .clients()
+            .forget(output.clone(), input.clone());
+        if known {
+            let unsaved = save_later(m);
+            unsaved.remembered.remove(&(output.clone(), input.clone()));
+            unsaved.forgotten.insert((output, input));
+        }
+    }
+}
+
+/// Write the remembered clients and connections after a short while
+///
+/// Connecting a client makes a burst of changes, they're written together.
+/// Returns the changes waiting to be written, to record the new one.
+fn save_later<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) -> &mut Unsaved {
+    if m.unsaved.is_none() {
+        let timer_tx = m.timer_tx.clone();
+        task::spawn(async move {
+            task::sleep(SAVE_DELAY).await;
+            let _ = timer_tx.send(Timer::Save).await;
+        });
+    }
+    m.unsaved.get_or_insert_with(Default::default)
+}
+
+/// Write the remembered clients and connections now
+async fn save_remembered<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) {
+    if m.unsaved.take().is_none() {
+        return;
+    }
+
+    for (output, input) in std::mem::take(&mut m.disconnected) {
+        debug!("Forgetting connection {} → {}", output, input);
+        m.settings.w().clients().forget(output, input);
+    }
+    save_settings(m).await;
+}
+
+/// Write the settings, telling the user when that fails
+async fn save_settings<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) {
+    if let Err(e) = m.settings.sync() {
+        error!("Can't save the settings: {}", e);
+        let error = e.to_string();
+        m.ui_handle.send_cmd(UiCmd::SettingsUnsaved { error }).await;
+    }
+}
+
+/// Restore the remembered connections of a port that just appeared
+///
+/// Only connections between clients that both reconnect are restored.
+async fn reconnect_port<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, port: &Port) {
+    let remembered: Vec<_> = {
+        let clients = m.settings.r().clients();
+        let reconnecting = |name: &str| {
+            m.graph
+                .port_by_name(name)
+                .filter(|p| clients.reconnects(&p.client_name))
+                .map(|p| p.id)
+        };
+        clients
+            .remembered(&port.full_name())
+            .filter_map(|(output, input)| {
+                let c = Connection {
+                    output: reconnecting(output)?,
+                    input: reconnecting(input)?,
+                };
+                Some((output.clone(), input.clone(), c))
+            })
+            .collect()
+    };
+
+    for (output, input, c) in remembered {
+        if !m.graph.is_connected(c.output, c.input) {
+            debug!("Restoring connection {} → {}", output, input);
+            set_connection(m, c, true).await;
+        }
+    }
+}
+
+/// Connect a port that just appeared according to the user's rules
+async fn apply_rules<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, port: &Port) {
+    let connections = m.rules.evaluate(port, &m.graph);
+    for c in connections {
+        if !m.graph.is_connected(c.output, c.input) {
+            debug!("Rule connects {}", connection_label(m, c));
+            set_connection(m, c, true).await;
+        }
+    }
+}
+
+/// Store a client we haven't seen before so the user can set up its launch
+fn remember_client<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, name: &str) {
+    let is_card = m.cards.values().any(|c| c.name == name);
+    if is_card || m.settings.r().clients().find(name).is_some() {
+        return;
+    }
+
+    debug!("Remembering new client {}", name);
+    m.settings.w().clients().update(Client::new(name.into()));
+    save_later(m).clients.insert(name.into());
+}
+
+/// Events from the program launcher
+async fn handle_launcher_ev<J: JackBackend, H: HardwareBackend>(
+    m: &mut Model<J, H>,
+    ev: LauncherEvent,
+) {
+    debug!("Handling launcher event: {:?}", ev);
+    match ev {
+        LauncherEvent::Started { name, pid } => debug!("Client {} runs as PID {}", name, pid),
+        LauncherEvent::Failed { name, .. } => stop_waiting(m, &name).await,
+        LauncherEvent::Exited {
+            name,
+            success,
+            uptime,
+        } => {
+            stop_waiting(m, &name).await;
+            if !success {
+                respawn(m, name, uptime).await;
+            }
+        }
+    }
+}
+
+/// A pending scene won't get any ports from this client
+async fn stop_waiting<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, name: &str) {
+    if let Some(ref mut pending) = m.pending_scene {
+        if pending.waiting.remove(name) {
+            warn!("Scene '{}' stops waiting for client {}", pending.name, name);
+            check_pending_scene(m).await;
+        }
+    }
+}
+
+/// Launch a crashed client again, if the user asked for that
+async fn respawn<J: JackBackend, H: HardwareBackend>(
+    m: &mut Model<J, H>,
+    name: String,
+    uptime: Duration,
+) {
+    let client = match m.settings.r().clients().find(&name) {
+        Some(c) if c.respawn && c.launchable() => c.clone(),
+        _ => return,
+    };
+
+    if uptime > RESPAWN_RESET {
+        m.respawns.remove(&name);
+    }
+    let crashes = m.respawns.entry(name).or_default();
+    *crashes += 1;
+
+    if *crashes > MAX_RESPAWNS {
+        error!(
+            "Client {} crashed {} times in a row, not re-spawning it",
+            client.name, MAX_RESPAWNS
+        );
+    } else {
+        warn!("Client {} crashed, re-spawning it", client.name);
+        m.launcher.send_cmd(LauncherCmd::Launch(client)).await;
+    }
+}
+
+/// Timers the model set for itself
+async fn handle_timer<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, t: Timer) {
+    match t {
+        Timer::SceneLaunch(name) => {
+            if matches!(m.pending_scene, Some(ref p) if p.name == name) {
+                warn!("Timed out waiting for the clients of scene '{}'", name);
+                m.pending_scene = None;
+                apply_scene(m, &name).await;
+            }
+        }
+        Timer::Restore(restart) if restart == m.restarts => {
+            for (output, input) in std::mem::take(&mut m.restore) {
+                warn!(
+                    "Can't restore {} → {} after restart, a port is missing",
+                    output, input
+                );
+            }
+        }
+        Timer::Restore(_) => {}
+        Timer::Save => save_remembered(m).await,
+    }
+}
+
+/// Requests from remote control front-ends
+async fn handle_control<J: JackBackend, H: HardwareBackend>(
+    m: &mut Model<J, H>,
+    cmd: ControlCmd,
+    reply: Replier<ControlReply>,
+) {
+    debug!("Handling control request: {:?}", cmd);
+    let no_card = |card: CardId| ControlReply::Error(format!("No card with ID {}", card));
+
+    let shutdown = matches!(cmd, ControlCmd::Shutdown);
+    let r = match cmd {
+        ControlCmd::ListCards => ControlReply::Cards(
+            m.cards
+                .values()
+                .map(|c| CardInfo {
+                    id: c.id,
+                    name: c.name.clone(),
+                    active: c.client_handle.is_some(),
+                })
+                .collect(),
+        ),
+        ControlCmd::ListPorts => ControlReply::Ports(m.graph.ports().cloned().collect()),
+        ControlCmd::ListConnections => ControlReply::Connections(
+            m.graph
+                .connections()
+                .filter_map(|c| connection_names(m, *c))
+                .collect(),
+        ),
+        ControlCmd::Connect { output, input } => control_connection(m, &output, &input, true).await,
+        ControlCmd::Disconnect { output, input } => {
+            control_connection(m, &output, &input, false).await
+        }
+        ControlCmd::SetVolume(volume) if m.cards.contains_key(&volume.card) => {
+            m.hw_handle
+                .send_cmd(HardwareCmd::SetMixerVolume(volume))
+                .await;
+            ControlReply::Ok
+        }
+        ControlCmd::SetMute(mute) if m.cards.contains_key(&mute.card) => {
+            m.hw_handle.send_cmd(HardwareCmd::SetMixerMute(mute)).await;
+            ControlReply::Ok
+        }
+        ControlCmd::SetVolume(VolumeCmd { card, .. }) => no_card(card),
+        ControlCmd::SetMute(MuteCmd { card, .. }) => no_card(card),
+        ControlCmd::CardUsage { card, usage, store } => match m.cards.get(&card).cloned() {
+            Some(c) => control_card_usage(m, c, usage, store).await,
+            None => no_card(card),
+        },
+        ControlCmd::Stats => ControlReply::Stats {
+            settings: m.jack_stats.clone(),
+            xruns: m.xruns,
+        },
+        ControlCmd::ExportSettings => match m.settings.export() {
+            Ok(doc) => ControlReply::Exported(doc),
+            Err(e) => ControlReply::Error(format!("Can't export settings: {}", e)),
+        },
+        ControlCmd::ImportSettings(doc) => match m.settings.import(&doc) {
+            Ok(()) => {
+                info!("Imported settings from a control front-end");
+                m.ui_handle.send_cmd(UiCmd::ClientsChanged).await;
+                m.ui_handle.send_cmd(UiCmd::ScenesChanged).await;
+                ControlReply::Ok
+            }
+            Err(e) => ControlReply::Error(format!("Can't import settings: {}", e)),
+        },
+        ControlCmd::Shutdown => ControlReply::Ok,
+    };
+
+    if let Err(_) = reply.reply(r).await {
+        warn!("Control front-end went away before its reply");
+    }
+
+    if shutdown {
+        info!("=== Recieved Shutdown Request ===");
+        end_program(m).await;
+    }
+}
+
+/// Change a connection between two named ports for a control front-end
+async fn control_connection<J: JackBackend, H: HardwareBackend>(
+    m: &mut Model<J, H>,
+    output: &str,
+    input: &str,
+    connect: bool,
+) -> ControlReply {
+    let c = match (m.graph.port_by_name(output), m.graph.port_by_name(input)) {
+        (Some(o), Some(i)) if o.dir == PortDirection::Output && i.dir == PortDirection::Input => {
+            Connection {
+                output: o.id,
+                input: i.id,
+            }
+        }
+        (Some(_), Some(_)) => {
+            return ControlReply::Error(format!("Can't connect {} to {}", output, input))
+        }
+        (None, _) => return ControlReply::Error(format!("No port named {}", output)),
+        (_, None) => return ControlReply::Error(format!("No port named {}", input)),
+    };
+
+    change_connection(m, c, connect).await;
+    send_history(m).await;
+    ControlReply::Ok
+}
+
+/// Start or stop a card for a control front-end
+async fn control_card_usage<J: JackBackend, H: HardwareBackend>(
+    m: &mut Model<J, H>,
+    card: Card,
+    usage: bool,
+    store: bool,
+) -> ControlReply {
+    let id = card.id;
+    if store {
+        m.settings.w().cards().set_card_usage(&card.name, usage);
+        save_settings(m).await;
+    }
+
+    match card.client_handle {
+        None if usage => {
+            signal_jack_card(card, m).await;
+            if m.cards[&id].client_handle.is_none() {
+                return ControlReply::Error(format!("Card {} could not be started", id));
+            }
+        }
+        Some(handle) if !usage => {
+            debug!("Stopping card {} for control front-end", id);
+            stop_card(m, id, handle).await;
+        }
+        _ => {}
+    }
+
+    ControlReply::Ok
+}
+
+/// Tell remote control front-ends about a jack event
+fn broadcast_jack_ev<J: JackBackend, H: HardwareBackend>(m: &Model<J, H>, ev: &JackEvent) {
+    let names = |a, b| connection_names(m, m.graph.connection(a, b));
+    let ev = match *ev {
+        JackEvent::XRun => ControlEvent::XRun,
+        JackEvent::JackSettings(ref s) => ControlEvent::JackSettings(s.clone()),
+        JackEvent::AddPort(ref port) => ControlEvent::AddPort(port.clone()),
+        JackEvent::DelPort(id) => ControlEvent::DelPort(id),
+        JackEvent::AddConnection(a, b) => match names(a, b) {
+            Some((output, input)) => ControlEvent::AddConnection { output, input },
+            None => return,
+        },
+        JackEvent::DelConnection(a, b) => match names(a, b) {
+            Some((output, input)) => ControlEvent::DelConnection { output, input },
+            None => return,
+        },
+        // The ports are removed (and added back) one by one
+        JackEvent::ServerStopped | JackEvent::ServerStarted | JackEvent::ServerStatus(_) => return,
+    };
+    m.control.broadcast(ev);
+}
+
+/// Tell remote control front-ends about a hardware event
+fn broadcast_hw_ev<J: JackBackend, H: HardwareBackend>(m: &Model<J, H>, ev: &HardwareEvent) {
+    let ev = match *ev {
+        HardwareEvent::NewCardFound { id, ref name, .. } => ControlEvent::AddCard {
+            id,
+            name: name.clone(),
+        },
+        HardwareEvent::DropCard { id } => ControlEvent::DelCard(id),
+        HardwareEvent::UpdateMixerVolume(ref v) => ControlEvent::VolumeChange(v.clone()),
+        HardwareEvent::UpdateMixerMute(ref mute) => ControlEvent::MuteChange(mute.clone()),
+    };
+    m.control.broadcast(ev);
+}
+
+async fn end_program<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) {
+    save_remembered(m).await;
+
+    // Returns once jackd is stopped
     m.jack_handle.send_cmd(JackCmd::Shutdown).await;
     m.jack_handle.close();
     m.hw_handle.send_cmd(HardwareCmd::Shutdown).await;
     m.hw_handle.close();
+    m.launcher.send_cmd(LauncherCmd::Shutdown).await;
+    m.launcher.close();
+    m.control.close();
+    m.reserve.close();
     info!("=== Sending Terminate Request ===");
     m.ui_handle
         .send_cmd(UiCmd::YouDontHaveToGoHomeButYouCantStayHere)
@@ -188,8 +1155,9 @@ async fn end_program(m: &mut Model) {
 }
 
 /// Events from the hardware runtime
-async fn handle_hw_ev(m: &mut Model, ev: HardwareEvent) {
+async fn handle_hw_ev<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, ev: HardwareEvent) {
     debug!("Handling HW event: {:?}", ev);
+    broadcast_hw_ev(m, &ev);
     use HardwareEvent::*;
     match ev {
         NewCardFound {
@@ -216,7 +1184,11 @@ async fn handle_hw_ev(m: &mut Model, ev: HardwareEvent) {
             };
 
             m.cards.insert(id, card.clone());
-            let usage = m.settings.r().cards().use_card(&name);
+            let usage = match m.jack_handle.master_card() {
+                // jackd is already using it, there's nothing to ask
+                Some(master) if master == name => CardUsage::Yes,
+                _ => m.settings.r().cards().use_card(&name),
+            };
 
             match usage {
                 CardUsage::Yes => {
@@ -235,14 +1207,20 @@ async fn handle_hw_ev(m: &mut Model, ev: HardwareEvent) {
         DropCard { id } => {
             let card = m.cards.remove(&id).unwrap();
             match card.client_handle {
-                Some(id) => {
+                Some(handle) => {
                     debug!("Dropping card with ID {}", id);
                     let _ = m
                         .jack_handle
-                        .send_card_action(JackCardAction::StopCard { id })
+                        .send_card_action(JackCardAction::StopCard { id: handle })
                         .await;
+                    m.reserve.release(id).await;
 
-                    m.ui_handle.send_cmd(UiCmd::DelCard(id as i32));
+                    m.ui_handle.send_cmd(UiCmd::DelCard(id)).await;
+                }
+                // The card jackd runs on is shown without an adapter
+                None if card.state == CardStatus::Active => {
+                    info!("Card {} run by jackd was removed", card.name);
+                    m.ui_handle.send_cmd(UiCmd::DelCard(id)).await;
                 }
                 None => {
                     error!("[Error]: Attempt to drop card that was never started, was there an error starting it?")
@@ -264,7 +1242,145 @@ async fn handle_hw_ev(m: &mut Model, ev: HardwareEvent) {
     }
 }
 
-async fn signal_jack_card(card: Card, m: &mut Model) {
+/// A file in the settings directory was changed on disk
+async fn handle_settings_ev<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>, file: String) {
+    match m.settings.reload(&file) {
+        Ok(Some(Section::App)) => {
+            m.rules = Rules::compile(&m.settings.r().app().rules);
+            m.ui_handle.send_cmd(UiCmd::SettingsChanged).await
+        }
+        Ok(Some(Section::Cards)) => apply_card_usage(m).await,
+        Ok(Some(Section::Clients)) => {
+            // Keep what was remembered since the last write
+            if let Some(unsaved) = &m.unsaved {
+                unsaved.apply(&mut m.settings.w().clients());
+            }
+            m.ui_handle.send_cmd(UiCmd::ClientsChanged).await
+        }
+        Ok(Some(Section::Scenes)) => m.ui_handle.send_cmd(UiCmd::ScenesChanged).await,
+        Ok(None) => {}
+        Err(e) => {
+            error!("Ignoring invalid edit of {}: {}", file, e);
+            let error = e.to_string();
+            m.ui_handle
+                .send_cmd(UiCmd::SettingsError { file, error })
+                .await;
+        }
+    }
+}
+
+/// Tell the user about settings files that were unreadable at startup
+async fn report_recovered_settings<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) {
+    for r in m.settings.take_recovered() {
+        m.ui_handle
+            .send_cmd(UiCmd::SettingsRecovered {
+                file: r.file,
+                error: r.error,
+                restored: r.restored,
+            })
+            .await;
+    }
+}
+
+/// Start or stop cards whose usage was changed in the settings
+async fn apply_card_usage<J: JackBackend, H: HardwareBackend>(m: &mut Model<J, H>) {
+    let cards: Vec<Card> = m.cards.values().cloned().collect();
+    for card in cards {
+        let usage = m.settings.r().cards().use_card(&card.name);
+        match (usage, card.client_handle) {
+            (CardUsage::Yes, None) => signal_jack_card(card, m).await,
+            (CardUsage::No, Some(handle)) => {
+                info!("Card {} was disabled in the settings", card.name);
+                stop_card(m, card.id, handle).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Stop a card that was started in jack and give it back
+async fn stop_card<J: JackBackend, H: HardwareBackend>(
+    m: &mut Model<J, H>,
+    id: CardId,
+    handle: u64,
+) {
+    let _ = m
+        .jack_handle
+        .send_card_action(JackCardAction::StopCard { id: handle })
+        .await;
+    m.reserve.release(id).await;
+    if let Some(card) = m.cards.get_mut(&id) {
+        card.client_handle = None;
+    }
+    m.ui_handle.send_cmd(UiCmd::DelCard(id)).await;
+}
+
+/// Events from the device reservation runtime
+async fn handle_reserve_ev<J: JackBackend, H: HardwareBackend>(
+    m: &mut Model<J, H>,
+    ev: ReserveEvent,
+) {
+    debug!("Handling reservation event: {:?}", ev);
+    match ev {
+        ReserveEvent::Lost(id) => {
+            let handle = m.cards.get_mut(&id).and_then(|c| c.client_handle.take());
+            if let Some(handle) = handle {
+                warn!("Card {} was claimed by another program, stopping it", id);
+                let _ = m
+                    .jack_handle
+                    .send_card_action(JackCardAction::StopCard { id: handle })
+                    .await;
+                m.ui_handle.send_cmd(UiCmd::DelCard(id)).await;
+            }
+        }
+    }
+}
+
+/// Build the adapter parameters of a card from its stored settings
+///
+/// Anything not set for the card is picked from the hardware and the
+/// server settings.  Channel counts never exceed what the card has.
+fn card_action<J: JackBackend, H: HardwareBackend>(
+    m: &Model<J, H>,
+    card: &Card,
+    rate: u32,
+    n_in: u32,
+    n_out: u32,
+) -> JackCardAction {
+    let stored = m.settings.r().cards().card(&card.name).cloned();
+    let stored = stored.unwrap_or_else(|| SoundCard::new(card.name.clone()));
+    let app = m.settings.r().app();
+
+    JackCardAction::StartCard {
+        id: card.id.to_string(),
+        name: stored.client_name.unwrap_or_else(|| card.name.clone()),
+        rate: stored.rate.unwrap_or(rate),
+        in_ports: stored.in_channels.map_or(n_in, |n| n.min(n_in)),
+        out_ports: stored.out_channels.map_or(n_out, |n| n.min(n_out)),
+        n_periods: stored.n_periods.unwrap_or(app.jack.n_periods),
+        resample_q: stored.resample_q.unwrap_or(app.jack.resample_q),
+    }
+}
+
+async fn signal_jack_card<J: JackBackend, H: HardwareBackend>(card: Card, m: &mut Model<J, H>) {
+    // Started as soon as there is a server
+    if !m.jack_handle.running() {
+        debug!("No jack server yet, card {} has to wait", card.name);
+        m.restart_cards.insert(card.id);
+        return;
+    }
+
+    // The card jackd runs on has no adapter, only show it
+    if m.jack_handle.master_card().as_ref() == Some(&card.name) {
+        let state = &mut m.cards.get_mut(&card.id).unwrap().state;
+        if *state != CardStatus::Active {
+            info!("Card {} is run by jackd directly", card.name);
+            *state = CardStatus::Active;
+            m.ui_handle.send_cmd(UiCmd::AddCard(card)).await;
+        }
+        return;
+    }
+
     let capture = card.capture().clone();
     let playback = card.playback().clone();
 
@@ -273,23 +1389,973 @@ async fn signal_jack_card(card: Card, m: &mut Model) {
             warn!("IN rate is not equal to OUT rate");
         }
 
+        // PulseAudio or PipeWire may still hold the card
+        let priority = m.settings.r().cards().reserve_priority();
+        if let Err(e) = m
+            .reserve
+            .acquire(card.id, card.name.clone(), priority)
+            .await
+        {
+            error!("Card {} is in use by another program: {}", card.id, e);
+            return;
+        }
+
         // Inform Jack here
-        let client_handle = m
-            .jack_handle
-            .send_card_action(JackCardAction::StartCard {
-                id: card.id.to_string(),
-                name: card.name.clone(),
-                rate: r_in, // FIXME: AAAAAAAAAAAAAAAAAAAAAAAH!
-                in_ports: n_in,
-                out_ports: n_out,
-            })
-            .await;
+        let action = card_action(m, &card, r_in, n_in, n_out);
+        let client_handle = m.jack_handle.send_card_action(action).await;
         match client_handle {
             Ok(h) => {
                 m.cards.get_mut(&card.id).unwrap().client_handle = Some(h);
                 m.ui_handle.send_cmd(UiCmd::AddCard(card)).await;
             }
-            Err(e) => error!("Card {} Could not be started by jack: {}", card.id, e),
+            Err(e) => {
+                error!("Card {} Could not be started by jack: {}", card.id, e);
+                m.reserve.release(card.id).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cb_channel;
+    use crate::model::card::{CardConfig, MixerChannel};
+    use crate::model::events::{MuteCmd, VolumeCmd};
+    use crate::model::port::{PortDirection, PortType};
+    use crate::rts::control;
+    use crate::rts::hardware::{MockHardware, MockStep};
+    use crate::rts::jack::FakeJack;
+    use crate::test_dir::TestDir;
+    use async_std::channel::Receiver;
+
+    /// Create a settings tree in a fresh temporary directory
+    fn settings() -> (Arc<Settings>, TestDir) {
+        let dir = TestDir::new("test");
+        (Settings::init(&*dir).unwrap(), dir)
+    }
+
+    type TestModel = Model<FakeJack, MockHardware>;
+
+    /// The model keeps its settings until the directory guard is dropped
+    fn model(script: Vec<MockStep>) -> (TestModel, Receiver<UiCmd>, TestDir) {
+        let (ui, ui_rx, _) = UiHandle::test_pair();
+        let hw = MockHardware::new(script);
+        let launcher = LauncherHandle::new();
+        let (control, _) = control::channel();
+        let reserve = ReserveHandle::start(|| Err(dbus::Error::new_failed("No bus in tests")));
+        let (settings, dir) = settings();
+        let watcher = SettingsWatcher::start(settings.dir());
+        (
+            Model::new(
+                FakeJack::new(),
+                ui,
+                hw,
+                launcher,
+                control,
+                reserve,
+                watcher,
+                settings,
+            ),
+            ui_rx,
+            dir,
+        )
+    }
+
+    fn channel() -> MixerChannel {
+        MixerChannel {
+            id: (0, "Master".into()),
+            name: "Master".into(),
+            is_playback: true,
+            has_switch: true,
+            volume_min: 0,
+            volume_max: 100,
+            volume: 50,
+            switch: false,
+            dirty: false,
         }
     }
+
+    fn new_card(id: CardId, name: &str) -> HardwareEvent {
+        let cfg = CardConfig {
+            sample_rate: 48000,
+            channels: 2,
+        };
+        HardwareEvent::NewCardFound {
+            id,
+            name: name.into(),
+            capture: Some(cfg.clone()),
+            playback: Some(cfg),
+            mixerchannels: vec![channel()],
+        }
+    }
+
+    /// Feed the next `n` scripted hardware events into the model
+    async fn step(m: &mut TestModel, n: usize) {
+        for _ in 0..n {
+            let ev = m.hw_handle.next_event().await.unwrap();
+            handle_hw_ev(m, ev).await;
+        }
+    }
+
+    /// Send a control request to the model and wait for its reply
+    async fn request(m: &mut TestModel, cmd: ControlCmd) -> ControlReply {
+        let (tx, rx) = cb_channel::bounded(1);
+        let req = task::spawn(async move { tx.send(cmd).await.unwrap() });
+        let (cmd, reply) = rx.recv().await.unwrap();
+        handle_control(m, cmd, reply).await;
+        req.await
+    }
+
+    /// Feed the next `n` fake jack events into the model
+    async fn step_jack(m: &mut TestModel, n: usize) {
+        for _ in 0..n {
+            let ev = m.jack_handle.next_event().await.unwrap();
+            handle_jack_ev(m, ev).await;
+        }
+    }
+
+    #[test]
+    fn unknown_card_asks_user() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            step(&mut m, 1).await;
+
+            match ui_rx.try_recv() {
+                Ok(UiCmd::AskCard(card)) => assert_eq!(card.name, "USB Audio"),
+                e => panic!("Expected AskCard, got {:?}", e),
+            }
+            assert!(m.cards.contains_key(&1));
+        });
+    }
+
+    #[test]
+    fn unused_card_is_ignored() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "HDMI"))]);
+            m.settings
+                .w()
+                .cards()
+                .set_card_usage(&"HDMI".to_owned(), false);
+            step(&mut m, 1).await;
+
+            assert!(ui_rx.try_recv().is_err());
+            assert_eq!(m.cards[&1].client_handle, None);
+        });
+    }
+
+    #[test]
+    fn mixer_updates_reach_ui() {
+        task::block_on(async {
+            let volume = VolumeCmd {
+                card: 1,
+                channel: (0, "Master".into()),
+                volume: 80,
+            };
+            let (mut m, ui_rx, _dir) = model(vec![
+                MockStep::Emit(new_card(1, "USB Audio")),
+                MockStep::Emit(HardwareEvent::UpdateMixerVolume(volume)),
+            ]);
+            step(&mut m, 2).await;
+
+            let _ = ui_rx.try_recv(); // AskCard
+            match ui_rx.try_recv() {
+                Ok(UiCmd::VolumeChange(v)) => assert_eq!(v.volume, 80),
+                e => panic!("Expected VolumeChange, got {:?}", e),
+            }
+        });
+    }
+
+    #[test]
+    fn mixer_commands_reach_hardware() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            step(&mut m, 1).await;
+            let _ = ui_rx.try_recv(); // AskCard
+
+            let mute = MuteCmd {
+                card: 1,
+                channel: (0, "Master".into()),
+                mute: true,
+            };
+            handle_ui_ev(&mut m, UiEvent::SetMuting(mute)).await;
+
+            match m.hw_handle.commands().as_slice() {
+                [HardwareCmd::SetMixerMute(m)] => assert!(m.mute),
+                cmds => panic!("Unexpected hardware commands {:?}", cmds),
+            }
+
+            // The mock echoes the change back like ALSA would
+            step(&mut m, 1).await;
+            match ui_rx.try_recv() {
+                Ok(UiCmd::MuteChange(m)) => assert!(m.mute),
+                e => panic!("Expected MuteChange, got {:?}", e),
+            }
+        });
+    }
+
+    #[test]
+    fn dropping_unstarted_card() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![
+                MockStep::Emit(new_card(1, "USB Audio")),
+                MockStep::Emit(HardwareEvent::DropCard { id: 1 }),
+            ]);
+            step(&mut m, 2).await;
+
+            assert!(m.cards.is_empty());
+            let _ = ui_rx.try_recv(); // AskCard
+            assert!(ui_rx.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn jack_events_reach_ui() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            jack.set_connection(out, _in, true);
+            jack.xrun();
+            step_jack(&mut m, 4).await;
+
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::AddPort(p)) if p.id == out));
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::AddPort(p)) if p.id == _in));
+            assert!(
+                matches!(ui_rx.try_recv(), Ok(UiCmd::AddConnection(a, b)) if a == _in && b == out)
+            );
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::IncrementXRun)));
+
+            assert_eq!(m.graph().ports().count(), 2);
+            assert!(m.graph().is_connected(out, _in));
+        });
+    }
+
+    #[test]
+    fn ui_connections_reach_jack() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+
+            handle_ui_ev(&mut m, UiEvent::SetConnection(out, _in, true)).await;
+            assert!(jack.is_connected(out, _in));
+
+            handle_ui_ev(&mut m, UiEvent::SetConnection(out, _in, false)).await;
+            assert!(!jack.is_connected(out, _in));
+        });
+    }
+
+    #[test]
+    fn used_card_is_started() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            m.settings
+                .w()
+                .cards()
+                .set_card_usage(&"USB Audio".to_owned(), true);
+            step(&mut m, 1).await;
+
+            let clients = m.jack_handle.loaded_clients();
+            assert_eq!(clients.len(), 1);
+            assert_eq!(m.cards[&1].client_handle, Some(clients[0].0));
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::AddCard(c)) if c.id == 1));
+            assert!(m.jack_handle.port_by_name("USB Audio:capture_2").is_some());
+        });
+    }
+
+    #[test]
+    fn failed_card_is_not_added() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            m.jack_handle.fail_card_loads(true);
+            m.settings
+                .w()
+                .cards()
+                .set_card_usage(&"USB Audio".to_owned(), true);
+            step(&mut m, 1).await;
+
+            assert_eq!(m.cards[&1].client_handle, None);
+            assert!(ui_rx.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn dropping_started_card() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![
+                MockStep::Emit(new_card(1, "USB Audio")),
+                MockStep::Emit(HardwareEvent::DropCard { id: 1 }),
+            ]);
+            m.settings
+                .w()
+                .cards()
+                .set_card_usage(&"USB Audio".to_owned(), true);
+            step(&mut m, 2).await;
+
+            assert!(m.jack_handle.loaded_clients().is_empty());
+            assert!(m.jack_handle.port_by_name("USB Audio:capture_1").is_none());
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::AddCard(_))));
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::DelCard(1))));
+        });
+    }
+
+    #[test]
+    fn undo_and_redo_connections() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            step_jack(&mut m, 2).await;
+
+            handle_ui_ev(&mut m, UiEvent::SetConnection(out, _in, true)).await;
+            step_jack(&mut m, 1).await;
+            assert!(jack.is_connected(out, _in));
+
+            handle_ui_ev(&mut m, UiEvent::Undo).await;
+            step_jack(&mut m, 1).await;
+            assert!(!jack.is_connected(out, _in));
+            assert!(m.history.entries().is_empty());
+
+            handle_ui_ev(&mut m, UiEvent::Redo).await;
+            step_jack(&mut m, 1).await;
+            assert!(jack.is_connected(out, _in));
+            assert_eq!(m.history.entries().len(), 1);
+        });
+    }
+
+    #[test]
+    fn external_changes_are_not_undoable() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            jack.set_connection(out, _in, true);
+            step_jack(&mut m, 3).await;
+
+            let entries = m.history.entries();
+            assert_eq!(entries.len(), 1);
+            assert!(!entries[0].undoable);
+            assert_eq!(entries[0].label, "synth:out_1 → system:playback_1");
+
+            handle_ui_ev(&mut m, UiEvent::Undo).await;
+            assert!(jack.is_connected(out, _in));
+        });
+    }
+
+    #[test]
+    fn save_and_recall_scene() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            jack.set_connection(out, _in, true);
+            step_jack(&mut m, 3).await;
+
+            handle_ui_ev(&mut m, UiEvent::SaveScene("live".into())).await;
+            jack.set_connection(out, _in, false);
+            step_jack(&mut m, 1).await;
+
+            // Pretend the scene also had a port that doesn't exist now
+            m.settings
+                .w()
+                .scenes()
+                .scenes
+                .get_mut("live")
+                .unwrap()
+                .connections
+                .insert(("reverb:out".into(), "system:playback_1".into()));
+
+            while ui_rx.try_recv().is_ok() {}
+            handle_ui_ev(&mut m, UiEvent::RecallScene("live".into())).await;
+            assert!(jack.is_connected(out, _in));
+
+            let mut missing = None;
+            while let Ok(cmd) = ui_rx.try_recv() {
+                if let UiCmd::SceneRecalled { missing: m, .. } = cmd {
+                    missing = Some(m);
+                }
+            }
+            assert_eq!(
+                missing,
+                Some(vec![("reverb:out".into(), "system:playback_1".into())])
+            );
+        });
+    }
+
+    #[test]
+    fn scene_waits_for_launched_client() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            step_jack(&mut m, 1).await;
+
+            let mut scene = settings::Scene::default();
+            scene
+                .connections
+                .insert(("reverb:out".into(), "system:playback_1".into()));
+            m.settings.w().scenes().scenes.insert("live".into(), scene);
+            m.settings.w().clients().update(Client {
+                command: vec!["true".into()],
+                ..Client::new("reverb".into())
+            });
+
+            handle_ui_ev(&mut m, UiEvent::RecallScene("live".into())).await;
+            assert!(m.pending_scene.is_some());
+
+            // The launched program registers its port
+            let out = jack.add_port("reverb", "out", PortType::Audio, PortDirection::Output);
+            step_jack(&mut m, 1).await;
+            assert!(m.pending_scene.is_none());
+            assert!(jack.is_connected(out, _in));
+        });
+    }
+
+    #[test]
+    fn crashed_client_is_respawned() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            m.settings.w().clients().update(Client {
+                command: vec!["false".into()],
+                respawn: true,
+                ..Client::new("synth".into())
+            });
+
+            let crash = || LauncherEvent::Exited {
+                name: "synth".into(),
+                success: false,
+                uptime: Duration::from_secs(1),
+            };
+            for _ in 0..MAX_RESPAWNS + 1 {
+                handle_launcher_ev(&mut m, crash()).await;
+            }
+            assert_eq!(m.respawns["synth"], MAX_RESPAWNS + 1);
+
+            // Running for a while resets the count
+            handle_launcher_ev(
+                &mut m,
+                LauncherEvent::Exited {
+                    name: "synth".into(),
+                    success: false,
+                    uptime: RESPAWN_RESET * 2,
+                },
+            )
+            .await;
+            assert_eq!(m.respawns["synth"], 1);
+        });
+    }
+
+    #[test]
+    fn new_clients_are_remembered() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            m.jack_handle
+                .add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            step_jack(&mut m, 1).await;
+
+            let clients = m.settings.r().clients();
+            let synth = clients.find("synth").unwrap();
+            assert!(!synth.launchable());
+        });
+    }
+
+    #[test]
+    fn replugged_ports_are_reconnected() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            jack.set_connection(out, _in, true);
+            step_jack(&mut m, 3).await;
+
+            // The synth restarts and comes back with a new port ID
+            jack.del_port(out);
+            step_jack(&mut m, 2).await;
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            step_jack(&mut m, 2).await;
+            assert!(jack.is_connected(out, _in));
+
+            // Connections the user removes are forgotten
+            handle_ui_ev(&mut m, UiEvent::SetConnection(out, _in, false)).await;
+            step_jack(&mut m, 1).await;
+            jack.del_port(out);
+            step_jack(&mut m, 1).await;
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            step_jack(&mut m, 1).await;
+            assert!(!jack.is_connected(out, _in));
+        });
+    }
+
+    #[test]
+    fn external_disconnects_are_forgotten() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            jack.set_connection(out, _in, true);
+            step_jack(&mut m, 3).await;
+
+            // Written once the burst of changes is over
+            let path = m.settings.dir().join("clients.json");
+            let stored = || std::fs::read_to_string(&path).unwrap_or_default();
+            assert!(!stored().contains("synth:out_1"));
+            handle_timer(&mut m, Timer::Save).await;
+            assert!(stored().contains("synth:out_1"));
+
+            // A port going away doesn't disconnect it for good
+            jack.del_port(out);
+            step_jack(&mut m, 2).await;
+            handle_timer(&mut m, Timer::Save).await;
+            assert!(stored().contains("synth:out_1"));
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            step_jack(&mut m, 2).await;
+            assert!(jack.is_connected(out, _in));
+
+            // Another program taking it away does
+            jack.set_connection(out, _in, false);
+            step_jack(&mut m, 1).await;
+            handle_timer(&mut m, Timer::Save).await;
+            assert!(!stored().contains("synth:out_1"));
+        });
+    }
+
+    #[test]
+    fn unsaved_clients_survive_edits_on_disk() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            m.jack_handle
+                .add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            step_jack(&mut m, 1).await;
+
+            // Edited before the new client was written
+            let path = m.settings.dir().join("clients.json");
+            let edited = std::fs::read_to_string(&path).unwrap().replace(
+                r#""connections": []"#,
+                r#""connections": [["a:out", "b:in"]]"#,
+            );
+            std::fs::write(&path, edited).unwrap();
+            handle_settings_ev(&mut m, "clients.json".into()).await;
+            assert!(m.settings.r().clients().find("synth").is_some());
+            handle_timer(&mut m, Timer::Save).await;
+
+            let stored = std::fs::read_to_string(&path).unwrap();
+            assert!(stored.contains("synth"));
+            assert!(stored.contains("a:out"));
+        });
+    }
+
+    #[test]
+    fn reconnect_can_be_disabled() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            m.settings.w().clients().update(Client {
+                reconnect: false,
+                ..Client::new("synth".into())
+            });
+            m.settings
+                .w()
+                .clients()
+                .remember("synth:out_1".into(), "system:playback_1".into());
+
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            step_jack(&mut m, 2).await;
+            assert!(!jack.is_connected(out, _in));
+
+            // Not even when the other end comes back
+            jack.del_port(_in);
+            step_jack(&mut m, 1).await;
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            step_jack(&mut m, 1).await;
+            assert!(!jack.is_connected(out, _in));
+        });
+    }
+
+    #[test]
+    fn rules_connect_new_ports() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            let rules = vec![settings::Rule {
+                pattern: r"^Firefox:output_(\d)$".into(),
+                target: "system:playback_$1".into(),
+                ..Default::default()
+            }];
+            handle_ui_ev(&mut m, UiEvent::SetRules(rules)).await;
+
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            let out = jack.add_port(
+                "Firefox",
+                "output_1",
+                PortType::Audio,
+                PortDirection::Output,
+            );
+            step_jack(&mut m, 2).await;
+            assert!(jack.is_connected(out, _in));
+        });
+    }
+
+    #[test]
+    fn control_requests() {
+        task::block_on(async {
+            let (mut m, _ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            let jack = m.jack_handle.clone();
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            let _in = jack.add_port(
+                "system",
+                "playback_1",
+                PortType::Audio,
+                PortDirection::Input,
+            );
+            step_jack(&mut m, 2).await;
+            step(&mut m, 1).await;
+
+            match request(&mut m, ControlCmd::ListPorts).await {
+                ControlReply::Ports(ports) => assert_eq!(ports.len(), 2),
+                r => panic!("Unexpected reply {:?}", r),
+            }
+
+            let connect = ControlCmd::Connect {
+                output: "synth:out_1".into(),
+                input: "system:playback_1".into(),
+            };
+            assert_eq!(request(&mut m, connect).await, ControlReply::Ok);
+            assert!(jack.is_connected(out, _in));
+            step_jack(&mut m, 1).await;
+            assert_eq!(
+                request(&mut m, ControlCmd::ListConnections).await,
+                ControlReply::Connections(vec![("synth:out_1".into(), "system:playback_1".into())])
+            );
+
+            let backwards = ControlCmd::Connect {
+                output: "system:playback_1".into(),
+                input: "synth:out_1".into(),
+            };
+            assert!(matches!(
+                request(&mut m, backwards).await,
+                ControlReply::Error(_)
+            ));
+
+            let volume = VolumeCmd {
+                card: 7,
+                channel: (0, "Master".into()),
+                volume: 10,
+            };
+            assert_eq!(
+                request(&mut m, ControlCmd::SetVolume(volume)).await,
+                ControlReply::Error("No card with ID 7".into())
+            );
+            assert!(matches!(
+                request(&mut m, ControlCmd::ListCards).await,
+                ControlReply::Cards(cards) if cards[0].name == "USB Audio" && !cards[0].active
+            ));
+        });
+    }
+
+    #[test]
+    fn master_card_has_no_adapter() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![
+                MockStep::Emit(new_card(1, "USB Audio")),
+                MockStep::Emit(HardwareEvent::DropCard { id: 1 }),
+            ]);
+            m.jack_handle.set_master_card(Some("USB Audio"));
+            step(&mut m, 1).await;
+
+            assert!(m.jack_handle.loaded_clients().is_empty());
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::AddCard(_))));
+            assert_eq!(m.cards[&1].state, CardStatus::Active);
+
+            // Reloading the card settings doesn't start it twice
+            apply_card_usage(&mut m).await;
+            assert!(m.jack_handle.loaded_clients().is_empty());
+            assert!(ui_rx.try_recv().is_err());
+
+            // Unplugging it removes it like any other card
+            step(&mut m, 1).await;
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::DelCard(1))));
+            assert!(!m.cards.contains_key(&1));
+        });
+    }
+
+    #[test]
+    fn card_settings_restart_adapter() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            m.settings
+                .w()
+                .cards()
+                .set_card_usage(&"USB Audio".to_owned(), true);
+            step(&mut m, 1).await;
+            assert!(m.jack_handle.port_by_name("USB Audio:capture_2").is_some());
+
+            let mut card = m.settings.r().cards().card("USB Audio").unwrap().clone();
+            card.client_name = Some("Desk".into());
+            card.in_channels = Some(1);
+            card.out_channels = Some(8);
+            handle_ui_ev(&mut m, UiEvent::UpdateCard(card)).await;
+
+            let clients = m.jack_handle.loaded_clients();
+            assert_eq!(clients.len(), 1);
+            assert_eq!(clients[0].1, "Desk");
+            assert!(m.jack_handle.port_by_name("Desk:capture_1").is_some());
+            assert!(m.jack_handle.port_by_name("Desk:capture_2").is_none());
+            assert!(m.jack_handle.port_by_name("Desk:playback_3").is_none());
+            let mut removed = false;
+            while let Ok(cmd) = ui_rx.try_recv() {
+                removed |= matches!(cmd, UiCmd::DelCard(1));
+            }
+            assert!(removed);
+        });
+    }
+
+    #[test]
+    fn restart_restores_cards_and_connections() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            m.settings
+                .w()
+                .cards()
+                .set_card_usage(&"USB Audio".to_owned(), true);
+            step(&mut m, 1).await;
+            let jack = m.jack_handle.clone();
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            step_jack(&mut m, 5).await;
+
+            let _in = jack.port_by_name("USB Audio:playback_1").unwrap();
+            handle_ui_ev(&mut m, UiEvent::SetConnection(out, _in, true)).await;
+            step_jack(&mut m, 1).await;
+
+            // Stopped, started, then the synth and the card come back
+            handle_ui_ev(&mut m, UiEvent::RestartJack).await;
+            step_jack(&mut m, 2).await;
+            assert_eq!(m.graph().ports().count(), 0);
+            step_jack(&mut m, 5).await;
+
+            let out = jack.port_by_name("synth:out_1").unwrap();
+            let _in = jack.port_by_name("USB Audio:playback_1").unwrap();
+            assert!(jack.is_connected(out, _in));
+            assert!(m.restore.is_empty());
+            assert_eq!(jack.loaded_clients().len(), 1);
+            assert_eq!(m.cards[&1].client_handle, Some(jack.loaded_clients()[0].0));
+
+            step_jack(&mut m, 1).await;
+            assert!(m.graph().is_connected(out, _in));
+            let mut added = 0;
+            while let Ok(cmd) = ui_rx.try_recv() {
+                added += matches!(cmd, UiCmd::AddCard(c) if c.id == 1) as u32;
+            }
+            assert_eq!(added, 2);
+        });
+    }
+
+    #[test]
+    fn server_outage_clears_ports() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![]);
+            let jack = m.jack_handle.clone();
+            let out = jack.add_port("synth", "out_1", PortType::Audio, PortDirection::Output);
+            step_jack(&mut m, 1).await;
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::AddPort(_))));
+
+            jack.set_running(false);
+            step_jack(&mut m, 1).await;
+            assert_eq!(m.graph().ports().count(), 0);
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::DelPort(id)) if id == out));
+        });
+    }
+
+    #[test]
+    fn cards_wait_for_server() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            m.settings
+                .w()
+                .cards()
+                .set_card_usage(&"USB Audio".to_owned(), true);
+            m.jack_handle.set_running(false);
+            step_jack(&mut m, 1).await;
+            while ui_rx.try_recv().is_ok() {}
+            step(&mut m, 1).await;
+
+            assert!(m.jack_handle.loaded_clients().is_empty());
+            assert!(ui_rx.try_recv().is_err());
+
+            m.jack_handle.set_running(true);
+            step_jack(&mut m, 1).await;
+            assert_eq!(m.jack_handle.loaded_clients().len(), 1);
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::AddCard(c)) if c.id == 1));
+        });
+    }
+
+    #[test]
+    fn control_card_usage_and_stats() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            step(&mut m, 1).await;
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::AskCard(_))));
+
+            let start = ControlCmd::CardUsage {
+                card: 1,
+                usage: true,
+                store: true,
+            };
+            assert_eq!(request(&mut m, start).await, ControlReply::Ok);
+            assert_eq!(m.jack_handle.loaded_clients().len(), 1);
+            assert_eq!(
+                m.settings.r().cards().use_card(&"USB Audio".to_owned()),
+                CardUsage::Yes
+            );
+
+            let stop = ControlCmd::CardUsage {
+                card: 1,
+                usage: false,
+                store: false,
+            };
+            assert_eq!(request(&mut m, stop).await, ControlReply::Ok);
+            assert!(m.jack_handle.loaded_clients().is_empty());
+
+            let stats = events::JackSettings {
+                cpu_percentage: 3.0,
+                sample_rate: 48000,
+                buffer_size: 256,
+                latency: 5.3,
+            };
+            handle_jack_ev(&mut m, JackEvent::XRun).await;
+            handle_jack_ev(&mut m, JackEvent::JackSettings(stats.clone())).await;
+            assert_eq!(
+                request(&mut m, ControlCmd::Stats).await,
+                ControlReply::Stats {
+                    settings: Some(stats),
+                    xruns: 1
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn card_usage_edited_on_disk() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            m.settings
+                .w()
+                .cards()
+                .set_card_usage(&"USB Audio".to_owned(), true);
+            m.settings.sync().unwrap();
+            step(&mut m, 1).await;
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::AddCard(_))));
+
+            let path = m.settings.dir().join("cards.json");
+            let edited = std::fs::read_to_string(&path)
+                .unwrap()
+                .replace(r#""_use": true"#, r#""_use": false"#);
+            std::fs::write(&path, edited).unwrap();
+            handle_settings_ev(&mut m, "cards.json".into()).await;
+            assert!(m.jack_handle.loaded_clients().is_empty());
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::DelCard(1))));
+
+            std::fs::write(&path, "not json").unwrap();
+            handle_settings_ev(&mut m, "cards.json".into()).await;
+            assert!(matches!(
+                ui_rx.try_recv(),
+                Ok(UiCmd::SettingsError { file, .. }) if file == "cards.json"
+            ));
+        });
+    }
+
+    #[test]
+    fn unsaved_settings_are_reported() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![]);
+            std::fs::remove_dir_all(m.settings.dir()).unwrap();
+            handle_ui_ev(&mut m, UiEvent::SaveScene("Live".into())).await;
+            assert!(matches!(
+                ui_rx.try_recv(),
+                Ok(UiCmd::SettingsUnsaved { .. })
+            ));
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::ScenesChanged)));
+        });
+    }
+
+    #[test]
+    fn card_claimed_by_others_is_stopped() {
+        task::block_on(async {
+            let (mut m, ui_rx, _dir) = model(vec![MockStep::Emit(new_card(1, "USB Audio"))]);
+            m.settings
+                .w()
+                .cards()
+                .set_card_usage(&"USB Audio".to_owned(), true);
+            step(&mut m, 1).await;
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::AddCard(_))));
+
+            handle_reserve_ev(&mut m, ReserveEvent::Lost(1)).await;
+            assert!(m.jack_handle.loaded_clients().is_empty());
+            assert_eq!(m.cards[&1].client_handle, None);
+            assert!(matches!(ui_rx.try_recv(), Ok(UiCmd::DelCard(1))));
+        });
+    }
 }
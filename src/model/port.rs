@@ -30,6 +30,11 @@ impl Port {
             is_hw,
         }
     }
+
+    /// Get the full `client:port` name of this port
+    pub fn full_name(&self) -> String {
+        format!("{}:{}", self.client_name, self.port_name)
+    }
 }
 
 /// Type of port
@@ -0,0 +1,262 @@
+//! Evaluate auto-connection rules against the port graph
+//!
+//! Enabled rules are tried in order of their priority, and the first
+//! rule matching a port decides where it gets connected.  Invalid
+//! patterns are logged and skipped.  Rules are compiled once, when
+//! they're loaded or changed.
+
+use crate::model::{
+    graph::{Connection, Graph},
+    port::{Port, PortDirection, PortType},
+};
+use crate::settings::{Rule, RuleDirection, RulePortType};
+use regex::Regex;
+use std::cmp::Reverse;
+
+/// A rule with its pattern compiled
+#[derive(Debug)]
+struct Compiled {
+    rule: Rule,
+    regex: Regex,
+}
+
+impl Compiled {
+    /// Check if the rule pattern and filters match a port
+    fn matches(&self, port: &Port) -> bool {
+        let dir = match self.rule.direction {
+            RuleDirection::Output => PortDirection::Output,
+            RuleDirection::Input => PortDirection::Input,
+        };
+        let tt = match self.rule.port_type {
+            RulePortType::Any => true,
+            RulePortType::Audio => port.tt == PortType::Audio,
+            RulePortType::Midi => port.tt == PortType::Midi,
+        };
+
+        port.dir == dir && tt && self.regex.is_match(&port.full_name())
+    }
+
+    /// Expand the target port name for a matching port
+    fn target(&self, port: &Port) -> Option<String> {
+        let name = port.full_name();
+        let caps = self.regex.captures(&name)?;
+        let mut target = String::new();
+        caps.expand(&self.rule.target, &mut target);
+        Some(target)
+    }
+
+    fn connection(&self, port: &Port, target: &Port) -> Option<Connection> {
+        if port.dir == target.dir || port.tt != target.tt {
+            warn!(
+                "Rule '{}' can't connect {} to {}",
+                self.rule.pattern,
+                port.full_name(),
+                target.full_name()
+            );
+            return None;
+        }
+
+        Some(match self.rule.direction {
+            RuleDirection::Output => Connection {
+                output: port.id,
+                input: target.id,
+            },
+            RuleDirection::Input => Connection {
+                output: target.id,
+                input: port.id,
+            },
+        })
+    }
+}
+
+/// All enabled rules, highest priority first
+#[derive(Debug, Default)]
+pub struct Rules(Vec<Compiled>);
+
+impl Rules {
+    /// Compile the enabled rules
+    pub fn compile(rules: &[Rule]) -> Self {
+        let mut compiled: Vec<_> = rules
+            .iter()
+            .filter(|rule| rule.enabled)
+            .filter_map(|rule| match Regex::new(&rule.pattern) {
+                Ok(regex) => Some(Compiled {
+                    rule: rule.clone(),
+                    regex,
+                }),
+                Err(e) => {
+                    warn!("Ignoring rule '{}': {}", rule.pattern, e);
+                    None
+                }
+            })
+            .collect();
+
+        // Sorting is stable, so equal priorities keep their order
+        compiled.sort_by_key(|c| Reverse(c.rule.priority));
+        Self(compiled)
+    }
+
+    /// The first rule matching a port, and the name of its target
+    fn first_match(&self, port: &Port) -> Option<(&Compiled, String)> {
+        let rule = self.0.iter().find(|r| r.matches(port))?;
+        Some((rule, rule.target(port)?))
+    }
+
+    /// Find the connections rules make when `port` registers
+    ///
+    /// This covers both the new port matching a rule, and the new port
+    /// being the target of a rule for a port that already exists.
+    pub fn evaluate(&self, port: &Port, graph: &Graph) -> Vec<Connection> {
+        let mut connections = vec![];
+        if self.0.is_empty() {
+            return connections;
+        }
+
+        if let Some((rule, target)) = self.first_match(port) {
+            if let Some(c) = graph
+                .port_by_name(&target)
+                .and_then(|t| rule.connection(port, t))
+            {
+                connections.push(c);
+            }
+        }
+
+        let name = port.full_name();
+        for other in graph.ports().filter(|p| p.id != port.id) {
+            match self.first_match(other) {
+                Some((rule, target)) if target == name => {
+                    connections.extend(rule.connection(other, port));
+                }
+                _ => {}
+            }
+        }
+
+        connections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(id: u32, name: &str, tt: PortType, dir: PortDirection) -> Port {
+        let (client, port) = crate::model::graph::split_name(name).unwrap();
+        Port::new(client.into(), port.into(), id, tt, dir, false)
+    }
+
+    fn rule(pattern: &str, target: &str, priority: i32) -> Rule {
+        Rule {
+            pattern: pattern.into(),
+            target: target.into(),
+            priority,
+            ..Default::default()
+        }
+    }
+
+    fn graph() -> Graph {
+        let mut g = Graph::default();
+        g.add_port(port(
+            1,
+            "system:playback_1",
+            PortType::Audio,
+            PortDirection::Input,
+        ));
+        g.add_port(port(
+            2,
+            "system:playback_2",
+            PortType::Audio,
+            PortDirection::Input,
+        ));
+        g
+    }
+
+    #[test]
+    fn capture_groups_pick_target() {
+        let mut g = graph();
+        let ff = port(
+            3,
+            "Firefox:output_2",
+            PortType::Audio,
+            PortDirection::Output,
+        );
+        g.add_port(ff.clone());
+
+        let rules = vec![rule(r"^Firefox:output_(\d)$", "system:playback_$1", 0)];
+        assert_eq!(
+            Rules::compile(&rules).evaluate(&ff, &g),
+            vec![Connection {
+                output: 3,
+                input: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn highest_priority_wins() {
+        let mut g = graph();
+        let ff = port(
+            3,
+            "Firefox:output_1",
+            PortType::Audio,
+            PortDirection::Output,
+        );
+        g.add_port(ff.clone());
+
+        let mut rules = vec![
+            rule("^Firefox:", "system:playback_1", 0),
+            rule("^Firefox:", "system:playback_2", 10),
+            rule("[", "system:playback_1", 20),
+        ];
+        assert_eq!(Rules::compile(&rules).evaluate(&ff, &g)[0].input, 2);
+
+        rules[1].enabled = false;
+        assert_eq!(Rules::compile(&rules).evaluate(&ff, &g)[0].input, 1);
+    }
+
+    #[test]
+    fn filters_type_and_direction() {
+        let mut g = graph();
+        let ff = port(
+            3,
+            "Firefox:output_1",
+            PortType::Audio,
+            PortDirection::Output,
+        );
+        g.add_port(ff.clone());
+
+        let mut midi = rule("^Firefox:", "system:playback_1", 0);
+        midi.port_type = RulePortType::Midi;
+        assert!(Rules::compile(&[midi]).evaluate(&ff, &g).is_empty());
+
+        let mut input = rule("^Firefox:", "system:playback_1", 0);
+        input.direction = RuleDirection::Input;
+        assert!(Rules::compile(&[input]).evaluate(&ff, &g).is_empty());
+    }
+
+    #[test]
+    fn target_registering_later() {
+        let mut g = Graph::default();
+        g.add_port(port(
+            3,
+            "Firefox:output_1",
+            PortType::Audio,
+            PortDirection::Output,
+        ));
+        let playback = port(
+            1,
+            "system:playback_1",
+            PortType::Audio,
+            PortDirection::Input,
+        );
+        g.add_port(playback.clone());
+
+        let rules = vec![rule(r"^Firefox:output_(\d)$", "system:playback_$1", 0)];
+        assert_eq!(
+            Rules::compile(&rules).evaluate(&playback, &g),
+            vec![Connection {
+                output: 3,
+                input: 1
+            }]
+        );
+    }
+}
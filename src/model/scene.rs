@@ -0,0 +1,184 @@
+//! Capture and recall routing scenes
+//!
+//! Scenes store connections by port name, since port IDs change
+//! whenever a client restarts.
+
+use crate::model::graph::{self, Connection, Graph};
+use crate::settings::Scene;
+use std::collections::BTreeSet;
+
+/// The changes needed to turn the live graph into a scene
+#[derive(Debug, Default, PartialEq)]
+pub struct SceneDiff {
+    /// Connections the scene has, but the graph doesn't
+    pub connect: Vec<Connection>,
+    /// Connections the graph has, but the scene doesn't
+    pub disconnect: Vec<Connection>,
+    /// Scene connections whose ports don't currently exist
+    pub missing: Vec<(String, String)>,
+}
+
+/// Take a snapshot of every connection in the graph
+pub fn capture(graph: &Graph) -> Scene {
+    let connections = graph
+        .connections()
+        .filter_map(|c| {
+            let output = graph.port(c.output)?.full_name();
+            let input = graph.port(c.input)?.full_name();
+            Some((output, input))
+        })
+        .collect();
+
+    Scene { connections }
+}
+
+/// Compute the changes needed to recall a scene
+pub fn diff(scene: &Scene, graph: &Graph) -> SceneDiff {
+    let mut diff = SceneDiff::default();
+    let mut wanted = vec![];
+
+    for (output, input) in scene.connections.iter() {
+        match (graph.port_by_name(output), graph.port_by_name(input)) {
+            (Some(o), Some(i)) => {
+                let c = Connection {
+                    output: o.id,
+                    input: i.id,
+                };
+                wanted.push(c);
+                if !graph.is_connected(o.id, i.id) {
+                    diff.connect.push(c);
+                }
+            }
+            _ => diff.missing.push((output.clone(), input.clone())),
+        }
+    }
+
+    diff.disconnect = graph
+        .connections()
+        .filter(|c| !wanted.contains(c))
+        .cloned()
+        .collect();
+
+    diff
+}
+
+/// All clients that have a port in the scene
+pub fn clients(scene: &Scene) -> BTreeSet<&str> {
+    scene
+        .connections
+        .iter()
+        .flat_map(|(output, input)| vec![output, input])
+        .filter_map(|name| graph::split_name(name))
+        .map(|(client, _)| client)
+        .collect()
+}
+
+/// Check that every scene port of the given clients is in the graph
+pub fn clients_ready(scene: &Scene, graph: &Graph, clients: &BTreeSet<String>) -> bool {
+    scene
+        .connections
+        .iter()
+        .flat_map(|(output, input)| vec![output, input])
+        .filter(|name| match graph::split_name(name) {
+            Some((client, _)) => clients.contains(client),
+            None => false,
+        })
+        .all(|name| graph.port_by_name(name).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::port::{Port, PortDirection, PortType};
+
+    fn graph() -> Graph {
+        let mut g = Graph::default();
+        for (id, client, port, dir) in vec![
+            (1, "synth", "out_l", PortDirection::Output),
+            (2, "synth", "out_r", PortDirection::Output),
+            (3, "system", "playback_1", PortDirection::Input),
+            (4, "system", "playback_2", PortDirection::Input),
+        ] {
+            g.add_port(Port::new(
+                client.into(),
+                port.into(),
+                id,
+                PortType::Audio,
+                dir,
+                false,
+            ));
+        }
+        g
+    }
+
+    fn pair(a: &str, b: &str) -> (String, String) {
+        (a.into(), b.into())
+    }
+
+    #[test]
+    fn capture_uses_port_names() {
+        let mut g = graph();
+        g.add_connection(3, 1);
+        let scene = capture(&g);
+        assert_eq!(
+            scene.connections.into_iter().collect::<Vec<_>>(),
+            vec![pair("synth:out_l", "system:playback_1")]
+        );
+    }
+
+    #[test]
+    fn diff_against_live_graph() {
+        let mut g = graph();
+        g.add_connection(3, 1);
+        g.add_connection(4, 1);
+
+        let mut scene = Scene::default();
+        scene
+            .connections
+            .insert(pair("synth:out_l", "system:playback_1"));
+        scene
+            .connections
+            .insert(pair("synth:out_r", "system:playback_2"));
+        scene
+            .connections
+            .insert(pair("reverb:out", "system:playback_1"));
+
+        let d = diff(&scene, &g);
+        assert_eq!(
+            d.connect,
+            vec![Connection {
+                output: 2,
+                input: 4
+            }]
+        );
+        assert_eq!(
+            d.disconnect,
+            vec![Connection {
+                output: 1,
+                input: 4
+            }]
+        );
+        assert_eq!(d.missing, vec![pair("reverb:out", "system:playback_1")]);
+    }
+
+    #[test]
+    fn wait_for_client_ports() {
+        let g = graph();
+        let mut scene = Scene::default();
+        scene
+            .connections
+            .insert(pair("synth:out_l", "system:playback_1"));
+        scene
+            .connections
+            .insert(pair("reverb:out", "system:playback_2"));
+
+        assert_eq!(
+            clients(&scene).into_iter().collect::<Vec<_>>(),
+            vec!["reverb", "synth", "system"]
+        );
+
+        let waiting = |c: &[&str]| c.iter().map(|c| c.to_string()).collect();
+        assert!(clients_ready(&scene, &g, &waiting(&["synth"])));
+        assert!(!clients_ready(&scene, &g, &waiting(&["synth", "reverb"])));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::settings::jack::JackSettings;
+use crate::settings::{jack::JackSettings, rules::Rule, SettingsFormat};
 use serde::{Deserialize, Serialize};
 
 /// jackctl application settings tree
@@ -14,6 +14,12 @@ pub struct AppSettings {
     /// Specify the order of inputs and outputs
     #[serde(rename = "input_direction")]
     pub io_order: IoOrder,
+    /// Rules to connect ports when they register
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Convert the settings files to this format on the next sync
+    #[serde(default)]
+    pub settings_format: Option<SettingsFormat>,
 }
 
 impl Default for AppSettings {
@@ -22,6 +28,8 @@ impl Default for AppSettings {
             ui_launch_mode: UiLaunchMode::Wizard,
             jack: JackSettings::default(),
             io_order: IoOrder::VerticalInputs,
+            rules: vec![],
+            settings_format: None,
         }
     }
 }
@@ -1,16 +1,39 @@
-use crate::{model::card::CardUsage, settings::Id};
+use crate::model::card::CardUsage;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
 /// Remember audio devices previously configured with jackctl
 ///
 ///
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CardSettings {
     /// Store all known card settings
     known: BTreeMap<String, SoundCard>,
-    /// Identify a "default" sound card
-    default: Id,
+    /// The card jackd drives directly, instead of the dummy driver
+    #[serde(default)]
+    default: Option<String>,
+    /// Activate unknown cards when there is no user to ask
+    #[serde(default)]
+    unattended_use: bool,
+    /// Priority when reserving cards from other audio servers
+    #[serde(default = "default_reserve_priority")]
+    reserve_priority: i32,
+}
+
+/// Higher than PulseAudio and PipeWire, which use 0
+fn default_reserve_priority() -> i32 {
+    10
+}
+
+impl Default for CardSettings {
+    fn default() -> Self {
+        Self {
+            known: BTreeMap::new(),
+            default: None,
+            unattended_use: false,
+            reserve_priority: default_reserve_priority(),
+        }
+    }
 }
 
 impl CardSettings {
@@ -18,19 +41,30 @@ impl CardSettings {
         match self.known.get_mut(name) {
             Some(entry) => entry._use = _use,
             None => {
-                self.known.insert(
-                    name.clone(),
-                    SoundCard {
-                        name: name.clone(),
-                        _use,
-                    },
-                );
+                let mut card = SoundCard::new(name.clone());
+                card._use = _use;
+                self.known.insert(name.clone(), card);
             }
         }
 
         trace!("{:?}", self.known);
     }
 
+    /// Get the stored settings of a card
+    pub fn card(&self, name: &str) -> Option<&SoundCard> {
+        self.known.get(name)
+    }
+
+    /// Iterate over all remembered cards
+    pub fn cards(&self) -> impl Iterator<Item = &SoundCard> {
+        self.known.values()
+    }
+
+    /// Store the settings of a card, replacing previous ones
+    pub fn update_card(&mut self, card: SoundCard) {
+        self.known.insert(card.name.clone(), card);
+    }
+
     pub fn use_card(&self, name: &String) -> CardUsage {
         match self.known.get(name) {
             Some(card) if card._use => CardUsage::Yes,
@@ -38,11 +72,68 @@ impl CardSettings {
             None => CardUsage::AskUser,
         }
     }
+
+    /// Name of the card jackd should run on, if any
+    pub fn master(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    /// Choose the card jackd runs on, or `None` for the dummy driver
+    pub fn set_master(&mut self, name: Option<String>) {
+        self.default = name;
+    }
+
+    /// Whether unknown cards should be used when running headless
+    pub fn unattended_usage(&self) -> bool {
+        self.unattended_use
+    }
+
+    /// Priority used for `org.freedesktop.ReserveDevice1` requests
+    pub fn reserve_priority(&self) -> i32 {
+        self.reserve_priority
+    }
 }
 
 /// Encoding information about a single sound card
-#[derive(Debug, Serialize, Deserialize)]
-struct SoundCard {
+///
+/// Audio settings that are `None` are picked automatically: the best
+/// rate and all channels of the card, and the jack server settings
+/// for periods and resample quality.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SoundCard {
     pub name: String,
     pub _use: bool,
+    /// Sample rate to run the card at
+    #[serde(default)]
+    pub rate: Option<u32>,
+    /// Number of capture channels to open
+    #[serde(default)]
+    pub in_channels: Option<u32>,
+    /// Number of playback channels to open
+    #[serde(default)]
+    pub out_channels: Option<u32>,
+    /// Periods of latency in the card's buffer
+    #[serde(default)]
+    pub n_periods: Option<u32>,
+    /// Quality at which to resample to the server rate
+    #[serde(default)]
+    pub resample_q: Option<u32>,
+    /// Name of the card's client in jack, instead of the card name
+    #[serde(default)]
+    pub client_name: Option<String>,
+}
+
+impl SoundCard {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            _use: false,
+            rate: None,
+            in_channels: None,
+            out_channels: None,
+            n_periods: None,
+            resample_q: None,
+            client_name: None,
+        }
+    }
 }
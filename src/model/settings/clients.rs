@@ -1,6 +1,9 @@
 use crate::settings::Id;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
 
 /// Store Jack client settings
 ///
@@ -9,9 +12,101 @@ use std::collections::BTreeMap;
 pub struct ClientSettings {
     /// A set of clients previously configured
     pub clients: BTreeMap<Id, Client>,
+    /// Connections to restore when their ports come back, by port name
+    #[serde(default)]
+    pub connections: BTreeSet<(String, String)>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl ClientSettings {
+    /// Find a client by its JACK client name
+    pub fn find(&self, name: &str) -> Option<&Client> {
+        self.clients.values().find(|c| c.name == name)
+    }
+
+    /// Add a client, or replace the one with the same name
+    pub fn update(&mut self, client: Client) {
+        let id = self
+            .clients
+            .iter()
+            .find(|(_, c)| c.name == client.name)
+            .map(|(id, _)| *id)
+            .unwrap_or_else(|| self.clients.keys().last().map(|id| id + 1).unwrap_or(0));
+        self.clients.insert(id, client);
+    }
+
+    /// Check if remembered connections should be restored for a client
+    ///
+    /// Clients we don't have settings for (like cards) are reconnected.
+    pub fn reconnects(&self, name: &str) -> bool {
+        self.find(name).map(|c| c.reconnect).unwrap_or(true)
+    }
+
+    /// Remember a connection, returns `true` if it wasn't known yet
+    pub fn remember(&mut self, output: String, input: String) -> bool {
+        self.connections.insert((output, input))
+    }
+
+    /// Forget a connection, returns `true` if it was known
+    pub fn forget(&mut self, output: String, input: String) -> bool {
+        self.connections.remove(&(output, input))
+    }
+
+    /// All remembered connections of a port
+    pub fn remembered<'s>(&'s self, port: &'s str) -> impl Iterator<Item = &'s (String, String)> {
+        self.connections
+            .iter()
+            .filter(move |(output, input)| output == port || input == port)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Client {
+    /// The JACK client name
     pub name: String,
+    /// Program and arguments used to launch this client
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Directory the program is launched in
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+    /// Extra environment variables for the program
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Launch the program again when it crashes
+    #[serde(default)]
+    pub respawn: bool,
+    /// Restore remembered connections when the client's ports appear
+    #[serde(default = "default_reconnect")]
+    pub reconnect: bool,
+}
+
+fn default_reconnect() -> bool {
+    true
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            command: vec![],
+            working_dir: None,
+            env: BTreeMap::new(),
+            respawn: false,
+            reconnect: default_reconnect(),
+        }
+    }
+}
+
+impl Client {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    /// Whether we know how to launch this client
+    pub fn launchable(&self) -> bool {
+        !self.command.is_empty()
+    }
 }
@@ -0,0 +1,138 @@
+use crate::error::SettingsError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+/// File format of the settings tree on disk
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SettingsFormat {
+    Json,
+    Toml,
+}
+
+impl Default for SettingsFormat {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
+impl SettingsFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Toml => "toml",
+        }
+    }
+
+    /// Pick the format of the settings already stored in `dir`
+    pub fn detect(dir: &Path) -> Self {
+        match dir.join("app.toml").exists() {
+            true => Self::Toml,
+            false => Self::Json,
+        }
+    }
+
+    pub fn to_string<T: Serialize>(self, t: &T) -> Result<String, SettingsError> {
+        Ok(match self {
+            Self::Json => serde_json::to_string_pretty(t)?,
+            Self::Toml => toml::to_string_pretty(&to_toml(t)?)?,
+        })
+    }
+
+    pub fn from_str<T: DeserializeOwned>(self, s: &str) -> Result<T, SettingsError> {
+        match self {
+            Self::Json => Ok(serde_json::from_str(s)?),
+            Self::Toml => from_toml(toml::from_str(s)?),
+        }
+    }
+}
+
+/// Convert a settings tree to a TOML value
+///
+/// TOML has no `null` and only string keys, so the tree is converted
+/// to JSON first: `None` fields are dropped and number keys (like
+/// client ids) become strings.
+pub fn to_toml<T: Serialize>(t: &T) -> Result<toml::Value, SettingsError> {
+    let mut json = serde_json::to_value(t)?;
+    strip_nulls(&mut json);
+    Ok(toml::Value::try_from(json)?)
+}
+
+/// Convert a TOML value back into a settings tree
+pub fn from_toml<T: DeserializeOwned>(v: toml::Value) -> Result<T, SettingsError> {
+    Ok(serde_json::from_value(serde_json::to_value(v)?)?)
+}
+
+fn strip_nulls(v: &mut Value) {
+    match v {
+        Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(vec) => vec.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// Put a comment above the first table of every top-level section
+pub fn comment_sections(doc: &str, comments: &[(&str, &str)]) -> String {
+    let mut out = String::new();
+    let mut pending: Vec<_> = comments.to_vec();
+
+    for line in doc.lines() {
+        let table = line.trim_start_matches('[');
+        let found = pending.iter().position(|(name, _)| {
+            line.starts_with('[')
+                && table.starts_with(name)
+                && matches!(table[name.len()..].chars().next(), Some('.') | Some(']'))
+        });
+        if let Some(idx) = found {
+            let (_, comment) = pending.remove(idx);
+            if !out.is_empty() && !out.ends_with("\n\n") {
+                out.push('\n');
+            }
+            comment
+                .lines()
+                .for_each(|l| out.push_str(&format!("# {}\n", l)));
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Tree {
+        name: Option<String>,
+        #[serde(default)]
+        by_id: BTreeMap<u64, String>,
+        pairs: Vec<(String, String)>,
+    }
+
+    #[test]
+    fn toml_roundtrip() {
+        let mut tree = Tree::default();
+        tree.by_id.insert(7, "synth".into());
+        tree.pairs.push(("a:out".into(), "b:in".into()));
+
+        let s = SettingsFormat::Toml.to_string(&tree).unwrap();
+        assert!(!s.contains("name"));
+        assert!(s.contains("[by_id]"));
+        assert_eq!(SettingsFormat::Toml.from_str::<Tree>(&s).unwrap(), tree);
+    }
+
+    #[test]
+    fn sections_are_commented() {
+        let doc = "[app]\nmode = \"Open\"\n\n[app.jack]\nrate = 1\n\n[[apps]]\nx = 1\n";
+        let out = comment_sections(doc, &[("app", "Application\nbehaviour")]);
+        assert!(out.starts_with("# Application\n# behaviour\n[app]\n"));
+        assert_eq!(out.matches('#').count(), 2);
+    }
+}
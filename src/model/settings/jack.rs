@@ -3,8 +3,6 @@ use serde::{Deserialize, Serialize};
 /// Jack server settings
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JackSettings {
-    /// Keep track of the version of this file
-    version: u8,
     /// Specify how the jack server is launched
     pub spawn_mode: SpawnMode,
     /// How should the jack server behave
@@ -25,7 +23,6 @@ impl Default for JackSettings {
     fn default() -> Self {
         // very controvertial default values
         Self {
-            version: 1,
             spawn_mode: SpawnMode::SoftSpawn,
             run_mode: RunMode::Uninitialized,
             realtime: false,
@@ -37,7 +34,7 @@ impl Default for JackSettings {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum SpawnMode {
     /// Wait for jack to be spawned
     Wait,
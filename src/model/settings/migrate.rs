@@ -0,0 +1,221 @@
+//! Versioned layouts of the settings files
+//!
+//! Every settings file stores a top-level `version`.  Files written by
+//! older versions of jackctl are upgraded one step at a time before
+//! they are deserialised, so that changing a settings struct doesn't
+//! throw the user's settings away.  Steps work on the generic JSON
+//! tree, which means they apply to TOML files as well.
+//!
+//! To change a layout, add a step to the end of the file's schema.
+
+use serde_json::{json, Map, Value};
+
+/// Upgrade a tree by one version, describing every change made
+type Step = fn(&mut Map<String, Value>, &mut Vec<String>);
+
+/// The layout history of one settings file
+pub struct Schema {
+    pub file: &'static str,
+    /// `steps[0]` upgrades version 1 to 2, and so on
+    steps: &'static [Step],
+}
+
+pub const APP: Schema = Schema {
+    file: "app",
+    steps: &[app_v2],
+};
+
+pub const CARDS: Schema = Schema {
+    file: "cards",
+    steps: &[cards_v2, cards_v3, cards_v4],
+};
+
+pub const CLIENTS: Schema = Schema {
+    file: "clients",
+    steps: &[clients_v2],
+};
+
+pub const SCENES: Schema = Schema {
+    file: "scenes",
+    steps: &[],
+};
+
+impl Schema {
+    /// The version written by this build of jackctl
+    pub fn current(&self) -> u64 {
+        self.steps.len() as u64 + 1
+    }
+
+    /// Upgrade `tree` to the current version
+    ///
+    /// Returns the list of changes, which is empty when the tree was
+    /// already up to date.  Trees from a newer jackctl are rejected.
+    pub fn migrate(&self, tree: &mut Value) -> Result<Vec<String>, String> {
+        let from = version(tree);
+        let map = match tree {
+            Value::Object(map) => map,
+            _ => return Err(format!("{} settings are not a table", self.file)),
+        };
+
+        if from > self.current() {
+            return Err(format!(
+                "{} settings are version {}, but this jackctl only knows up to version {}",
+                self.file,
+                from,
+                self.current()
+            ));
+        }
+
+        let mut changes = vec![];
+        for (to, step) in (from + 1..).zip(&self.steps[from as usize - 1..]) {
+            let mut step_changes = vec![];
+            step(map, &mut step_changes);
+            changes.extend(
+                step_changes
+                    .into_iter()
+                    .map(|c| format!("version {}: {}", to, c)),
+            );
+            map.insert("version".into(), json!(to));
+        }
+
+        Ok(changes)
+    }
+
+    /// Record the current version in a tree that is about to be written
+    pub fn stamp(&self, tree: &mut Value) {
+        if let Value::Object(map) = tree {
+            map.insert("version".into(), json!(self.current()));
+        }
+    }
+}
+
+/// The version of a stored tree
+///
+/// Before settings were versioned only the jack settings had a
+/// version, so anything without one is version 1.
+pub fn version(tree: &Value) -> u64 {
+    tree.get("version")
+        .or_else(|| tree.get("jack").and_then(|jack| jack.get("version")))
+        .and_then(Value::as_u64)
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// Insert `key` with a default value unless it's already there
+fn add_default(map: &mut Map<String, Value>, key: &str, value: Value, changes: &mut Vec<String>) {
+    if !map.contains_key(key) {
+        changes.push(format!("added `{}` = {}", key, value));
+        map.insert(key.into(), value);
+    }
+}
+
+/// Move the version out of the jack settings, add auto-connect rules
+fn app_v2(app: &mut Map<String, Value>, changes: &mut Vec<String>) {
+    if let Some(Value::Object(jack)) = app.get_mut("jack") {
+        if jack.remove("version").is_some() {
+            changes.push("moved `jack.version` to `version`".into());
+        }
+    }
+    add_default(app, "rules", json!([]), changes);
+}
+
+/// Add unattended card usage and the device reservation priority
+fn cards_v2(cards: &mut Map<String, Value>, changes: &mut Vec<String>) {
+    add_default(cards, "unattended_use", json!(false), changes);
+    add_default(cards, "reserve_priority", json!(10), changes);
+}
+
+/// Add per-card audio settings, picked automatically until they're set
+fn cards_v3(cards: &mut Map<String, Value>, changes: &mut Vec<String>) {
+    if let Some(Value::Object(known)) = cards.get_mut("known") {
+        for (name, card) in known.iter_mut() {
+            if let Value::Object(card) = card {
+                let mut added = vec![];
+                for key in &[
+                    "rate",
+                    "in_channels",
+                    "out_channels",
+                    "n_periods",
+                    "resample_q",
+                    "client_name",
+                ] {
+                    add_default(card, key, Value::Null, &mut added);
+                }
+                changes.extend(added.into_iter().map(|c| format!("card {}: {}", name, c)));
+            }
+        }
+    }
+}
+
+/// Identify the default card by name, the old numeric id was never used
+fn cards_v4(cards: &mut Map<String, Value>, changes: &mut Vec<String>) {
+    if let Some(id) = cards
+        .get("default")
+        .filter(|d| !d.is_string() && !d.is_null())
+    {
+        changes.push(format!("unset the unused default card id {}", id));
+        cards.insert("default".into(), Value::Null);
+    }
+}
+
+/// Add remembered connections and per-client reconnection
+fn clients_v2(clients: &mut Map<String, Value>, changes: &mut Vec<String>) {
+    add_default(clients, "connections", json!([]), changes);
+    if let Some(Value::Object(known)) = clients.get_mut("clients") {
+        for (id, client) in known.iter_mut() {
+            if let Value::Object(client) = client {
+                let mut added = vec![];
+                add_default(client, "reconnect", json!(true), &mut added);
+                changes.extend(added.into_iter().map(|c| format!("client {}: {}", id, c)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_unversioned_app() {
+        let mut tree = json!({
+            "ui_launch_mode": "Open",
+            "jack": { "version": 1, "realtime": true },
+        });
+        let changes = APP.migrate(&mut tree).unwrap();
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(tree["version"], APP.current());
+        assert_eq!(tree["rules"], json!([]));
+        assert!(tree["jack"].get("version").is_none());
+        assert!(APP.migrate(&mut tree).unwrap().is_empty());
+    }
+
+    #[test]
+    fn upgrade_clients() {
+        let mut tree = json!({ "clients": { "0": { "name": "synth" } } });
+        let changes = CLIENTS.migrate(&mut tree).unwrap();
+        assert_eq!(tree["clients"]["0"]["reconnect"], true);
+        assert!(changes.iter().any(|c| c.contains("client 0")));
+    }
+
+    #[test]
+    fn upgrade_cards() {
+        let mut tree = json!({ "known": { "USB Audio": { "name": "USB Audio", "_use": true } } });
+        CARDS.migrate(&mut tree).unwrap();
+        assert_eq!(tree["version"], CARDS.current());
+        assert_eq!(tree["known"]["USB Audio"]["rate"], Value::Null);
+        assert_eq!(tree["reserve_priority"], 10);
+
+        let mut tree = json!({ "version": 3, "default": 0 });
+        assert_eq!(CARDS.migrate(&mut tree).unwrap().len(), 1);
+        assert_eq!(tree["default"], Value::Null);
+    }
+
+    #[test]
+    fn reject_newer_versions() {
+        let mut tree = json!({ "version": CARDS.current() + 1 });
+        assert!(CARDS.migrate(&mut tree).is_err());
+        assert!(CARDS.migrate(&mut json!([])).is_err());
+    }
+}
@@ -5,13 +5,20 @@
 //! - GUI behaviour changes
 //! - Modify jack behaviour
 //! - Define run mode (pa-bridge mode, jack-service spawning, force-spawn, etc)
+//! - Auto-connection rules, matching port names by regex
 //!
 //! ## Jack client storage
 //!
 //! - Remember bitwig settings (for example)
+//! - Remember how to launch clients, and re-spawn them
 //! - Patchbay persistance
 //! - Toggle on/off via app/user settings
 //!
+//! ## Scene storage
+//!
+//! - Save named snapshots of the connection graph
+//! - Recall them later by name
+//!
 //! ## Sound card storage
 //!
 //! - Remember which audio devices have been configured before
@@ -35,27 +42,59 @@
 //!
 //! After applying changes to the settings, don't forget to call
 //! [`sync()`](Settings::sync)!
+//!
+//! ## File format
+//!
+//! Settings are stored as JSON, or as TOML when an `app.toml` exists.
+//! Setting `settings_format` in the app settings converts the whole
+//! tree on the next sync.  The tree can also be exported into (and
+//! imported from) a single commented TOML file.
+//!
+//! ## Crash safety
+//!
+//! Files are written to a temporary file first and renamed into
+//! place, so they are never left half-written.  The previous contents
+//! of a changed file are kept as `<file>.bak.1` to `<file>.bak.3`.  A
+//! file that can't be loaded at startup is moved aside to
+//! `<file>.broken.1`, shifting earlier broken files down the same
+//! way, and the newest backup that still loads is used instead.
 
 mod app;
 pub use app::{IoOrder, UiLaunchMode};
 
 mod cards;
+pub use cards::SoundCard;
 mod clients;
+pub use clients::{Client, ClientSettings};
+mod format;
+pub use format::SettingsFormat;
 mod jack;
+pub use self::jack::SpawnMode;
+mod migrate;
+mod rules;
+pub use rules::{Rule, RuleDirection, RulePortType};
+mod scenes;
+pub use scenes::Scene;
 
 use crate::error::SettingsError;
 use directories::ProjectDirs;
-use serde::de::DeserializeOwned;
+use migrate::Schema;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
 use std::{
-    fs::{self, File, OpenOptions},
-    io::{Read, Write},
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
     path::{Path, PathBuf},
-    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard},
 };
 
 /// Use a simple u64 to identify audio devices in the future
 pub type Id = u64;
 
+/// Number of previous versions kept for every settings file
+const BACKUPS: usize = 3;
+
 /// Create the required directories
 pub fn scaffold() -> ProjectDirs {
     let dir = ProjectDirs::from("tech", "sigsegv", "jackctl").unwrap();
@@ -67,21 +106,61 @@ pub fn scaffold() -> ProjectDirs {
 #[derive(Default, Debug)]
 pub struct Settings {
     base: PathBuf,
+    /// Format of the files currently on disk
+    format: RwLock<SettingsFormat>,
     app: RwLock<app::AppSettings>,
     clients: RwLock<clients::ClientSettings>,
     cards: RwLock<cards::CardSettings>,
+    scenes: RwLock<scenes::SceneSettings>,
+    /// Contents last written to (or reloaded from) every file
+    known: Mutex<BTreeMap<String, String>>,
+    /// Files with invalid edits, which are not overwritten
+    conflicts: Mutex<BTreeSet<String>>,
+    /// Files that couldn't be loaded at startup
+    recovered: Mutex<Vec<Recovery>>,
+}
+
+/// A settings file that was replaced because it couldn't be loaded
+#[derive(Clone, Debug)]
+pub struct Recovery {
+    pub file: String,
+    pub error: String,
+    /// The backup that was used instead, if any of them could be loaded
+    pub restored: Option<String>,
+}
+
+/// One of the settings files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Section {
+    App,
+    Cards,
+    Clients,
+    Scenes,
 }
 
 impl Settings {
     /// Create a new settings tree from a config path
     pub fn init<'p>(path: impl Into<&'p Path>) -> Result<Arc<Settings>, SettingsError> {
         let base = path.into().to_path_buf();
+        let format = SettingsFormat::detect(&base);
+        let file = |name: &str| base.join(format!("{}.{}", name, format.extension()));
+        let mut recovered = vec![];
+
+        let app = load_path(file("app"), format, &migrate::APP, &mut recovered);
+        let clients = load_path(file("clients"), format, &migrate::CLIENTS, &mut recovered);
+        let cards = load_path(file("cards"), format, &migrate::CARDS, &mut recovered);
+        let scenes = load_path(file("scenes"), format, &migrate::SCENES, &mut recovered);
 
         let this = Arc::new(Self {
-            app: RwLock::new(load_path(base.join("app.json"))),
-            clients: RwLock::new(load_path(base.join("clients.json"))),
-            cards: RwLock::new(load_path(base.join("cards.json"))),
+            format: RwLock::new(format),
+            app: RwLock::new(app),
+            clients: RwLock::new(clients),
+            cards: RwLock::new(cards),
+            scenes: RwLock::new(scenes),
             base,
+            known: Default::default(),
+            conflicts: Default::default(),
+            recovered: Mutex::new(recovered),
         });
         this.sync()?;
         Ok(this)
@@ -89,23 +168,173 @@ impl Settings {
 
     /// Sync any changes back to disk
     pub fn sync(self: &Arc<Self>) -> Result<(), SettingsError> {
+        let old = *self.format.read().unwrap();
+        let format = self.r().app().settings_format.unwrap_or(old);
+
         vec![
-            ("app.json", serde_json::to_string_pretty(&self.app)?),
-            ("clients.json", serde_json::to_string_pretty(&self.clients)?),
-            ("cards.json", serde_json::to_string_pretty(&self.cards)?),
+            (
+                "app",
+                format.to_string(&versioned(&self.app, &migrate::APP)?)?,
+            ),
+            (
+                "clients",
+                format.to_string(&versioned(&self.clients, &migrate::CLIENTS)?)?,
+            ),
+            (
+                "cards",
+                format.to_string(&versioned(&self.cards, &migrate::CARDS)?)?,
+            ),
+            (
+                "scenes",
+                format.to_string(&versioned(&self.scenes, &migrate::SCENES)?)?,
+            ),
         ]
         .into_iter()
-        .map(|(path, json)| {
-            OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(self.base.join(path))
-                .and_then(|mut f| f.write_all(json.as_bytes()))
+        .map(|(name, data)| {
+            let file = format!("{}.{}", name, format.extension());
+            if self.conflicts.lock().unwrap().contains(&file) {
+                warn!("Not overwriting {} until the errors in it are fixed", file);
+                return Ok(());
+            }
+
+            // Remember the contents first, to ignore our own change
+            self.known
+                .lock()
+                .unwrap()
+                .insert(file.clone(), data.clone());
+            let path = self.base.join(&file);
+            rotate_backups(&path, format, &data)
+                .and_then(|_| write_atomic(&path, &data))
                 .map_err(Into::into)
         })
-        .collect::<Result<Vec<_>, SettingsError>>()
-        .map(|_| ())
+        .collect::<Result<Vec<_>, SettingsError>>()?;
+
+        // Remove the old files so that the format is detected correctly
+        if format != old {
+            info!("Converted settings from {:?} to {:?}", old, format);
+            for name in SECTIONS.iter().map(|(name, _)| name) {
+                let file = format!("{}.{}", name, old.extension());
+                if !self.conflicts.lock().unwrap().contains(&file) {
+                    let _ = fs::remove_file(self.base.join(file));
+                }
+            }
+            *self.format.write().unwrap() = format;
+        }
+
+        Ok(())
+    }
+
+    /// Reload a file in the settings directory that changed on disk
+    ///
+    /// Returns the section that was reloaded, or `None` for unrelated
+    /// files and our own writes.  A file that can't be loaded leaves
+    /// the settings alone, and isn't overwritten until it's fixed.
+    pub fn reload(self: &Arc<Self>, file: &str) -> Result<Option<Section>, SettingsError> {
+        let format = *self.format.read().unwrap();
+        let section = match file.strip_suffix(&format!(".{}", format.extension())) {
+            Some("app") => Section::App,
+            Some("cards") => Section::Cards,
+            Some("clients") => Section::Clients,
+            Some("scenes") => Section::Scenes,
+            _ => return Ok(None),
+        };
+
+        let data = match fs::read_to_string(self.base.join(file)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if self.known.lock().unwrap().get(file) == Some(&data) {
+            return Ok(None);
+        }
+
+        let loaded = match section {
+            Section::App => parse(&data, format, &migrate::APP, file)
+                .map(|app| *self.app.write().unwrap() = app),
+            Section::Cards => parse(&data, format, &migrate::CARDS, file)
+                .map(|cards| *self.cards.write().unwrap() = cards),
+            Section::Clients => parse(&data, format, &migrate::CLIENTS, file)
+                .map(|clients| *self.clients.write().unwrap() = clients),
+            Section::Scenes => parse(&data, format, &migrate::SCENES, file)
+                .map(|scenes| *self.scenes.write().unwrap() = scenes),
+        };
+        if let Err(e) = loaded {
+            self.conflicts.lock().unwrap().insert(file.into());
+            return Err(e);
+        }
+
+        info!("Reloaded {} after it was changed on disk", file);
+        self.conflicts.lock().unwrap().remove(file);
+        self.known.lock().unwrap().insert(file.into(), data);
+        Ok(Some(section))
+    }
+
+    /// The directory settings are stored in
+    pub fn dir(&self) -> &Path {
+        &self.base
+    }
+
+    /// Take the files that had to be recovered at startup
+    pub fn take_recovered(&self) -> Vec<Recovery> {
+        std::mem::take(&mut *self.recovered.lock().unwrap())
+    }
+
+    /// Export the whole settings tree as one commented TOML document
+    pub fn export(self: &Arc<Self>) -> Result<String, SettingsError> {
+        let mut bundle = toml::value::Table::new();
+        let mut add = |schema: &Schema, tree: Value| -> Result<(), SettingsError> {
+            bundle.insert(schema.file.into(), format::to_toml(&tree)?);
+            Ok(())
+        };
+        add(&migrate::APP, versioned(&self.app, &migrate::APP)?)?;
+        add(&migrate::CARDS, versioned(&self.cards, &migrate::CARDS)?)?;
+        add(
+            &migrate::CLIENTS,
+            versioned(&self.clients, &migrate::CLIENTS)?,
+        )?;
+        add(&migrate::SCENES, versioned(&self.scenes, &migrate::SCENES)?)?;
+
+        let doc = toml::to_string_pretty(&toml::Value::Table(bundle))?;
+        Ok(format!(
+            "# jackctl configuration\n#\n# Load it with `jackctl import <file>`\n\n{}",
+            format::comment_sections(&doc, &SECTIONS)
+        ))
+    }
+
+    /// Replace settings with the sections of an exported TOML document
+    ///
+    /// Sections missing from the document are left alone.  Nothing is
+    /// replaced if any section is invalid.
+    pub fn import(self: &Arc<Self>, doc: &str) -> Result<(), SettingsError> {
+        let mut bundle: toml::value::Table = toml::from_str(doc)?;
+        let mut section = |name: &str| bundle.remove(name);
+
+        let app: Option<app::AppSettings> = section("app")
+            .map(|v| upgrade(format::from_toml(v)?, &migrate::APP, "import"))
+            .transpose()?;
+        let cards: Option<cards::CardSettings> = section("cards")
+            .map(|v| upgrade(format::from_toml(v)?, &migrate::CARDS, "import"))
+            .transpose()?;
+        let clients: Option<clients::ClientSettings> = section("clients")
+            .map(|v| upgrade(format::from_toml(v)?, &migrate::CLIENTS, "import"))
+            .transpose()?;
+        let scenes: Option<scenes::SceneSettings> = section("scenes")
+            .map(|v| upgrade(format::from_toml(v)?, &migrate::SCENES, "import"))
+            .transpose()?;
+
+        if let Some(app) = app {
+            *self.app.write().unwrap() = app;
+        }
+        if let Some(cards) = cards {
+            *self.cards.write().unwrap() = cards;
+        }
+        if let Some(clients) = clients {
+            *self.clients.write().unwrap() = clients;
+        }
+        if let Some(scenes) = scenes {
+            *self.scenes.write().unwrap() = scenes;
+        }
+        self.sync()
     }
 
     /// Get read access to any stored setting
@@ -123,14 +352,204 @@ impl Settings {
     }
 }
 
-fn load_path<T: Default + DeserializeOwned>(path: PathBuf) -> T {
-    File::open(path)
-        .and_then(|mut f| {
-            let mut c = String::new();
-            f.read_to_string(&mut c).map(|_| c)
-        })
-        .and_then(|s| serde_json::from_str(&s).map_err(Into::into))
-        .unwrap_or_else(|_| T::default())
+/// Settings files and what they contain
+const SECTIONS: [(&str, &str); 4] = [
+    (
+        "app",
+        "Application behaviour, jack server settings and auto-connection rules",
+    ),
+    ("cards", "Sound cards seen before, and whether to use them"),
+    (
+        "clients",
+        "Programs to launch and re-spawn, and remembered connections",
+    ),
+    ("scenes", "Saved routing scenes"),
+];
+
+/// Load a settings file, upgrading it from older versions
+///
+/// A missing file gives the default settings.  A file that can't be
+/// loaded is moved out of the way and replaced by the newest backup
+/// that still loads, or the defaults if there is none.  Either way
+/// it's added to `recovered`, so the user can be told about it.
+fn load_path<T: Default + DeserializeOwned>(
+    path: PathBuf,
+    format: SettingsFormat,
+    schema: &Schema,
+    recovered: &mut Vec<Recovery>,
+) -> T {
+    let error = match load(&path, format, schema) {
+        Ok(Some(t)) => return t,
+        Ok(None) => return T::default(),
+        Err(e) => e,
+    };
+
+    error!("Can't load {}: {}", path.display(), error);
+    match quarantine(&path, format) {
+        Ok(broken) => warn!("Moved the unreadable file to {}", broken.display()),
+        Err(e) => error!("Can't move {} out of the way: {}", path.display(), e),
+    }
+
+    let file_name = |p: &Path| p.file_name().unwrap().to_string_lossy().into_owned();
+    let mut recovery = Recovery {
+        file: file_name(&path),
+        error: error.to_string(),
+        restored: None,
+    };
+
+    let restored = (1..=BACKUPS)
+        .map(|n| backup_path(&path, format, n))
+        .find_map(|backup| match load(&backup, format, schema) {
+            Ok(Some(t)) => Some((backup, t)),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Can't use backup {}: {}", backup.display(), e);
+                None
+            }
+        });
+
+    let t = match restored {
+        Some((backup, t)) => {
+            warn!("Restored {} from {}", path.display(), backup.display());
+            recovery.restored = Some(file_name(&backup));
+            t
+        }
+        None => {
+            warn!("No usable backup of {}, using defaults", path.display());
+            T::default()
+        }
+    };
+
+    recovered.push(recovery);
+    t
+}
+
+/// The path of the `n`th most recent backup of a settings file
+fn backup_path(path: &Path, format: SettingsFormat, n: usize) -> PathBuf {
+    path.with_extension(format!("{}.bak.{}", format.extension(), n))
+}
+
+/// The path of the `n`th most recent unreadable copy of a settings file
+fn broken_path(path: &Path, format: SettingsFormat, n: usize) -> PathBuf {
+    path.with_extension(format!("{}.broken.{}", format.extension(), n))
+}
+
+/// Shift numbered copies of a file down by one, dropping the oldest
+fn shift_copies(numbered: impl Fn(usize) -> PathBuf) -> io::Result<()> {
+    for n in (1..BACKUPS).rev() {
+        let from = numbered(n);
+        if from.exists() {
+            fs::rename(from, numbered(n + 1))?;
+        }
+    }
+    Ok(())
+}
+
+/// Keep the current contents of `path` before replacing them by `data`
+///
+/// Older backups are shifted down, and the oldest one is dropped.
+/// Nothing happens if the contents didn't change.
+fn rotate_backups(path: &Path, format: SettingsFormat, data: &str) -> io::Result<()> {
+    match fs::read_to_string(path) {
+        Ok(old) if old != data => {}
+        Ok(_) => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+
+    shift_copies(|n| backup_path(path, format, n))?;
+    fs::copy(path, backup_path(path, format, 1)).map(|_| ())
+}
+
+/// Move an unreadable file aside, without replacing an earlier one
+///
+/// Returns where the file was moved to.
+fn quarantine(path: &Path, format: SettingsFormat) -> io::Result<PathBuf> {
+    shift_copies(|n| broken_path(path, format, n))?;
+    let broken = broken_path(path, format, 1);
+    fs::rename(path, &broken)?;
+    Ok(broken)
+}
+
+/// Replace the contents of `path` without ever leaving it half-written
+///
+/// The data is written to a temporary file and flushed to disk before
+/// it's renamed over the old file.
+fn write_atomic(path: &Path, data: &str) -> io::Result<()> {
+    let tmp = path.with_extension(format!(
+        "{}.tmp",
+        path.extension().unwrap_or_default().to_string_lossy()
+    ));
+
+    let mut f = File::create(&tmp)?;
+    f.write_all(data.as_bytes())?;
+    f.sync_all()?;
+    fs::rename(&tmp, path)?;
+
+    // Make the rename itself durable
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn load<T: DeserializeOwned>(
+    path: &Path,
+    format: SettingsFormat,
+    schema: &Schema,
+) -> Result<Option<T>, SettingsError> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let tree: Value = format.from_str(&data)?;
+    let from = migrate::version(&tree);
+    if from < schema.current() {
+        let backup = path.with_extension(format!("{}.v{}.bak", format.extension(), from));
+        fs::copy(path, &backup)?;
+        info!(
+            "Upgrading {} from version {}, keeping the original as {}",
+            path.display(),
+            from,
+            backup.display()
+        );
+    }
+
+    upgrade(tree, schema, &path.display().to_string()).map(Some)
+}
+
+/// Parse the contents of a settings file
+fn parse<T: DeserializeOwned>(
+    data: &str,
+    format: SettingsFormat,
+    schema: &Schema,
+    origin: &str,
+) -> Result<T, SettingsError> {
+    upgrade(format.from_str(data)?, schema, origin)
+}
+
+/// Migrate a settings tree to the current version and deserialise it
+fn upgrade<T: DeserializeOwned>(
+    mut tree: Value,
+    schema: &Schema,
+    origin: &str,
+) -> Result<T, SettingsError> {
+    let changes = schema
+        .migrate(&mut tree)
+        .map_err(SettingsError::Migration)?;
+    for change in changes {
+        info!("{}: {}", origin, change);
+    }
+    Ok(serde_json::from_value(tree)?)
+}
+
+/// Serialise a settings tree, recording its version
+fn versioned<T: Serialize>(t: &T, schema: &Schema) -> Result<Value, SettingsError> {
+    let mut tree = serde_json::to_value(t)?;
+    schema.stamp(&mut tree);
+    Ok(tree)
 }
 
 pub struct ReadSettings<'settings> {
@@ -152,6 +571,11 @@ impl<'s> ReadSettings<'s> {
     pub fn cards(self) -> RwLockReadGuard<'s, cards::CardSettings> {
         self.inner.cards.read().unwrap()
     }
+
+    /// Get read access to the `scenes` settings
+    pub fn scenes(self) -> RwLockReadGuard<'s, scenes::SceneSettings> {
+        self.inner.scenes.read().unwrap()
+    }
 }
 
 pub struct WriteSettings<'settings> {
@@ -173,4 +597,132 @@ impl<'s> WriteSettings<'s> {
     pub fn cards(self) -> RwLockWriteGuard<'s, cards::CardSettings> {
         self.inner.cards.write().unwrap()
     }
+
+    /// Get write access to the `scenes` settings
+    pub fn scenes(self) -> RwLockWriteGuard<'s, scenes::SceneSettings> {
+        self.inner.scenes.write().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::card::CardUsage;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn convert_to_toml() {
+        let dir = TestDir::new("settings");
+        let s = Settings::init(&*dir).unwrap();
+        assert!(dir.join("app.json").exists());
+
+        s.w().app().settings_format = Some(SettingsFormat::Toml);
+        s.w().clients().update(Client::new("synth".into()));
+        s.sync().unwrap();
+        assert!(dir.join("app.toml").exists());
+        assert!(!dir.join("app.json").exists());
+
+        let s = Settings::init(&*dir).unwrap();
+        assert_eq!(s.r().app().settings_format, Some(SettingsFormat::Toml));
+        assert!(s.r().clients().find("synth").is_some());
+    }
+
+    #[test]
+    fn upgrade_old_files() {
+        let dir = TestDir::new("settings");
+        fs::write(
+            dir.join("clients.json"),
+            r#"{ "clients": { "0": { "name": "synth", "command": ["synth"],
+                 "working_dir": null, "env": {}, "respawn": true } } }"#,
+        )
+        .unwrap();
+        fs::write(dir.join("cards.json"), r#"{ "version": 99 }"#).unwrap();
+
+        let s = Settings::init(&*dir).unwrap();
+        assert!(dir.join("clients.json.v1.bak").exists());
+        assert!(s.r().clients().find("synth").unwrap().respawn);
+        assert!(dir.join("cards.json.broken.1").exists());
+
+        let stored = fs::read_to_string(dir.join("clients.json")).unwrap();
+        let stored: Value = serde_json::from_str(&stored).unwrap();
+        assert_eq!(stored["version"], migrate::CLIENTS.current());
+    }
+
+    #[test]
+    fn restore_corrupt_files() {
+        let dir = TestDir::new("settings");
+        let s = Settings::init(&*dir).unwrap();
+        assert!(s.take_recovered().is_empty());
+        s.w().cards().set_card_usage(&"USB Audio".into(), true);
+        s.sync().unwrap();
+        s.w().cards().set_card_usage(&"HDMI".into(), false);
+        s.sync().unwrap();
+        s.w().cards().set_card_usage(&"Onboard".into(), true);
+        s.sync().unwrap();
+        assert!(dir.join("cards.json.bak.3").exists());
+        assert!(!dir.join("cards.json.tmp").exists());
+
+        // A crash left a half-written file, and the last backup is bad too
+        fs::write(dir.join("cards.json"), r#"{ "known": {"#).unwrap();
+        fs::write(dir.join("cards.json.bak.1"), "").unwrap();
+
+        let s = Settings::init(&*dir).unwrap();
+        assert_eq!(s.r().cards().use_card(&"USB Audio".into()), CardUsage::Yes);
+        assert!(dir.join("cards.json.broken.1").exists());
+
+        let recovered = s.take_recovered();
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].file, "cards.json");
+        assert_eq!(recovered[0].restored.as_deref(), Some("cards.json.bak.2"));
+
+        // Breaking it again keeps the first broken file
+        fs::write(dir.join("cards.json"), "{").unwrap();
+        Settings::init(&*dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("cards.json.broken.2")).unwrap(),
+            r#"{ "known": {"#
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("cards.json.broken.1")).unwrap(),
+            "{"
+        );
+    }
+
+    #[test]
+    fn reload_edits() {
+        let dir = TestDir::new("settings");
+        let s = Settings::init(&*dir).unwrap();
+        assert_eq!(s.reload("app.json").unwrap(), None);
+        assert_eq!(s.reload("app.json.v1.bak").unwrap(), None);
+
+        let edited = fs::read_to_string(dir.join("scenes.json"))
+            .unwrap()
+            .replace("{}", r#"{ "live": { "connections": [] } }"#);
+        fs::write(dir.join("scenes.json"), edited).unwrap();
+        assert_eq!(s.reload("scenes.json").unwrap(), Some(Section::Scenes));
+        assert!(s.r().scenes().scenes.contains_key("live"));
+
+        // Broken edits are kept until they are fixed
+        fs::write(dir.join("scenes.json"), "{").unwrap();
+        assert!(s.reload("scenes.json").is_err());
+        s.sync().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("scenes.json")).unwrap(), "{");
+        assert!(s.r().scenes().scenes.contains_key("live"));
+    }
+
+    #[test]
+    fn export_and_import() {
+        let (dir_a, dir_b) = (TestDir::new("settings"), TestDir::new("settings"));
+        let a = Settings::init(&*dir_a).unwrap();
+        a.w().cards().set_card_usage(&"USB Audio".into(), true);
+        a.w().clients().update(Client::new("synth".into()));
+        let doc = a.export().unwrap();
+        assert!(doc.contains("# Programs to launch"));
+
+        let b = Settings::init(&*dir_b).unwrap();
+        assert!(b.import("[cards]\nknown = 5\n").is_err());
+        b.import(&doc).unwrap();
+        assert_eq!(b.r().cards().use_card(&"USB Audio".into()), CardUsage::Yes);
+        assert!(b.r().clients().find("synth").is_some());
+    }
 }
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+/// Connect ports automatically when they register
+///
+/// A rule like `^Firefox:output_(\d)$ -> system:playback_$1` matches
+/// newly registered ports by their full `client:port` name.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    /// Regular expression matched against `client:port` names
+    pub pattern: String,
+    /// Port to connect to, may refer to capture groups (`$1`)
+    pub target: String,
+    /// Rules with a higher priority are tried first
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Only match ports of this type
+    #[serde(default)]
+    pub port_type: RulePortType,
+    /// Which side of the connection the pattern matches
+    #[serde(default)]
+    pub direction: RuleDirection,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            pattern: String::new(),
+            target: String::new(),
+            priority: 0,
+            enabled: default_enabled(),
+            port_type: RulePortType::default(),
+            direction: RuleDirection::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RulePortType {
+    #[serde(rename = "any")]
+    Any,
+    #[serde(rename = "audio")]
+    Audio,
+    #[serde(rename = "midi")]
+    Midi,
+}
+
+impl Default for RulePortType {
+    fn default() -> Self {
+        Self::Any
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RuleDirection {
+    /// The pattern matches outputs, the target is an input
+    #[serde(rename = "output")]
+    Output,
+    /// The pattern matches inputs, the target is an output
+    #[serde(rename = "input")]
+    Input,
+}
+
+impl Default for RuleDirection {
+    fn default() -> Self {
+        Self::Output
+    }
+}
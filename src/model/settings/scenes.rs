@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Store named routing scenes
+///
+/// A scene is a snapshot of every connection in the jack graph.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SceneSettings {
+    /// All saved scenes by name
+    pub scenes: BTreeMap<String, Scene>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    /// Connections as `(output, input)` pairs of `client:port` names
+    pub connections: BTreeSet<(String, String)>,
+}
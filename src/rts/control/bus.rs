@@ -0,0 +1,451 @@
+//! The `net.jackctl.Control` D-Bus service
+//!
+//! Exported on the session bus at `/net/jackctl/Control`.  Method
+//! calls are forwarded to the model, and model events are emitted as
+//! signals on the same interface.  The service runs on its own
+//! thread, since the dbus connection is blocking.
+
+use super::ControlClient;
+use crate::model::{
+    events::{ControlCmd, ControlEvent, ControlReply, MuteCmd, VolumeCmd},
+    port::{Port, PortDirection, PortType},
+};
+use async_std::{channel::TryRecvError, task};
+use dbus::{
+    blocking::{stdintf::org_freedesktop_dbus::RequestNameReply, LocalConnection},
+    channel::Sender,
+    strings::{Interface, Member, Path},
+    Message,
+};
+use dbus_tree::{Factory, MTFn, Method, MethodErr, Tree};
+use std::{thread, time::Duration};
+
+pub const BUS_NAME: &str = "net.jackctl.Control";
+pub const OBJECT_PATH: &str = "/net/jackctl/Control";
+pub const INTERFACE: &str = "net.jackctl.Control";
+
+/// How long to wait for method calls before emitting queued signals
+const PROCESS_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// A port as sent over the bus: id, name, type and direction
+type PortTuple = (u32, String, String, String);
+
+type Handler = fn(&ControlClient, &Message) -> Result<Message, MethodErr>;
+
+/// Start the D-Bus service on the session bus
+pub fn start(client: ControlClient) {
+    spawn(client, LocalConnection::new_session);
+}
+
+/// Run the service on a connection made by `connect`
+pub(crate) fn spawn<F>(client: ControlClient, connect: F) -> thread::JoinHandle<()>
+where
+    F: FnOnce() -> Result<LocalConnection, dbus::Error> + Send + 'static,
+{
+    thread::spawn(move || {
+        if let Err(e) = connect().and_then(|conn| run(conn, client)) {
+            error!("D-Bus control service failed: {}", e);
+        }
+    })
+}
+
+fn run(conn: LocalConnection, client: ControlClient) -> Result<(), dbus::Error> {
+    let events = client.subscribe();
+    tree(&client).start_receive(&conn);
+
+    match conn.request_name(BUS_NAME, false, false, true)? {
+        RequestNameReply::PrimaryOwner => info!("Exported {} on D-Bus", BUS_NAME),
+        reply => {
+            warn!("Can't own D-Bus name {} ({:?})", BUS_NAME, reply);
+            return Ok(());
+        }
+    }
+
+    loop {
+        conn.process(PROCESS_TIMEOUT)?;
+        loop {
+            match events.try_recv() {
+                Ok(ev) => {
+                    let _ = conn.send(signal(&ev));
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Closed) => {
+                    info!("D-Bus control service shutting down");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn tree(client: &ControlClient) -> Tree<MTFn<()>, ()> {
+    let f = Factory::new_fn::<()>();
+    let method = |name: &'static str, handler: Handler| -> Method<MTFn<()>, ()> {
+        let client = client.clone();
+        f.method(name, (), move |m| Ok(vec![handler(&client, m.msg)?]))
+    };
+
+    let iface = f
+        .interface(INTERFACE, ())
+        .add_m(method("ListCards", list_cards).outarg::<Vec<(i32, String, bool)>, _>("cards"))
+        .add_m(method("ListPorts", list_ports).outarg::<Vec<PortTuple>, _>("ports"))
+        .add_m(
+            method("ListConnections", list_connections)
+                .outarg::<Vec<(String, String)>, _>("connections"),
+        )
+        .add_m(
+            method("Connect", connect)
+                .inarg::<&str, _>("output")
+                .inarg::<&str, _>("input"),
+        )
+        .add_m(
+            method("Disconnect", disconnect)
+                .inarg::<&str, _>("output")
+                .inarg::<&str, _>("input"),
+        )
+        .add_m(
+            method("SetVolume", set_volume)
+                .inarg::<i32, _>("card")
+                .inarg::<u32, _>("channel")
+                .inarg::<&str, _>("channel_name")
+                .inarg::<i64, _>("volume"),
+        )
+        .add_m(
+            method("SetMute", set_mute)
+                .inarg::<i32, _>("card")
+                .inarg::<u32, _>("channel")
+                .inarg::<&str, _>("channel_name")
+                .inarg::<bool, _>("mute"),
+        )
+        .add_m(method("ExportSettings", export_settings).outarg::<&str, _>("settings"))
+        .add_m(method("ImportSettings", import_settings).inarg::<&str, _>("settings"))
+        .add_m(method("Shutdown", shutdown))
+        .add_s(f.signal("XRun", ()))
+        .add_s(
+            f.signal("JackSettings", ())
+                .sarg::<f64, _>("cpu_percentage")
+                .sarg::<u64, _>("sample_rate")
+                .sarg::<u64, _>("buffer_size")
+                .sarg::<f64, _>("latency"),
+        )
+        .add_s(
+            f.signal("PortAdded", ())
+                .sarg::<u32, _>("id")
+                .sarg::<&str, _>("name")
+                .sarg::<&str, _>("type")
+                .sarg::<&str, _>("direction"),
+        )
+        .add_s(f.signal("PortRemoved", ()).sarg::<u32, _>("id"))
+        .add_s(
+            f.signal("Connected", ())
+                .sarg::<&str, _>("output")
+                .sarg::<&str, _>("input"),
+        )
+        .add_s(
+            f.signal("Disconnected", ())
+                .sarg::<&str, _>("output")
+                .sarg::<&str, _>("input"),
+        )
+        .add_s(
+            f.signal("CardAdded", ())
+                .sarg::<i32, _>("id")
+                .sarg::<&str, _>("name"),
+        )
+        .add_s(f.signal("CardRemoved", ()).sarg::<i32, _>("id"))
+        .add_s(
+            f.signal("VolumeChanged", ())
+                .sarg::<i32, _>("card")
+                .sarg::<u32, _>("channel")
+                .sarg::<&str, _>("channel_name")
+                .sarg::<i64, _>("volume"),
+        )
+        .add_s(
+            f.signal("MuteChanged", ())
+                .sarg::<i32, _>("card")
+                .sarg::<u32, _>("channel")
+                .sarg::<&str, _>("channel_name")
+                .sarg::<bool, _>("mute"),
+        );
+
+    f.tree(())
+        .add(f.object_path(OBJECT_PATH, ()).introspectable().add(iface))
+}
+
+/// Forward a request to the model, turning errors into D-Bus errors
+fn request(client: &ControlClient, cmd: ControlCmd) -> Result<ControlReply, MethodErr> {
+    match task::block_on(client.request(cmd)) {
+        ControlReply::Error(e) => Err(MethodErr::failed(&e)),
+        reply => Ok(reply),
+    }
+}
+
+fn unexpected(reply: ControlReply) -> MethodErr {
+    MethodErr::failed(&format!("Unexpected reply: {:?}", reply))
+}
+
+fn list_cards(client: &ControlClient, msg: &Message) -> Result<Message, MethodErr> {
+    match request(client, ControlCmd::ListCards)? {
+        ControlReply::Cards(cards) => {
+            let cards: Vec<_> = cards
+                .into_iter()
+                .map(|c| (c.id, c.name, c.active))
+                .collect();
+            Ok(msg.method_return().append1(cards))
+        }
+        reply => Err(unexpected(reply)),
+    }
+}
+
+fn list_ports(client: &ControlClient, msg: &Message) -> Result<Message, MethodErr> {
+    match request(client, ControlCmd::ListPorts)? {
+        ControlReply::Ports(ports) => {
+            let ports: Vec<_> = ports.iter().map(port_tuple).collect();
+            Ok(msg.method_return().append1(ports))
+        }
+        reply => Err(unexpected(reply)),
+    }
+}
+
+fn list_connections(client: &ControlClient, msg: &Message) -> Result<Message, MethodErr> {
+    match request(client, ControlCmd::ListConnections)? {
+        ControlReply::Connections(c) => Ok(msg.method_return().append1(c)),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+fn connect(client: &ControlClient, msg: &Message) -> Result<Message, MethodErr> {
+    let (output, input): (String, String) = msg.read2()?;
+    request(client, ControlCmd::Connect { output, input })?;
+    Ok(msg.method_return())
+}
+
+fn disconnect(client: &ControlClient, msg: &Message) -> Result<Message, MethodErr> {
+    let (output, input): (String, String) = msg.read2()?;
+    request(client, ControlCmd::Disconnect { output, input })?;
+    Ok(msg.method_return())
+}
+
+fn set_volume(client: &ControlClient, msg: &Message) -> Result<Message, MethodErr> {
+    let (card, idx, name, volume): (i32, u32, String, i64) = msg.read4()?;
+    request(
+        client,
+        ControlCmd::SetVolume(VolumeCmd {
+            card,
+            channel: (idx, name),
+            volume,
+        }),
+    )?;
+    Ok(msg.method_return())
+}
+
+fn set_mute(client: &ControlClient, msg: &Message) -> Result<Message, MethodErr> {
+    let (card, idx, name, mute): (i32, u32, String, bool) = msg.read4()?;
+    request(
+        client,
+        ControlCmd::SetMute(MuteCmd {
+            card,
+            channel: (idx, name),
+            mute,
+        }),
+    )?;
+    Ok(msg.method_return())
+}
+
+fn export_settings(client: &ControlClient, msg: &Message) -> Result<Message, MethodErr> {
+    match request(client, ControlCmd::ExportSettings)? {
+        ControlReply::Exported(doc) => Ok(msg.method_return().append1(doc)),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+fn import_settings(client: &ControlClient, msg: &Message) -> Result<Message, MethodErr> {
+    let doc: String = msg.read1()?;
+    request(client, ControlCmd::ImportSettings(doc))?;
+    Ok(msg.method_return())
+}
+
+fn shutdown(client: &ControlClient, msg: &Message) -> Result<Message, MethodErr> {
+    request(client, ControlCmd::Shutdown)?;
+    Ok(msg.method_return())
+}
+
+fn port_tuple(p: &Port) -> PortTuple {
+    let tt = match p.tt {
+        PortType::Audio => "audio",
+        PortType::Midi => "midi",
+        PortType::Unknown => "unknown",
+    };
+    let dir = match p.dir {
+        PortDirection::Input => "input",
+        PortDirection::Output => "output",
+    };
+    (p.id, p.full_name(), tt.into(), dir.into())
+}
+
+/// Build the signal message mirroring a model event
+fn signal(ev: &ControlEvent) -> Message {
+    let path = Path::from(OBJECT_PATH);
+    let iface = Interface::from(INTERFACE);
+    let msg = |name: &'static str| Message::signal(&path, &iface, &Member::from(name));
+
+    use ControlEvent::*;
+    match ev {
+        XRun => msg("XRun"),
+        JackSettings(s) => msg("JackSettings")
+            .append3(s.cpu_percentage as f64, s.sample_rate, s.buffer_size)
+            .append1(s.latency as f64),
+        AddPort(p) => {
+            let (id, name, tt, dir) = port_tuple(p);
+            msg("PortAdded").append3(id, name, tt).append1(dir)
+        }
+        DelPort(id) => msg("PortRemoved").append1(id),
+        AddConnection { output, input } => msg("Connected").append2(output, input),
+        DelConnection { output, input } => msg("Disconnected").append2(output, input),
+        AddCard { id, name } => msg("CardAdded").append2(id, name),
+        DelCard(id) => msg("CardRemoved").append1(id),
+        VolumeChange(v) => msg("VolumeChanged")
+            .append3(v.card, v.channel.0, &v.channel.1)
+            .append1(v.volume),
+        MuteChange(m) => msg("MuteChanged")
+            .append3(m.card, m.channel.0, &m.channel.1)
+            .append1(m.mute),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::model::events::CardInfo;
+    use crate::rts::control;
+    use dbus::channel::Channel;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::time::Instant;
+
+    /// A private `dbus-daemon`, killed when dropped
+    pub(crate) struct TestBus {
+        daemon: Child,
+        pub(crate) address: String,
+    }
+
+    impl TestBus {
+        /// Start a new bus, or `None` if there's no `dbus-daemon`
+        pub(crate) fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(&["--session", "--nofork", "--print-address=1"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .ok()?;
+
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+            let address = address.trim().to_owned();
+            Some(Self { daemon, address })
+        }
+
+        pub(crate) fn connect(&self) -> Result<LocalConnection, dbus::Error> {
+            open(&self.address)
+        }
+
+        /// Wait until some connection owns `name`
+        pub(crate) fn wait_for(&self, conn: &LocalConnection, name: &str) {
+            let proxy = conn.with_proxy("org.freedesktop.DBus", "/", Duration::from_secs(1));
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(5) {
+                let owned: Result<(bool,), _> =
+                    proxy.method_call("org.freedesktop.DBus", "NameHasOwner", (name,));
+                if let Ok((true,)) = owned {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            panic!("Nobody took the bus name {}", name);
+        }
+    }
+
+    /// Connect to a bus by its address
+    pub(crate) fn open(address: &str) -> Result<LocalConnection, dbus::Error> {
+        let mut channel = Channel::open_private(address)?;
+        channel.register()?;
+        Ok(LocalConnection::from(channel))
+    }
+
+    impl Drop for TestBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[test]
+    fn methods_and_signals() {
+        let bus = match TestBus::start() {
+            Some(bus) => bus,
+            None => return eprintln!("No dbus-daemon available, skipping"),
+        };
+
+        // Play the model
+        let (handle, client) = control::channel();
+        let model = handle.clone();
+        task::spawn(async move {
+            while let Some((cmd, reply)) = model.next_request().await {
+                let r = match cmd {
+                    ControlCmd::ListCards => ControlReply::Cards(vec![CardInfo {
+                        id: 1,
+                        name: "USB Audio".into(),
+                        active: true,
+                    }]),
+                    ControlCmd::Connect { output, .. } if output == "synth:out" => ControlReply::Ok,
+                    _ => ControlReply::Error("no such port".into()),
+                };
+                let _ = reply.reply(r).await;
+            }
+        });
+
+        let address = bus.address.clone();
+        spawn(client, move || open(&address));
+
+        let conn = bus.connect().unwrap();
+        bus.wait_for(&conn, BUS_NAME);
+        let proxy = conn.with_proxy(BUS_NAME, OBJECT_PATH, Duration::from_secs(1));
+
+        let (cards,): (Vec<(i32, String, bool)>,) =
+            proxy.method_call(INTERFACE, "ListCards", ()).unwrap();
+        assert_eq!(cards, vec![(1, "USB Audio".to_owned(), true)]);
+
+        let ok: Result<(), _> =
+            proxy.method_call(INTERFACE, "Connect", ("synth:out", "system:playback_1"));
+        assert!(ok.is_ok());
+        let err: Result<(), dbus::Error> =
+            proxy.method_call(INTERFACE, "Connect", ("nope:out", "system:playback_1"));
+        assert_eq!(err.unwrap_err().message(), Some("no such port"));
+
+        // Model events come out as signals
+        conn.add_match_no_cb("type='signal',interface='net.jackctl.Control'")
+            .unwrap();
+        handle.broadcast(ControlEvent::AddConnection {
+            output: "synth:out".into(),
+            input: "system:playback_1".into(),
+        });
+
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed() < Duration::from_secs(5), "No signal");
+            let msg = conn
+                .channel()
+                .blocking_pop_message(Duration::from_millis(100))
+                .unwrap();
+            match msg {
+                Some(msg) if msg.member().as_deref() == Some("Connected") => {
+                    let (output, input): (String, String) = msg.read2().unwrap();
+                    assert_eq!(output, "synth:out");
+                    assert_eq!(input, "system:playback_1");
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+}
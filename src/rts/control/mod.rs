@@ -0,0 +1,98 @@
+//! Remote control of a running jackctl
+//!
+//! Front-ends (like the D-Bus service or the JSON-RPC socket) hold a
+//! `ControlClient`.  Their requests are answered by the model through
+//! its `ControlHandle`, and model events are broadcast to every
+//! subscribed front-end.
+
+pub mod bus;
+pub mod socket;
+
+use crate::cb_channel::{self, Replier, ReturningReceiver, ReturningSender};
+use crate::model::events::{ControlCmd, ControlEvent, ControlReply};
+use async_std::channel::{bounded, Receiver, Sender, TrySendError};
+use std::sync::{Arc, Mutex};
+
+/// Events are dropped for subscribers that fall this far behind
+const SUBSCRIBER_BUFFER: usize = 256;
+
+type Subscribers = Arc<Mutex<Vec<Sender<ControlEvent>>>>;
+
+/// Create a connected pair of control handles
+pub fn channel() -> (ControlHandle, ControlClient) {
+    let (req_tx, req_rx) = cb_channel::bounded(128);
+    let subscribers = Subscribers::default();
+
+    (
+        ControlHandle {
+            req_rx: Arc::new(req_rx),
+            subscribers: subscribers.clone(),
+        },
+        ControlClient {
+            req_tx,
+            subscribers,
+        },
+    )
+}
+
+/// The model side of the control channel
+#[derive(Clone, Debug)]
+pub struct ControlHandle {
+    req_rx: Arc<ReturningReceiver<ControlCmd, ControlReply>>,
+    subscribers: Subscribers,
+}
+
+impl ControlHandle {
+    /// Wait for the next request
+    ///
+    /// Without any front-ends left this never returns.
+    pub async fn next_request(&self) -> Option<(ControlCmd, Replier<ControlReply>)> {
+        match self.req_rx.recv().await {
+            Ok(req) => Some(req),
+            Err(_) => futures::future::pending().await,
+        }
+    }
+
+    /// Send an event to every subscribed front-end
+    pub fn broadcast(&self, ev: ControlEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| match tx.try_send(ev.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Control subscriber is lagging behind, dropping event");
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            });
+    }
+
+    pub fn close(&self) {
+        self.subscribers.lock().unwrap().clear();
+    }
+}
+
+/// The front-end side of the control channel
+#[derive(Clone, Debug)]
+pub struct ControlClient {
+    req_tx: ReturningSender<ControlCmd, ControlReply>,
+    subscribers: Subscribers,
+}
+
+impl ControlClient {
+    /// Send a request to the model and wait for its answer
+    pub async fn request(&self, cmd: ControlCmd) -> ControlReply {
+        match self.req_tx.send(cmd).await {
+            Ok(reply) => reply,
+            Err(_) => ControlReply::Error("jackctl is shutting down".into()),
+        }
+    }
+
+    /// Receive all future model events
+    pub fn subscribe(&self) -> Receiver<ControlEvent> {
+        let (tx, rx) = bounded(SUBSCRIBER_BUFFER);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
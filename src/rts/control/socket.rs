@@ -0,0 +1,445 @@
+//! A line-delimited JSON-RPC control socket
+//!
+//! Listens on `$XDG_RUNTIME_DIR/jackctl/control.sock`.  Every line is
+//! one JSON-RPC 2.0 request, answered by one line.  After `subscribe`
+//! model events follow as `event` notifications on the same socket.
+
+use super::ControlClient;
+use crate::model::{
+    card::{CardId, Volume},
+    events::{ControlCmd, ControlEvent, ControlReply, JackSettings, MuteCmd, VolumeCmd},
+    port::{Port, PortDirection, PortType},
+};
+use async_std::{
+    channel::{unbounded, Sender},
+    io::BufReader,
+    os::unix::net::{UnixListener, UnixStream},
+    prelude::*,
+    task,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::{
+    fs, io,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+};
+
+pub const SOCKET_NAME: &str = "control.sock";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// Start listening in the given runtime directory
+pub fn start(client: ControlClient, dir: &Path) {
+    let path = dir.join(SOCKET_NAME);
+    if let Err(e) = fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+    {
+        error!("Can't create runtime directory {}: {}", dir.display(), e);
+        return;
+    }
+
+    task::spawn(async move {
+        if let Err(e) = serve(client, path).await {
+            error!("JSON-RPC control socket failed: {}", e);
+        }
+    });
+}
+
+/// Bind the socket, replacing one left behind by a dead instance
+async fn bind(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another jackctl", path.display()),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    UnixListener::bind(path).await
+}
+
+pub(crate) async fn serve(client: ControlClient, path: PathBuf) -> io::Result<()> {
+    let listener = bind(&path).await?;
+    info!("Listening for JSON-RPC on {}", path.display());
+
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Ok(stream) => {
+                let client = client.clone();
+                task::spawn(async move {
+                    if let Err(e) = connection(client, stream).await {
+                        debug!("JSON-RPC connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Failed to accept JSON-RPC connection: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Serve a single connection until it is closed
+async fn connection(client: ControlClient, stream: UnixStream) -> io::Result<()> {
+    let (out_tx, out_rx) = unbounded::<Value>();
+
+    // Replies and notifications share the write half
+    let mut writer = stream.clone();
+    let write_task = task::spawn(async move {
+        while let Ok(msg) = out_rx.recv().await {
+            let line = format!("{}\n", msg);
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(stream).lines();
+    let mut subscribed = false;
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (id, result) = match serde_json::from_str::<Value>(&line) {
+            Ok(req) => (
+                req.get("id").cloned().unwrap_or(Value::Null),
+                handle(&client, &req, &out_tx, &mut subscribed).await,
+            ),
+            Err(e) => (Value::Null, Err((PARSE_ERROR, e.to_string()))),
+        };
+
+        let reply = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+        if out_tx.send(reply).await.is_err() {
+            break;
+        }
+    }
+
+    // Also stops the event forwarding of a subscription
+    out_tx.close();
+    write_task.await;
+    Ok(())
+}
+
+type RpcResult = Result<Value, (i64, String)>;
+
+async fn handle(
+    client: &ControlClient,
+    req: &Value,
+    out_tx: &Sender<Value>,
+    subscribed: &mut bool,
+) -> RpcResult {
+    let method = req
+        .get("method")
+        .and_then(Value::as_str)
+        .ok_or_else(|| (INVALID_REQUEST, "Missing method".to_owned()))?;
+    let params = req.get("params").cloned().unwrap_or(Value::Null);
+
+    let cmd = match method {
+        "ports" => ControlCmd::ListPorts,
+        "connections" => ControlCmd::ListConnections,
+        "cards" => ControlCmd::ListCards,
+        "stats" => ControlCmd::Stats,
+        "connect" => {
+            let p: ConnectParams = params_of(params)?;
+            ControlCmd::Connect {
+                output: p.output,
+                input: p.input,
+            }
+        }
+        "disconnect" => {
+            let p: ConnectParams = params_of(params)?;
+            ControlCmd::Disconnect {
+                output: p.output,
+                input: p.input,
+            }
+        }
+        "set_volume" => {
+            let p: VolumeParams = params_of(params)?;
+            ControlCmd::SetVolume(VolumeCmd {
+                card: p.card,
+                channel: (p.index, p.channel),
+                volume: p.volume,
+            })
+        }
+        "set_mute" => {
+            let p: MuteParams = params_of(params)?;
+            ControlCmd::SetMute(MuteCmd {
+                card: p.card,
+                channel: (p.index, p.channel),
+                mute: p.mute,
+            })
+        }
+        "card_usage" => {
+            let p: UsageParams = params_of(params)?;
+            ControlCmd::CardUsage {
+                card: p.card,
+                usage: p.usage,
+                store: p.store,
+            }
+        }
+        "subscribe" => {
+            if !*subscribed {
+                *subscribed = true;
+                let events = client.subscribe();
+                let out_tx = out_tx.clone();
+                task::spawn(async move {
+                    while let Ok(ev) = events.recv().await {
+                        let note = json!({
+                            "jsonrpc": "2.0",
+                            "method": "event",
+                            "params": event_json(&ev),
+                        });
+                        if out_tx.send(note).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            return Ok(json!(true));
+        }
+        "export_settings" => ControlCmd::ExportSettings,
+        "import_settings" => {
+            let p: ImportParams = params_of(params)?;
+            ControlCmd::ImportSettings(p.settings)
+        }
+        "shutdown" => ControlCmd::Shutdown,
+        m => return Err((METHOD_NOT_FOUND, format!("Unknown method {}", m))),
+    };
+
+    reply_json(client.request(cmd).await)
+}
+
+fn params_of<T: DeserializeOwned>(params: Value) -> Result<T, (i64, String)> {
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+#[derive(Deserialize)]
+struct ConnectParams {
+    output: String,
+    input: String,
+}
+
+#[derive(Deserialize)]
+struct VolumeParams {
+    card: CardId,
+    channel: String,
+    #[serde(default)]
+    index: u32,
+    volume: Volume,
+}
+
+#[derive(Deserialize)]
+struct MuteParams {
+    card: CardId,
+    channel: String,
+    #[serde(default)]
+    index: u32,
+    mute: bool,
+}
+
+#[derive(Deserialize)]
+struct UsageParams {
+    card: CardId,
+    #[serde(rename = "use")]
+    usage: bool,
+    #[serde(default)]
+    store: bool,
+}
+
+#[derive(Deserialize)]
+struct ImportParams {
+    settings: String,
+}
+
+fn reply_json(reply: ControlReply) -> RpcResult {
+    Ok(match reply {
+        ControlReply::Ok => json!(true),
+        ControlReply::Error(e) => return Err((SERVER_ERROR, e)),
+        ControlReply::Cards(cards) => Value::Array(
+            cards
+                .iter()
+                .map(|c| json!({ "id": c.id, "name": c.name, "active": c.active }))
+                .collect(),
+        ),
+        ControlReply::Ports(ports) => Value::Array(ports.iter().map(port_json).collect()),
+        ControlReply::Connections(connections) => Value::Array(
+            connections
+                .iter()
+                .map(|(output, input)| json!({ "output": output, "input": input }))
+                .collect(),
+        ),
+        ControlReply::Stats { settings, xruns } => json!({
+            "settings": settings.as_ref().map(settings_json),
+            "xruns": xruns,
+        }),
+        ControlReply::Exported(doc) => json!(doc),
+    })
+}
+
+fn port_json(p: &Port) -> Value {
+    let tt = match p.tt {
+        PortType::Audio => "audio",
+        PortType::Midi => "midi",
+        PortType::Unknown => "unknown",
+    };
+    let dir = match p.dir {
+        PortDirection::Input => "input",
+        PortDirection::Output => "output",
+    };
+    json!({ "id": p.id, "name": p.full_name(), "type": tt, "direction": dir })
+}
+
+fn settings_json(s: &JackSettings) -> Value {
+    json!({
+        "cpu_percentage": s.cpu_percentage,
+        "sample_rate": s.sample_rate,
+        "buffer_size": s.buffer_size,
+        "latency": s.latency,
+    })
+}
+
+fn event_json(ev: &ControlEvent) -> Value {
+    match ev {
+        ControlEvent::XRun => json!({ "type": "xrun" }),
+        ControlEvent::JackSettings(s) => {
+            json!({ "type": "jack_settings", "settings": settings_json(s) })
+        }
+        ControlEvent::AddPort(p) => json!({ "type": "port_added", "port": port_json(p) }),
+        ControlEvent::DelPort(id) => json!({ "type": "port_removed", "id": id }),
+        ControlEvent::AddConnection { output, input } => {
+            json!({ "type": "connected", "output": output, "input": input })
+        }
+        ControlEvent::DelConnection { output, input } => {
+            json!({ "type": "disconnected", "output": output, "input": input })
+        }
+        ControlEvent::AddCard { id, name } => {
+            json!({ "type": "card_added", "id": id, "name": name })
+        }
+        ControlEvent::DelCard(id) => json!({ "type": "card_removed", "id": id }),
+        ControlEvent::VolumeChange(v) => json!({
+            "type": "volume_changed",
+            "card": v.card,
+            "channel": v.channel.1,
+            "index": v.channel.0,
+            "volume": v.volume,
+        }),
+        ControlEvent::MuteChange(m) => json!({
+            "type": "mute_changed",
+            "card": m.card,
+            "channel": m.channel.1,
+            "index": m.channel.0,
+            "mute": m.mute,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rts::control;
+    use crate::test_dir::TestDir;
+
+    async fn roundtrip(
+        lines: &mut (impl Stream<Item = io::Result<String>> + Unpin),
+        stream: &mut UnixStream,
+        req: Value,
+    ) -> Value {
+        stream
+            .write_all(format!("{}\n", req).as_bytes())
+            .await
+            .unwrap();
+        let line = lines.next().await.unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn requests_and_notifications() {
+        task::block_on(async {
+            let dir = TestDir::new("socket");
+            let path = dir.join(SOCKET_NAME);
+
+            // Stand in for the model
+            let (handle, client) = control::channel();
+            let model = handle.clone();
+            task::spawn(async move {
+                while let Some((cmd, reply)) = model.next_request().await {
+                    let r = match cmd {
+                        ControlCmd::ListConnections => {
+                            ControlReply::Connections(vec![("a:out".into(), "b:in".into())])
+                        }
+                        ControlCmd::SetVolume(v) if v.channel == (1, "PCM".into()) => {
+                            ControlReply::Ok
+                        }
+                        cmd => ControlReply::Error(format!("{:?}", cmd)),
+                    };
+                    let _ = reply.reply(r).await;
+                }
+            });
+
+            task::spawn(serve(client, path.clone()));
+            let mut stream = loop {
+                match UnixStream::connect(&path).await {
+                    Ok(s) => break s,
+                    Err(_) => task::sleep(std::time::Duration::from_millis(10)).await,
+                }
+            };
+            let mut lines = BufReader::new(stream.clone()).lines();
+
+            let r = roundtrip(
+                &mut lines,
+                &mut stream,
+                json!({ "jsonrpc": "2.0", "id": 1, "method": "connections" }),
+            )
+            .await;
+            assert_eq!(r["id"], 1);
+            assert_eq!(r["result"][0]["output"], "a:out");
+
+            let set = json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "set_volume",
+                "params": { "card": 0, "channel": "PCM", "index": 1, "volume": 5 },
+            });
+            let r = roundtrip(&mut lines, &mut stream, set).await;
+            assert_eq!(r["result"], true);
+
+            let bad = json!({ "jsonrpc": "2.0", "id": 3, "method": "connect", "params": {} });
+            let r = roundtrip(&mut lines, &mut stream, bad).await;
+            assert_eq!(r["error"]["code"], INVALID_PARAMS);
+
+            let unknown = json!({ "jsonrpc": "2.0", "id": 4, "method": "dance" });
+            let r = roundtrip(&mut lines, &mut stream, unknown).await;
+            assert_eq!(r["error"]["code"], METHOD_NOT_FOUND);
+
+            let sub = json!({ "jsonrpc": "2.0", "id": 5, "method": "subscribe" });
+            let r = roundtrip(&mut lines, &mut stream, sub).await;
+            assert_eq!(r["result"], true);
+
+            handle.broadcast(ControlEvent::DelPort(9));
+            let line = lines.next().await.unwrap().unwrap();
+            let note: Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(note["method"], "event");
+            assert_eq!(note["params"]["type"], "port_removed");
+            assert_eq!(note["params"]["id"], 9);
+        });
+    }
+}
@@ -1,6 +1,7 @@
 use crate::cb_channel::{self, ReturningReceiver, ReturningSender};
 use crate::model::card::{CardConfig, ChannelCount, MixerChannel, SampleRate, Volume};
 use crate::model::events::{HardwareCardAction, HardwareCmd, HardwareEvent, MuteCmd, VolumeCmd};
+use crate::rts::hardware::HardwareBackend;
 use alsa::card::Card;
 use alsa::card::Iter as CardIter;
 use alsa::mixer::{Elem, Mixer, Selem, SelemChannelId, SelemId};
@@ -11,6 +12,7 @@ use async_std::{
     sync::RwLock,
     task,
 };
+use futures::future::{BoxFuture, FutureExt};
 use std::collections::hash_map::HashMap;
 use std::sync::Arc;
 
@@ -50,19 +52,21 @@ pub struct AlsaHandle {
     card_tx: ReturningSender<HardwareCardAction, ()>,
 }
 
-impl AlsaHandle {
-    pub async fn next_event(&self) -> Option<HardwareEvent> {
-        self.event_rx.recv().await.ok()
+impl HardwareBackend for AlsaHandle {
+    fn next_event(&self) -> BoxFuture<'_, Option<HardwareEvent>> {
+        async move { self.event_rx.recv().await.ok() }.boxed()
     }
 
-    // TODO: trait this
-    pub async fn send_cmd(&self, cmd: HardwareCmd) {
-        if let Err(_) = self.cmd_tx.send(cmd).await {
-            error!("Failed to send CMD to hardware runtime!");
+    fn send_cmd(&self, cmd: HardwareCmd) -> BoxFuture<'_, ()> {
+        async move {
+            if let Err(_) = self.cmd_tx.send(cmd).await {
+                error!("Failed to send CMD to hardware runtime!");
+            }
         }
+        .boxed()
     }
 
-    pub fn close(&self) {
+    fn close(&self) {
         self.cmd_tx.close();
         self.event_rx.close();
         self.card_tx.close();
@@ -80,6 +84,14 @@ pub struct AlsaController {
     known_cards: RwLock<HashMap<CardId, bool>>,
 }
 
+/// Find the index of the card called `name`, if it's plugged in
+pub fn find_card(name: &str) -> Option<CardId> {
+    CardIter::new()
+        .filter_map(Result::ok)
+        .find(|card| card.get_name().map_or(false, |n| n == name))
+        .map(|card| card.get_index())
+}
+
 fn extract_selem(id: &SelemId) -> ChannelId {
     let index = id.get_index();
     let name = id
@@ -0,0 +1,101 @@
+//! An in-memory hardware backend driven by a script
+//!
+//! The mock never touches ALSA.  It plays back a list of
+//! [`MockStep`](MockStep)s as hardware events, and records every
+//! command the model sends it.  Mixer commands are echoed back as
+//! mixer updates, the same way the ALSA poller would report them.
+
+use crate::model::events::{HardwareCmd, HardwareEvent};
+use crate::rts::hardware::HardwareBackend;
+use async_std::{
+    channel::{unbounded, Receiver, Sender},
+    task,
+};
+use futures::future::{BoxFuture, FutureExt};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A single step of a mock hardware script
+#[derive(Clone, Debug)]
+pub enum MockStep {
+    /// Send an event to the model
+    Emit(HardwareEvent),
+    /// Wait for some time before running the next step
+    Sleep(Duration),
+}
+
+#[derive(Clone, Debug)]
+pub struct MockHardware {
+    /// Send events to the model, kept so the channel stays open
+    event_tx: Sender<HardwareEvent>,
+    /// Receive events from the script
+    event_rx: Receiver<HardwareEvent>,
+    /// Every command the model has sent so far
+    cmds: Arc<Mutex<Vec<HardwareCmd>>>,
+}
+
+impl MockHardware {
+    /// Create a mock backend and start playing back a script
+    pub fn new(script: Vec<MockStep>) -> Self {
+        let (event_tx, event_rx) = unbounded();
+
+        let tx = event_tx.clone();
+        task::spawn(async move {
+            for step in script {
+                match step {
+                    MockStep::Emit(ev) => {
+                        if tx.send(ev).await.is_err() {
+                            break;
+                        }
+                    }
+                    MockStep::Sleep(d) => task::sleep(d).await,
+                }
+            }
+        });
+
+        Self {
+            event_tx,
+            event_rx,
+            cmds: Default::default(),
+        }
+    }
+
+    /// Send a single event to the model outside of the script
+    pub async fn emit(&self, ev: HardwareEvent) {
+        let _ = self.event_tx.send(ev).await;
+    }
+
+    /// Get a copy of all commands received so far
+    pub fn commands(&self) -> Vec<HardwareCmd> {
+        self.cmds.lock().unwrap().clone()
+    }
+}
+
+impl HardwareBackend for MockHardware {
+    fn next_event(&self) -> BoxFuture<'_, Option<HardwareEvent>> {
+        async move { self.event_rx.recv().await.ok() }.boxed()
+    }
+
+    fn send_cmd(&self, cmd: HardwareCmd) -> BoxFuture<'_, ()> {
+        async move {
+            self.cmds.lock().unwrap().push(cmd.clone());
+
+            let echo = match cmd {
+                HardwareCmd::SetMixerVolume(v) => Some(HardwareEvent::UpdateMixerVolume(v)),
+                HardwareCmd::SetMixerMute(m) => Some(HardwareEvent::UpdateMixerMute(m)),
+                HardwareCmd::Shutdown => None,
+            };
+
+            if let Some(ev) = echo {
+                self.emit(ev).await;
+            }
+        }
+        .boxed()
+    }
+
+    fn close(&self) {
+        self.event_tx.close();
+    }
+}
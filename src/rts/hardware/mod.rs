@@ -1,9 +1,33 @@
 //ifconfig is linux
 mod alsa_card;
+#[cfg(test)]
+mod mock;
 
+pub use alsa_card::find_card;
 pub use alsa_card::AlsaHandle as HardwareHandle;
 pub use alsa_card::CardId;
 pub use alsa_card::ChannelId;
+#[cfg(test)]
+pub use mock::{MockHardware, MockStep};
 
 // ifconfig is mac
 // mod coraudio;
+
+use crate::model::events::{HardwareCmd, HardwareEvent};
+use futures::future::BoxFuture;
+use std::fmt::Debug;
+
+/// A sound card backend the model can drive
+///
+/// Handles are cheap to clone, and all clones talk to the same
+/// runtime.
+pub trait HardwareBackend: Clone + Debug + Send + Sync + 'static {
+    /// Wait for the next hardware event
+    fn next_event(&self) -> BoxFuture<'_, Option<HardwareEvent>>;
+
+    /// Send a command to the hardware runtime
+    fn send_cmd(&self, cmd: HardwareCmd) -> BoxFuture<'_, ()>;
+
+    /// Close the channels to the hardware runtime
+    fn close(&self);
+}
@@ -1,41 +1,102 @@
 use crate::model::events::JackEvent;
 use crate::model::port::{Port, PortDirection, PortType};
-use async_std::channel::Sender;
+use crate::rts::jack::JackRuntime;
+use async_std::{channel::Sender, task};
 use jack::Error as JackError;
-use jack::{NotificationHandler, Port as JackPort, PortFlags, PortId, Unowned};
+use jack::{
+    Client, ClientStatus, NotificationHandler, Port as JackPort, PortFlags, PortId, Unowned,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
 
-pub struct JackNotificationController {
-    pipe: Sender<JackEvent>,
+/// Highest port ID probed when looking for existing ports
+const MAX_PORT_ID: PortId = 65536;
+
+/// Keeps callback events in order with the initial graph
+///
+/// Callbacks start as soon as the client is activated, but the graph
+/// that existed before is only read afterwards.  Until it is sent,
+/// callback events are held back.  Callbacks for ports and
+/// connections that the initial graph already contains are dropped,
+/// as they may arrive after it.
+#[derive(Debug, Default)]
+pub struct Backlog {
+    /// Callback events waiting for the initial graph
+    queue: Option<Vec<JackEvent>>,
+    /// Ports sent with the initial graph
+    synced_ports: HashSet<PortId>,
+    /// Connections sent with the initial graph
+    synced_connections: HashSet<(PortId, PortId)>,
 }
 
-impl JackNotificationController {
-    pub fn new(pipe: Sender<JackEvent>) -> Self {
-        Self { pipe }
+impl Backlog {
+    /// Hold back callback events until `initial_sync` runs
+    pub fn hold(&mut self) {
+        *self = Self {
+            queue: Some(vec![]),
+            ..Default::default()
+        };
     }
 
-    fn identify_port(&self, p: &JackPort<Unowned>) -> Result<(PortType, PortDirection), JackError> {
-        let port_type = match p.port_type()?.as_str() {
-            "32 bit float mono audio" => PortType::Audio,
-            "8 bit raw midi" => PortType::Midi,
-            e => {
-                warn!("Unknown port type: {}", e);
-                PortType::Unknown
+    /// Whether a callback event is already part of the initial graph
+    fn synced(&mut self, e: &JackEvent) -> bool {
+        match e {
+            JackEvent::AddPort(port) => self.synced_ports.remove(&port.id),
+            JackEvent::AddConnection(a, b) => self.synced_connections.remove(&(*a, *b)),
+            JackEvent::DelPort(id) => {
+                self.synced_ports.remove(id);
+                self.synced_connections.retain(|(a, b)| a != id && b != id);
+                false
             }
-        };
+            JackEvent::DelConnection(a, b) => {
+                self.synced_connections.remove(&(*a, *b));
+                false
+            }
+            _ => false,
+        }
+    }
+}
 
-        trace!("=== {:?} ===", p.name());
-        let flags = p.flags();
-        let port_dir = if flags.contains(PortFlags::IS_OUTPUT) {
-            PortDirection::Output
-        } else {
-            PortDirection::Input
-        };
+pub struct JackNotificationController {
+    pipe: Sender<JackEvent>,
+    /// Set when the server shuts the client down
+    dead: Arc<AtomicBool>,
+    /// Shared with `initial_sync`
+    backlog: Arc<Mutex<Backlog>>,
+}
 
-        Ok((port_type, port_dir))
+impl JackNotificationController {
+    pub fn new(
+        pipe: Sender<JackEvent>,
+        dead: Arc<AtomicBool>,
+        backlog: Arc<Mutex<Backlog>>,
+    ) -> Self {
+        Self {
+            pipe,
+            dead,
+            backlog,
+        }
     }
 
     fn sync_send(&mut self, e: JackEvent) {
-        async_std::task::block_on(async {
+        {
+            let mut backlog = self.backlog.lock().unwrap();
+            if backlog.synced(&e) {
+                return;
+            }
+            // Once the queue is gone everything before us was sent
+            if let Some(queue) = &mut backlog.queue {
+                queue.push(e);
+                return;
+            }
+        }
+
+        task::block_on(async {
             match self.pipe.send(e).await {
                 Ok(_) => (),
                 Err(e) => {
@@ -46,7 +107,208 @@ impl JackNotificationController {
     }
 }
 
+fn identify_port(p: &JackPort<Unowned>) -> Result<(PortType, PortDirection), JackError> {
+    let port_type = match p.port_type()?.as_str() {
+        "32 bit float mono audio" => PortType::Audio,
+        "8 bit raw midi" => PortType::Midi,
+        e => {
+            warn!("Unknown port type: {}", e);
+            PortType::Unknown
+        }
+    };
+
+    trace!("=== {:?} ===", p.name());
+    let flags = p.flags();
+    let port_dir = if flags.contains(PortFlags::IS_OUTPUT) {
+        PortDirection::Output
+    } else {
+        PortDirection::Input
+    };
+
+    Ok((port_type, port_dir))
+}
+
+/// Turn a jack port into a model port
+fn make_port(port_id: PortId, jack_port: &JackPort<Unowned>) -> Option<Port> {
+    let name = match jack_port.name() {
+        Ok(n) => n,
+        Err(e) => {
+            error!("Jack refused to give name for port {}: {}", port_id, e);
+            return None;
+        }
+    };
+
+    let names: Vec<&str> = name.splitn(2, ":").collect();
+    let client_name = names[0];
+    let port_name = names.get(1).unwrap_or(&"");
+
+    let (tt, dir) = match identify_port(&jack_port) {
+        Ok(pt) => pt,
+        Err(e) => {
+            error!("Error identifying port {} \"{}\": {}", port_id, name, e);
+            return None;
+        }
+    };
+
+    // TODO: is this hardware?
+    Some(Port::new(
+        client_name.to_owned(),
+        port_name.to_string(),
+        port_id,
+        tt,
+        dir,
+        false,
+    ))
+}
+
+/// Find the IDs of all ports that currently exist on the server
+///
+/// Jack has no call to get the ID of a port, so we probe IDs until
+/// every port that `Client::ports` lists has been found.
+fn existing_ports(client: &Client) -> Vec<(PortId, JackPort<Unowned>)> {
+    let mut names: HashSet<String> = client
+        .ports(None, None, PortFlags::empty())
+        .into_iter()
+        .collect();
+
+    let mut found = vec![];
+    for id in 1..MAX_PORT_ID {
+        if names.is_empty() {
+            break;
+        }
+
+        if let Some(port) = client.port_by_id(id) {
+            if port.name().map_or(false, |name| names.remove(&name)) {
+                found.push((id, port));
+            }
+        }
+    }
+
+    for name in &names {
+        warn!("Could not find the ID of existing port {}", name);
+    }
+
+    found
+}
+
+/// Send events for all ports and connections that existed before we
+/// attached to the server, followed by the callbacks held back since
+pub async fn initial_sync(jack: Arc<JackRuntime>) {
+    // Sending waits for the model, keep it off the executor
+    task::spawn_blocking(move || send_graph(&jack)).await
+}
+
+fn send_graph(jack: &JackRuntime) {
+    let mut events = vec![];
+    let found = jack.with_client(|client| {
+        let ports = existing_ports(client);
+        let ids: HashMap<String, PortId> = ports
+            .iter()
+            .filter_map(|(id, p)| p.name().ok().map(|n| (n, *id)))
+            .collect();
+
+        for (id, jack_port) in ports.iter() {
+            if let Some(port) = make_port(*id, jack_port) {
+                events.push(JackEvent::AddPort(port));
+            }
+        }
+
+        // Only look at outputs to see every connection once
+        for (id, jack_port) in ports.iter() {
+            if !jack_port.flags().contains(PortFlags::IS_OUTPUT) {
+                continue;
+            }
+
+            for other in jack_port.get_connections() {
+                match ids.get(&other) {
+                    // Like ports_connected, report (destination, source)
+                    Some(other) => events.push(JackEvent::AddConnection(*other, *id)),
+                    None => warn!("Connection to unknown port {}", other),
+                }
+            }
+        }
+    });
+    if found.is_none() {
+        warn!("No jack client to find the existing ports with");
+        jack.backlog.lock().unwrap().queue = None;
+        return;
+    }
+
+    // Leave out ports that went away while we were looking
+    let gone: HashSet<PortId> = jack
+        .with_client(|client| {
+            events
+                .iter()
+                .filter_map(|e| match e {
+                    JackEvent::AddPort(p) if client.port_by_name(&p.full_name()).is_none() => {
+                        Some(p.id)
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    events.retain(|e| match e {
+        JackEvent::AddPort(p) => !gone.contains(&p.id),
+        JackEvent::AddConnection(a, b) => !gone.contains(a) && !gone.contains(b),
+        _ => true,
+    });
+    info!("Found {} existing ports and connections", events.len());
+
+    // Callbacks keep queueing until everything before them is sent,
+    // but aren't held up while the model takes its time
+    let mut pending = {
+        let mut backlog = jack.backlog.lock().unwrap();
+        for e in &events {
+            match e {
+                JackEvent::AddPort(p) => {
+                    backlog.synced_ports.insert(p.id);
+                }
+                JackEvent::AddConnection(a, b) => {
+                    backlog.synced_connections.insert((*a, *b));
+                }
+                _ => (),
+            }
+        }
+        let queued = backlog.queue.as_mut().map(std::mem::take);
+        let queued: Vec<_> = queued
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|e| !backlog.synced(e))
+            .collect();
+        events.extend(queued);
+        events
+    };
+
+    loop {
+        for e in pending {
+            if let Err(e) = task::block_on(jack.event_tx.send(e)) {
+                error!("Failed to send initial jack graph: {}", e);
+                jack.backlog.lock().unwrap().queue = None;
+                return;
+            }
+        }
+
+        let mut backlog = jack.backlog.lock().unwrap();
+        pending = backlog
+            .queue
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default();
+        if pending.is_empty() {
+            backlog.queue = None;
+            return;
+        }
+    }
+}
+
 impl NotificationHandler for JackNotificationController {
+    unsafe fn shutdown(&mut self, _status: ClientStatus, _reason: &str) {
+        // This runs like a signal handler, so only leave a note for
+        // the runtime to pick up
+        self.dead.store(true, Ordering::Release);
+    }
+
     fn client_registration(&mut self, _: &jack::Client, _name: &str, _is_registered: bool) {
         trace!("EVENT: client_registration {}, {}", _name, _is_registered);
     }
@@ -63,36 +325,9 @@ impl NotificationHandler for JackNotificationController {
                 }
             };
 
-            let name = match jack_port.name() {
-                Ok(n) => n,
-                Err(e) => {
-                    error!("Jack refused to give name for port {}: {}", port_id, e);
-                    return;
-                }
-            };
-
-            let names: Vec<&str> = name.split(":").collect();
-            let client_name = names[0];
-            let port_name = names[1];
-
-            let (tt, dir) = match self.identify_port(&jack_port) {
-                Ok(pt) => pt,
-                Err(e) => {
-                    error!("Error identifying port {} \"{}\": {}", port_id, name, e);
-                    return;
-                }
-            };
-
-            // TODO: is this hardware?
-            let port = Port::new(
-                client_name.to_owned(),
-                port_name.to_owned(),
-                port_id,
-                tt,
-                dir,
-                false,
-            );
-            self.sync_send(JackEvent::AddPort(port));
+            if let Some(port) = make_port(port_id, &jack_port) {
+                self.sync_send(JackEvent::AddPort(port));
+            }
         } else {
             self.sync_send(JackEvent::DelPort(port_id));
         }
@@ -15,24 +15,23 @@ pub async fn spawn_handle(jack: Arc<JackRuntime>) {
                     in_ports,
                     out_ports,
                     rate,
+                    n_periods,
+                    resample_q,
                 },
                 r,
             ) => {
-                let result = launch_card(
-                    &jack.a_client.as_client(),
-                    &id,
-                    &name,
-                    rate,
-                    in_ports,
-                    out_ports,
-                    jack.n_periods,
-                    jack.resample_q,
-                );
-                r.reply(result).await.unwrap();
+                let result = jack.with_client(|c| {
+                    launch_card(
+                        c, &id, &name, rate, in_ports, out_ports, n_periods, resample_q,
+                    )
+                });
+                r.reply(result.unwrap_or(Err(jack::Error::UnknownError)))
+                    .await
+                    .unwrap();
             }
             (JackCardAction::StopCard { id }, r) => {
                 info!("Stopping card {}", id);
-                stop_card(&jack.a_client.as_client(), id);
+                jack.with_client(|c| stop_card(c, id));
                 r.reply(Ok(0)).await.unwrap();
             }
         }
@@ -5,7 +5,10 @@ use crate::model::{
 use crate::rts::jack::JackRuntime;
 use async_std::task;
 use jack::Client;
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
 
 pub async fn do_event(jack: Arc<JackRuntime>) {
     loop {
@@ -13,12 +16,19 @@ pub async fn do_event(jack: Arc<JackRuntime>) {
             break;
         }
 
-        let settings = interval_update(&jack);
+        // The server shut us down, bring it back in the background
+        if jack.dead.swap(false, Ordering::AcqRel) {
+            let jack = Arc::clone(&jack);
+            task::spawn(async move { jack.recover().await });
+        }
 
-        jack.event_tx
-            .send(JackEvent::JackSettings(settings))
-            .await
-            .unwrap();
+        // Nothing to report without a server
+        if let Some(settings) = jack.with_client(|c| interval_update(c, &jack)) {
+            jack.event_tx
+                .send(JackEvent::JackSettings(settings))
+                .await
+                .unwrap();
+        }
 
         // this rate limits updates to the mixers, we don't need to update at 100 FPS
         task::sleep(Duration::from_millis(100)).await;
@@ -35,10 +45,24 @@ pub async fn spawn_handle(jack: Arc<JackRuntime>) {
                 output,
                 connect,
             } => {
-                connect_ports(&jack.a_client.as_client(), output, input, connect);
+                let done = jack.with_client(|c| connect_ports(c, output, input, connect));
+                if done.is_none() {
+                    error!("Can't change a connection without a jack server");
+                }
                 trace!("Connect ports...");
             }
+            JackCmd::Restart => {
+                info!("Restarting the jack server");
+                // Waiting for a server must not hold up other commands
+                let jack = Arc::clone(&jack);
+                task::spawn(async move {
+                    if let Err(e) = jack.restart().await {
+                        error!("Failed to restart the jack server: {}", e);
+                    }
+                });
+            }
             JackCmd::Shutdown => {
+                jack.stop();
                 break;
             }
         }
@@ -68,12 +92,12 @@ fn connect_ports(client: &Client, input: JackPortType, output: JackPortType, con
     }
 }
 
-fn interval_update(jack: &Arc<JackRuntime>) -> JackSettings {
-    let client = jack.a_client.as_client();
+fn interval_update(client: &Client, jack: &JackRuntime) -> JackSettings {
     let cpu_percentage = client.cpu_load();
     let sample_rate = client.sample_rate() as u64;
     let buffer_size = client.buffer_size() as u64;
-    let latency = (buffer_size) as f32 / (sample_rate as f32 / 1000.0) * jack.n_periods as f32;
+    let n_periods = jack.n_periods.load(Ordering::Relaxed);
+    let latency = (buffer_size) as f32 / (sample_rate as f32 / 1000.0) * n_periods as f32;
 
     JackSettings {
         cpu_percentage,
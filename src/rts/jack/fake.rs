@@ -0,0 +1,306 @@
+//! An in-process stand-in for a JACK server
+//!
+//! The fake keeps its own port graph and reports changes to it with
+//! the same events the real notification handler sends.  It does not
+//! need a running `jackd`, which makes it useful for testing the
+//! model.
+
+use crate::model::{
+    events::{JackCardAction, JackCmd, JackEvent},
+    port::{JackPortType, Port, PortDirection, PortType},
+};
+use crate::rts::jack::JackBackend;
+use async_std::channel::{unbounded, Receiver, Sender};
+use futures::future::{BoxFuture, FutureExt};
+use jack::InternalClientID;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Default)]
+struct FakeGraph {
+    /// All ports currently registered
+    ports: BTreeMap<JackPortType, Port>,
+    /// Connections between ports as (source, destination)
+    connections: BTreeSet<(JackPortType, JackPortType)>,
+    /// Internal clients loaded via card actions, and their ports
+    clients: BTreeMap<InternalClientID, (String, Vec<JackPortType>)>,
+    /// Every command the model has sent so far
+    cmds: Vec<JackCmd>,
+    /// Make internal client loading fail
+    fail_cards: bool,
+    /// Card the server pretends to run on
+    master: Option<String>,
+    /// Pretend there is no server yet
+    stopped: bool,
+    next_port: JackPortType,
+    next_client: InternalClientID,
+}
+
+#[derive(Clone, Debug)]
+pub struct FakeJack {
+    graph: Arc<Mutex<FakeGraph>>,
+    event_tx: Sender<JackEvent>,
+    event_rx: Receiver<JackEvent>,
+}
+
+impl FakeJack {
+    /// Create an empty fake server
+    pub fn new() -> Self {
+        let (event_tx, event_rx) = unbounded();
+        Self {
+            graph: Default::default(),
+            event_tx,
+            event_rx,
+        }
+    }
+
+    fn send(&self, ev: JackEvent) {
+        let _ = self.event_tx.try_send(ev);
+    }
+
+    /// Register a new port and return its id
+    pub fn add_port(
+        &self,
+        client: &str,
+        port: &str,
+        tt: PortType,
+        dir: PortDirection,
+    ) -> JackPortType {
+        let mut g = self.graph.lock().unwrap();
+        g.next_port += 1;
+        let id = g.next_port;
+
+        let port = Port::new(client.into(), port.into(), id, tt, dir, false);
+        g.ports.insert(id, port.clone());
+        self.send(JackEvent::AddPort(port));
+        id
+    }
+
+    /// Unregister a port, dropping all of its connections first
+    pub fn del_port(&self, id: JackPortType) {
+        let mut g = self.graph.lock().unwrap();
+        let dropped: Vec<_> = g
+            .connections
+            .iter()
+            .filter(|(src, dst)| *src == id || *dst == id)
+            .cloned()
+            .collect();
+
+        for (src, dst) in dropped {
+            g.connections.remove(&(src, dst));
+            self.send(JackEvent::DelConnection(dst, src));
+        }
+
+        if g.ports.remove(&id).is_some() {
+            self.send(JackEvent::DelPort(id));
+        }
+    }
+
+    /// Change a connection as if some other JACK client did it
+    pub fn set_connection(&self, src: JackPortType, dst: JackPortType, connect: bool) {
+        let mut g = self.graph.lock().unwrap();
+        self.connect_inner(&mut g, src, dst, connect);
+    }
+
+    fn connect_inner(
+        &self,
+        g: &mut FakeGraph,
+        src: JackPortType,
+        dst: JackPortType,
+        connect: bool,
+    ) {
+        if !g.ports.contains_key(&src) || !g.ports.contains_key(&dst) {
+            error!("Fake connection {}->{} has a missing port", src, dst);
+            return;
+        }
+
+        // Like jack, we report (destination, source)
+        if connect && g.connections.insert((src, dst)) {
+            self.send(JackEvent::AddConnection(dst, src));
+        } else if !connect && g.connections.remove(&(src, dst)) {
+            self.send(JackEvent::DelConnection(dst, src));
+        }
+    }
+
+    /// Restart the server like the real runtime does
+    ///
+    /// Internal clients die with the server.  All other clients come
+    /// back, with new port ids and without their connections.
+    fn restart_inner(&self, g: &mut FakeGraph) {
+        let internal: BTreeSet<JackPortType> = g
+            .clients
+            .values()
+            .flat_map(|(_, ports)| ports.iter().cloned())
+            .collect();
+        let survivors: Vec<Port> = g
+            .ports
+            .values()
+            .filter(|p| !internal.contains(&p.id))
+            .cloned()
+            .collect();
+
+        g.ports.clear();
+        g.connections.clear();
+        g.clients.clear();
+        self.send(JackEvent::ServerStopped);
+        self.send(JackEvent::ServerStarted);
+
+        for mut port in survivors {
+            g.next_port += 1;
+            port.id = g.next_port;
+            g.ports.insert(port.id, port.clone());
+            self.send(JackEvent::AddPort(port));
+        }
+    }
+
+    /// Take the server away, or bring it back
+    ///
+    /// Unlike a restart, no ports are added or removed.
+    pub fn set_running(&self, running: bool) {
+        let mut g = self.graph.lock().unwrap();
+        if g.stopped == running {
+            g.stopped = !running;
+            self.send(match running {
+                true => JackEvent::ServerStarted,
+                false => JackEvent::ServerStopped,
+            });
+        }
+    }
+
+    /// Simulate a server overrun
+    pub fn xrun(&self) {
+        self.send(JackEvent::XRun);
+    }
+
+    /// Make all following internal client loads fail
+    pub fn fail_card_loads(&self, fail: bool) {
+        self.graph.lock().unwrap().fail_cards = fail;
+    }
+
+    /// Pretend the server runs directly on a card
+    pub fn set_master_card(&self, name: Option<&str>) {
+        self.graph.lock().unwrap().master = name.map(Into::into);
+    }
+
+    /// Get a port by its full `client:port` name
+    pub fn port_by_name(&self, name: &str) -> Option<JackPortType> {
+        let g = self.graph.lock().unwrap();
+        g.ports
+            .values()
+            .find(|p| format!("{}:{}", p.client_name, p.port_name) == name)
+            .map(|p| p.id)
+    }
+
+    /// Check whether two ports are connected
+    pub fn is_connected(&self, src: JackPortType, dst: JackPortType) -> bool {
+        self.graph.lock().unwrap().connections.contains(&(src, dst))
+    }
+
+    /// Get the names of all loaded internal clients
+    pub fn loaded_clients(&self) -> Vec<(InternalClientID, String)> {
+        let g = self.graph.lock().unwrap();
+        g.clients
+            .iter()
+            .map(|(id, (name, _))| (*id, name.clone()))
+            .collect()
+    }
+
+    /// Get a copy of all commands received so far
+    pub fn commands(&self) -> Vec<JackCmd> {
+        self.graph.lock().unwrap().cmds.clone()
+    }
+
+    fn load_card(&self, name: &str, in_ports: u32, out_ports: u32) -> Option<InternalClientID> {
+        if self.graph.lock().unwrap().fail_cards {
+            return None;
+        }
+
+        // audioadapter registers capture ports as outputs and
+        // playback ports as inputs
+        let mut ports = vec![];
+        for i in 1..=in_ports {
+            let name_ = format!("capture_{}", i);
+            ports.push(self.add_port(name, &name_, PortType::Audio, PortDirection::Output));
+        }
+        for i in 1..=out_ports {
+            let name_ = format!("playback_{}", i);
+            ports.push(self.add_port(name, &name_, PortType::Audio, PortDirection::Input));
+        }
+
+        let mut g = self.graph.lock().unwrap();
+        g.next_client += 1;
+        let id = g.next_client;
+        g.clients.insert(id, (name.into(), ports));
+        Some(id)
+    }
+
+    fn unload_card(&self, id: InternalClientID) {
+        let client = self.graph.lock().unwrap().clients.remove(&id);
+        match client {
+            Some((_, ports)) => ports.into_iter().for_each(|p| self.del_port(p)),
+            None => error!("Fake jack has no internal client {}", id),
+        }
+    }
+}
+
+impl JackBackend for FakeJack {
+    fn send_cmd(&self, cmd: JackCmd) -> BoxFuture<'_, ()> {
+        async move {
+            let mut g = self.graph.lock().unwrap();
+            g.cmds.push(cmd.clone());
+
+            match cmd {
+                // See `cmd::spawn_handle`: `input` is the source port
+                JackCmd::ConnectPorts {
+                    input,
+                    output,
+                    connect,
+                } => self.connect_inner(&mut g, input, output, connect),
+                JackCmd::Restart => self.restart_inner(&mut g),
+                JackCmd::Shutdown => {}
+            }
+        }
+        .boxed()
+    }
+
+    fn next_event(&self) -> BoxFuture<'_, Option<JackEvent>> {
+        async move { self.event_rx.recv().await.ok() }.boxed()
+    }
+
+    fn send_card_action(
+        &self,
+        action: JackCardAction,
+    ) -> BoxFuture<'_, Result<InternalClientID, jack::Error>> {
+        async move {
+            match action {
+                JackCardAction::StartCard {
+                    name,
+                    in_ports,
+                    out_ports,
+                    ..
+                } => self
+                    .load_card(&name, in_ports, out_ports)
+                    .ok_or(jack::Error::UnknownError),
+                JackCardAction::StopCard { id } => {
+                    self.unload_card(id);
+                    Ok(0)
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn master_card(&self) -> Option<String> {
+        self.graph.lock().unwrap().master.clone()
+    }
+
+    fn running(&self) -> bool {
+        !self.graph.lock().unwrap().stopped
+    }
+
+    fn close(&self) {
+        self.event_tx.close();
+    }
+}
@@ -1,19 +1,82 @@
 mod async_client;
 mod card;
 mod cmd;
+#[cfg(test)]
+mod fake;
 mod server;
 
-use self::async_client::JackNotificationController;
-use self::server::JackServer;
+#[cfg(test)]
+pub use fake::FakeJack;
+
+use self::async_client::{Backlog, JackNotificationController};
+use self::server::{Driver, JackServer};
 use crate::cb_channel::{self, ReturningReceiver, ReturningSender};
-use crate::model::events::{JackCardAction, JackCmd, JackEvent};
-use crate::settings::Settings;
+use crate::model::events::{JackCardAction, JackCmd, JackEvent, ServerStatus};
+use crate::rts::hardware;
+use crate::settings::{Settings, SpawnMode};
 use async_std::{
     channel::{bounded, Receiver, Sender},
     task,
 };
+use futures::future::{BoxFuture, FutureExt};
 use jack::{AsyncClient, Client as JackClient, InternalClientID};
-use std::sync::Arc;
+use std::{
+    fmt::Debug,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// How often to look for a server in `SpawnMode::Wait`
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often to try connecting to a server that was just found
+const CONNECT_TRIES: u32 = 10;
+
+/// Give up bringing jack back after this many attempts in a row
+const MAX_RECOVERIES: u32 = 5;
+
+/// Wait before the first attempt, doubled for every following one
+const RECOVERY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A server that stayed up this long resets the attempts
+const RECOVERY_RESET: Duration = Duration::from_secs(60);
+
+/// A jack runtime the model can drive
+///
+/// Handles are cheap to clone, and all clones talk to the same
+/// runtime.
+pub trait JackBackend: Clone + Debug + Send + Sync + 'static {
+    /// Send a jack command to the runtime
+    fn send_cmd(&self, cmd: JackCmd) -> BoxFuture<'_, ()>;
+
+    /// Wait for the next jack event
+    fn next_event(&self) -> BoxFuture<'_, Option<JackEvent>>;
+
+    /// Send a card action and wait for the reply
+    fn send_card_action(
+        &self,
+        action: JackCardAction,
+    ) -> BoxFuture<'_, Result<InternalClientID, jack::Error>>;
+
+    /// The name of the card jackd runs on directly, if any
+    ///
+    /// That card's ports belong to the `system` client, so it must
+    /// not be started with `audioadapter` as well.
+    fn master_card(&self) -> Option<String>;
+
+    /// Whether jackctl is connected to a server
+    ///
+    /// Cards can only be started while it is.
+    fn running(&self) -> bool;
+
+    /// Close the channels to the jack runtime
+    fn close(&self);
+}
 
 /// An easily clonable handle to the jack runtime
 #[derive(Clone, Debug)]
@@ -24,28 +87,48 @@ pub struct JackHandle {
     event_rx: Receiver<JackEvent>,
     /// Send card actions to jack runtime with blocking ACK
     card_tx: ReturningSender<JackCardAction, Result<InternalClientID, jack::Error>>,
+    /// The card jackd was started on
+    master: Arc<RwLock<Option<String>>>,
+    /// Whether the runtime has a client
+    running: Arc<AtomicBool>,
+    /// Closed once the runtime has shut down
+    stopped_rx: Receiver<()>,
 }
 
-impl JackHandle {
-    /// Send a jack command to the associated task
-    pub async fn send_cmd(&self, cmd: JackCmd) {
-        self.cmd_tx.send(cmd).await.unwrap();
+impl JackBackend for JackHandle {
+    fn send_cmd(&self, cmd: JackCmd) -> BoxFuture<'_, ()> {
+        async move {
+            let shutdown = matches!(cmd, JackCmd::Shutdown);
+            self.cmd_tx.send(cmd).await.unwrap();
+
+            // jackctl must not exit before its server is stopped
+            if shutdown {
+                let _ = self.stopped_rx.recv().await;
+            }
+        }
+        .boxed()
     }
 
-    /// Wait for the next jack event
-    pub async fn next_event(&self) -> Option<JackEvent> {
-        self.event_rx.recv().await.ok()
+    fn next_event(&self) -> BoxFuture<'_, Option<JackEvent>> {
+        async move { self.event_rx.recv().await.ok() }.boxed()
     }
 
-    /// Send a card action and wait for the reply
-    pub async fn send_card_action(
+    fn send_card_action(
         &self,
         action: JackCardAction,
-    ) -> Result<InternalClientID, jack::Error> {
-        self.card_tx.send(action).await.unwrap()
+    ) -> BoxFuture<'_, Result<InternalClientID, jack::Error>> {
+        async move { self.card_tx.send(action).await.unwrap() }.boxed()
+    }
+
+    fn master_card(&self) -> Option<String> {
+        self.master.read().unwrap().clone()
+    }
+
+    fn running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
     }
 
-    pub fn close(&self) {
+    fn close(&self) {
         self.cmd_tx.close();
         self.event_rx.close();
         self.card_tx.close();
@@ -55,15 +138,34 @@ impl JackHandle {
 /// Jack server runtime and signalling state
 #[derive(Debug)]
 pub struct JackRuntime {
-    #[allow(unused)]
     /// reference for the jack server, server will stop when dropped
-    server: JackServer,
-    /// Resample Quality fetched from settings on boot
-    resample_q: u32,
+    server: Mutex<Option<JackServer>>,
+    /// Settings to (re)start the server with
+    settings: Arc<Settings>,
+    /// Where the PID of a spawned server is kept
+    pid_file: Option<PathBuf>,
     /// number of periods per frame, fetched from settings on boot
-    n_periods: u32,
-    /// Async jack client
-    a_client: AsyncClient<JackNotificationController, ()>,
+    n_periods: AtomicU32,
+    /// Async jack client, `None` until a server is found and while it restarts
+    a_client: RwLock<Option<AsyncClient<JackNotificationController, ()>>>,
+    /// The card jackd runs on, shared with the handles
+    master: Arc<RwLock<Option<String>>>,
+    /// Whether `a_client` is set, shared with the handles
+    running: Arc<AtomicBool>,
+    /// Set while a server is being found or started
+    booting: AtomicBool,
+    /// Bumped to call off waiting for a server
+    generation: AtomicU32,
+    /// Set by the notification handler when the server shuts us down
+    dead: Arc<AtomicBool>,
+    /// Attempts to bring jack back since it last stayed up
+    recoveries: AtomicU32,
+    /// When the current server was connected to
+    up_since: Mutex<Option<Instant>>,
+    /// Orders callback events with the initial graph
+    backlog: Arc<Mutex<Backlog>>,
+    /// Closed once the runtime has shut down
+    stopped_tx: Sender<()>,
     /// Receive jack commands
     cmd_rx: Receiver<JackCmd>,
     /// Send events to the model layer
@@ -73,44 +175,354 @@ pub struct JackRuntime {
 }
 
 impl JackRuntime {
-    pub fn start(settings: Arc<Settings>) -> Result<JackHandle, jack::Error> {
-        // start the server first
-        let app_settings = settings.r().app();
-        let jack_settings = &app_settings.jack;
-        let server = server::JackServer::new(
-            jack_settings.sample_rate,
-            jack_settings.period_size,
-            jack_settings.realtime,
-        );
+    /// Start the runtime, the server is found or started in the background
+    ///
+    /// The PID of a spawned jackd is kept in `run_dir`.
+    pub fn start(settings: Arc<Settings>, run_dir: Option<&Path>) -> JackHandle {
+        let pid_file = server::init(run_dir);
+        let master = Arc::new(RwLock::new(None));
+        let running = Arc::new(AtomicBool::new(false));
 
         // Open the channels
         let (event_tx, event_rx) = bounded(128);
         let (cmd_tx, cmd_rx) = bounded(128);
         let (card_tx, card_rx) = cb_channel::bounded(128);
-
-        // initialise jack
-        let a_client = async_client::JackNotificationController::new(event_tx.clone());
-        let (client, _) = JackClient::new("jackctl", jack::ClientOptions::NO_START_SERVER)?;
-        let a_client = client.activate_async(a_client, ())?;
+        let (stopped_tx, stopped_rx) = bounded(1);
 
         // Initialise and bootstrap the jack runtime
         Arc::new(Self {
-            server,
-            a_client,
+            server: Mutex::new(None),
+            n_periods: AtomicU32::new(settings.r().app().jack.n_periods),
+            settings,
+            pid_file,
+            a_client: RwLock::new(None),
+            master: Arc::clone(&master),
+            running: Arc::clone(&running),
+            booting: AtomicBool::new(false),
+            generation: AtomicU32::new(0),
+            dead: Arc::new(AtomicBool::new(false)),
+            recoveries: AtomicU32::new(0),
+            up_since: Mutex::new(None),
+            backlog: Default::default(),
+            stopped_tx,
             cmd_rx,
             event_tx,
             card_rx,
-            n_periods: jack_settings.n_periods,
-            resample_q: jack_settings.resample_q,
         })
         .bootstrap();
 
         // Return a sending handle
-        Ok(JackHandle {
+        JackHandle {
             cmd_tx,
             event_rx,
             card_tx,
-        })
+            master,
+            running,
+            stopped_rx,
+        }
+    }
+
+    /// Start jackd with the current settings
+    fn launch(
+        settings: &Arc<Settings>,
+        mode: SpawnMode,
+        pid_file: Option<PathBuf>,
+    ) -> io::Result<JackServer> {
+        let (driver, rate) = Self::driver(settings);
+        let app_settings = settings.r().app();
+        let jack_settings = &app_settings.jack;
+        server::JackServer::new(
+            mode,
+            driver,
+            rate,
+            jack_settings.period_size,
+            jack_settings.realtime,
+            pid_file,
+        )
+    }
+
+    /// Open and activate the jackctl client
+    fn connect(&self) -> Result<AsyncClient<JackNotificationController, ()>, jack::Error> {
+        self.backlog.lock().unwrap().hold();
+        let handler = async_client::JackNotificationController::new(
+            self.event_tx.clone(),
+            Arc::clone(&self.dead),
+            Arc::clone(&self.backlog),
+        );
+        let (client, _) = JackClient::new("jackctl", jack::ClientOptions::NO_START_SERVER)?;
+        client.activate_async(handler, ())
+    }
+
+    /// Run `f` with the jack client, unless there is no server
+    fn with_client<T>(&self, f: impl FnOnce(&JackClient) -> T) -> Option<T> {
+        self.a_client
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|c| f(c.as_client()))
+    }
+
+    /// Tell the model what is happening to the server
+    async fn status(&self, status: ServerStatus) {
+        info!("{}", status);
+        let _ = self.event_tx.send(JackEvent::ServerStatus(status)).await;
+    }
+
+    /// Find or start a server as the spawn mode says, and connect to it
+    ///
+    /// The model is told once the server runs, followed by all of its
+    /// ports.  Does nothing if the runtime is already looking for one.
+    async fn boot(self: &Arc<Self>, mode: SpawnMode) -> Result<(), jack::Error> {
+        if self.booting.swap(true, Ordering::AcqRel) {
+            warn!("Already looking for a jack server");
+            return Ok(());
+        }
+        let res = self.find_server(mode).await;
+        self.booting.store(false, Ordering::Release);
+        res
+    }
+
+    async fn find_server(self: &Arc<Self>, mode: SpawnMode) -> Result<(), jack::Error> {
+        match (mode, server::is_running()) {
+            (SpawnMode::Wait, false) => {
+                self.status(ServerStatus::Waiting).await;
+                if !self.wait_for_server().await {
+                    debug!("Stopped waiting for a jack server");
+                    return Ok(());
+                }
+            }
+            (SpawnMode::ForceSpawn, true) => self.status(ServerStatus::Replacing).await,
+            (SpawnMode::Wait, true) | (SpawnMode::SoftSpawn, true) => {}
+            (_, false) => self.status(ServerStatus::Starting).await,
+        }
+
+        // Stopping other servers and starting jackd takes a while
+        let (settings, pid_file) = (Arc::clone(&self.settings), self.pid_file.clone());
+        let launched = task::spawn_blocking(move || Self::launch(&settings, mode, pid_file));
+        let server = match launched.await {
+            Ok(server) => server,
+            Err(e) => {
+                let e = format!("can't run jackd: {}", e);
+                self.status(ServerStatus::Failed(e)).await;
+                return Err(jack::Error::UnknownError);
+            }
+        };
+        let spawned = server.spawned();
+        *self.master.write().unwrap() = server.master().map(Into::into);
+        *self.server.lock().unwrap() = Some(server);
+        self.n_periods
+            .store(self.settings.r().app().jack.n_periods, Ordering::Relaxed);
+
+        // A server that was only just found may not take clients yet
+        let mut tries = 1;
+        let a_client = loop {
+            match self.connect() {
+                Ok(a_client) => break a_client,
+                Err(_) if tries < CONNECT_TRIES => {
+                    tries += 1;
+                    task::sleep(WAIT_INTERVAL / 2).await;
+                }
+                Err(e) => {
+                    self.status(ServerStatus::Failed(e.to_string())).await;
+                    return Err(e);
+                }
+            }
+        };
+
+        let status = if spawned {
+            ServerStatus::Running
+        } else {
+            let app = self.settings.r().app();
+            let (rate, frames) = (app.jack.sample_rate, app.jack.period_size);
+            let client = a_client.as_client();
+            let (sample_rate, period_size) = (client.sample_rate() as u32, client.buffer_size());
+            ServerStatus::External {
+                sample_rate,
+                period_size,
+                wanted: Some((rate, frames)).filter(|w| *w != (sample_rate, period_size)),
+            }
+        };
+        *self.a_client.write().unwrap() = Some(a_client);
+        *self.up_since.lock().unwrap() = Some(Instant::now());
+        self.running.store(true, Ordering::Release);
+        self.status(status).await;
+        let _ = self.event_tx.send(JackEvent::ServerStarted).await;
+
+        async_client::initial_sync(Arc::clone(self)).await;
+        Ok(())
+    }
+
+    /// Wait for another program to start a server
+    ///
+    /// Returns `false` if a restart or shutdown called it off.
+    async fn wait_for_server(&self) -> bool {
+        let generation = self.generation.load(Ordering::Acquire);
+        while !server::is_running() {
+            if self.stopped_tx.is_closed() || self.generation.load(Ordering::Acquire) != generation
+            {
+                return false;
+            }
+            task::sleep(WAIT_INTERVAL).await;
+        }
+        true
+    }
+
+    /// Restart jackd with the current settings and reconnect to it
+    ///
+    /// The model is told when the old server is gone, and again once
+    /// the new one runs.  A server another program started is left
+    /// alone, unless the spawn mode replaces it anyway.
+    async fn restart(self: &Arc<Self>) -> Result<(), jack::Error> {
+        let external = self
+            .server
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(false, |s| !s.spawned());
+        if external && self.spawn_mode() != SpawnMode::ForceSpawn {
+            self.status(ServerStatus::NotRestarted).await;
+            return Ok(());
+        }
+
+        // Stop waiting for a server, this boots one right away
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        while self.booting.load(Ordering::Acquire) {
+            task::sleep(WAIT_INTERVAL / 10).await;
+        }
+
+        // Leave before the server goes away under the client
+        self.running.store(false, Ordering::Release);
+        let old = self.a_client.write().unwrap().take();
+        if let Some(Err(e)) = old.map(AsyncClient::deactivate) {
+            error!("Failed to deactivate the jack client: {}", e);
+        }
+        let _ = self.event_tx.send(JackEvent::ServerStopped).await;
+
+        let old = self.server.lock().unwrap().take();
+        if let Some(mut server) = old {
+            task::spawn_blocking(move || server.end()).await;
+        }
+        // A restart by hand gets a fresh set of recovery attempts
+        self.recoveries.store(0, Ordering::Relaxed);
+        self.boot(self.spawn_mode()).await
+    }
+
+    /// Leave jack and stop the server if we started it
+    fn stop(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.running.store(false, Ordering::Release);
+        let old = self.a_client.write().unwrap().take();
+        if let Some(Err(e)) = old.map(AsyncClient::deactivate) {
+            error!("Failed to deactivate the jack client: {}", e);
+        }
+        if let Some(mut server) = self.server.lock().unwrap().take() {
+            server.end();
+        }
+        self.stopped_tx.close();
+    }
+
+    fn spawn_mode(&self) -> SpawnMode {
+        self.settings.r().app().jack.spawn_mode
+    }
+
+    /// Bring jack back after the server shut us down
+    ///
+    /// A server that jackctl started is started again.  Attempts are
+    /// spaced out further and further, and after `MAX_RECOVERIES` in a
+    /// row the runtime gives up until jack is restarted by hand.  For
+    /// any other server we wait until it comes back.
+    async fn recover(self: &Arc<Self>) {
+        error!("The jack server went away");
+        // A restart or shutdown from here on takes over
+        let generation = self.generation.load(Ordering::Acquire);
+        self.running.store(false, Ordering::Release);
+        let old = self.a_client.write().unwrap().take();
+        if let Some(Err(e)) = old.map(AsyncClient::deactivate) {
+            debug!("Failed to deactivate the dead jack client: {}", e);
+        }
+        let _ = self.event_tx.send(JackEvent::ServerStopped).await;
+
+        // Reap our jackd, whatever is left of it
+        let old = self.server.lock().unwrap().take();
+        let owned = old.as_ref().map_or(false, JackServer::spawned);
+        if let Some(mut server) = old {
+            task::spawn_blocking(move || server.end()).await;
+        }
+
+        if !owned {
+            while let Err(e) = self.boot(SpawnMode::Wait).await {
+                warn!("Failed to connect to the jack server: {}", e);
+                task::sleep(WAIT_INTERVAL).await;
+                if self.generation.load(Ordering::Acquire) != generation {
+                    return;
+                }
+            }
+            return;
+        }
+
+        let stable = self.up_since.lock().unwrap().take();
+        if stable.map_or(false, |t| t.elapsed() >= RECOVERY_RESET) {
+            self.recoveries.store(0, Ordering::Relaxed);
+        }
+
+        loop {
+            let attempt = self.recoveries.fetch_add(1, Ordering::Relaxed) + 1;
+            if attempt > MAX_RECOVERIES {
+                let e = format!("gave up after {} attempts", MAX_RECOVERIES);
+                self.status(ServerStatus::Failed(e)).await;
+                return;
+            }
+
+            let delay = backoff(attempt);
+            self.status(ServerStatus::Recovering {
+                attempt,
+                max: MAX_RECOVERIES,
+                delay,
+            })
+            .await;
+            task::sleep(delay).await;
+            if self.generation.load(Ordering::Acquire) != generation {
+                return;
+            }
+
+            match self.boot(self.spawn_mode()).await {
+                Ok(()) => return,
+                Err(e) => warn!("Attempt {} to bring jack back failed: {}", attempt, e),
+            }
+        }
+    }
+
+    /// Pick the jackd driver and sample rate from the settings
+    ///
+    /// The default card runs at its own rate and periods.  Without a
+    /// default card, or when it's not plugged in, jackd runs on the
+    /// dummy driver at the server rate.
+    fn driver(settings: &Arc<Settings>) -> (Driver, u32) {
+        let app = settings.r().app();
+        let jack = &app.jack;
+        let cards = settings.r().cards();
+        let name = match cards.master() {
+            Some(name) => name,
+            None => return (Driver::Dummy, jack.sample_rate),
+        };
+        let card = match hardware::find_card(name) {
+            Some(card) => card,
+            None => {
+                warn!("Default card {} is missing, using the dummy driver", name);
+                return (Driver::Dummy, jack.sample_rate);
+            }
+        };
+
+        let stored = cards.card(name);
+        let driver = Driver::Alsa {
+            name: name.into(),
+            card,
+            n_periods: stored.and_then(|c| c.n_periods).unwrap_or(jack.n_periods),
+            in_channels: stored.and_then(|c| c.in_channels),
+            out_channels: stored.and_then(|c| c.out_channels),
+        };
+        let rate = stored.and_then(|c| c.rate).unwrap_or(jack.sample_rate);
+        (driver, rate)
     }
 
     /// Bootstrap a smol runtime on a dedicated thread
@@ -128,5 +540,30 @@ impl JackRuntime {
             let rt = Arc::clone(&self);
             task::spawn(async move { cmd::do_event(rt).await });
         }
+        {
+            let rt = Arc::clone(self);
+            task::spawn(async move {
+                if let Err(e) = rt.boot(rt.spawn_mode()).await {
+                    error!("Failed to connect to a jack server: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// How long to wait before an attempt to bring jack back
+fn backoff(attempt: u32) -> Duration {
+    RECOVERY_BACKOFF * 2u32.pow(attempt.saturating_sub(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_backs_off() {
+        assert_eq!(backoff(1), RECOVERY_BACKOFF);
+        assert_eq!(backoff(2), RECOVERY_BACKOFF * 2);
+        assert_eq!(backoff(MAX_RECOVERIES), RECOVERY_BACKOFF * 16);
     }
 }
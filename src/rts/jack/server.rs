@@ -1,90 +1,269 @@
-use once_cell::sync::OnceCell;
-use psutil::process;
+use crate::rts::hardware::CardId;
+use crate::settings::SpawnMode;
+use psutil::process::{self, os::linux::ProcessExt, Process};
+use std::fs;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
 use std::panic;
+use std::path::{Path, PathBuf};
 use std::process::abort;
 use std::process::{Child, ChildStderr, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// How long servers get to shut down before they're killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Name of the file in the runtime directory that holds our jackd's PID
+const PID_FILE: &str = "jackd.pid";
+
+/// The backend jackd runs with
+#[derive(Clone, Debug, PartialEq)]
+pub enum Driver {
+    /// No hardware at all, every card goes through `audioadapter`
+    Dummy,
+    /// Run natively on one ALSA card
+    Alsa {
+        /// Name of the card in the settings
+        name: String,
+        /// ALSA index of the card
+        card: CardId,
+        n_periods: u32,
+        in_channels: Option<u32>,
+        out_channels: Option<u32>,
+    },
+}
+
+impl Driver {
+    /// Driver arguments for jackd
+    fn args(&self, rate: u32, frames: u32) -> Vec<String> {
+        let mut args: Vec<String> = match self {
+            // This magic incantation launches jack with no input or output ports at all
+            Self::Dummy => vec!["-d", "dummy", "-C", "0", "-P", "0"]
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            Self::Alsa {
+                card,
+                n_periods,
+                in_channels,
+                out_channels,
+                ..
+            } => {
+                let mut args = vec![
+                    "-d".into(),
+                    "alsa".into(),
+                    "-d".into(),
+                    format!("hw:{}", card),
+                    "-n".into(),
+                    n_periods.to_string(),
+                ];
+                if let Some(n) = in_channels {
+                    args.extend(vec!["-i".into(), n.to_string()]);
+                }
+                if let Some(n) = out_channels {
+                    args.extend(vec!["-o".into(), n.to_string()]);
+                }
+                args
+            }
+        };
+        args.extend(vec![
+            "-r".into(),
+            rate.to_string(),
+            "-p".into(),
+            frames.to_string(),
+        ]);
+        args
+    }
+}
 
 #[derive(Debug)]
 pub struct JackServer {
     jack_process: Option<Child>,
+    /// The driver the spawned server ended up with
+    driver: Option<Driver>,
+    /// Where the PID of the spawned server is kept
+    pid_file: Option<PathBuf>,
 }
 
-static JACKCTL_SPAWNED_SERVER: OnceCell<bool> = OnceCell::new();
+/// The jackd this process spawned, 0 if there is none
+static SPAWNED_PID: AtomicU32 = AtomicU32::new(0);
 
 fn panic_kill(info: &panic::PanicInfo) -> ! {
     // logs "panicked at '$reason', src/main.rs:27:4" to the host stderr
     error!("{}", info);
 
-    if *JACKCTL_SPAWNED_SERVER.get().unwrap_or(&false) {
+    // Only ever the server we started, never anyone else's.  Its PID
+    // file is cleaned up on the next start.
+    let pid = SPAWNED_PID.swap(0, Ordering::SeqCst);
+    if pid != 0 {
         error!("Killing Local Server");
-        let _ = Command::new("killall").arg("-9").arg("jackd").spawn();
+        if let Ok(server) = Process::new(pid) {
+            stop_process(&server);
+        }
     }
 
     abort();
 }
 
+/// Set up the PID file, stopping a jackd left behind by a crashed jackctl
+///
+/// Only one jackctl runs per session, so a server named in the file
+/// can't belong to a running instance.  Returns the path of the PID
+/// file, to hand to the servers that are started.
+pub fn init(run_dir: Option<&Path>) -> Option<PathBuf> {
+    let dir = match run_dir {
+        Some(dir) => dir,
+        None => {
+            warn!("No runtime directory, jackd can't be cleaned up after a crash");
+            return None;
+        }
+    };
+    let path = dir.join(PID_FILE);
+
+    let pid = fs::read_to_string(&path)
+        .ok()
+        .and_then(|pid| pid.trim().parse().ok());
+    if let Some(pid) = pid {
+        // The PID may have been reused since, even by another user
+        let stale = |p: &Process| p.name().map_or(false, |n| n == "jackd") && same_user(p);
+        match Process::new(pid) {
+            Ok(server) if stale(&server) => {
+                warn!("Stopping jackd {} left behind by an earlier jackctl", pid);
+                stop_process(&server);
+            }
+            _ => debug!("jackd {} from an earlier jackctl is gone", pid),
+        }
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Can't remove {}: {}", path.display(), e);
+        }
+    }
+
+    Some(path)
+}
+
+/// Remember the jackd we spawned, in memory and in the PID file
+fn track(pid: u32, pid_file: Option<&Path>) {
+    SPAWNED_PID.store(pid, Ordering::SeqCst);
+    if let Some(path) = pid_file {
+        let written = path
+            .parent()
+            .map_or(Ok(()), |dir| {
+                fs::DirBuilder::new()
+                    .recursive(true)
+                    .mode(0o700)
+                    .create(dir)
+            })
+            .and_then(|_| fs::write(path, format!("{}\n", pid)));
+        if let Err(e) = written {
+            warn!("Can't write {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Forget the jackd we spawned, once it's stopped
+fn untrack(pid_file: Option<&Path>) {
+    SPAWNED_PID.store(0, Ordering::SeqCst);
+    if let Some(path) = pid_file {
+        let _ = fs::remove_file(path);
+    }
+}
+
 impl JackServer {
-    pub fn new(rate: u32, frames: u32, realtime: bool) -> Self {
+    /// Start jackd as the spawn mode says
+    ///
+    /// `Wait` never starts a server, `SoftSpawn` only starts one if
+    /// none is running, and `ForceSpawn` stops all other servers
+    /// first.  If jackd can't run on a card it's restarted with the
+    /// dummy driver, so that the cards can still be used as adapters.
+    /// Fails if jackd can't be run at all.  The PID of a started server
+    /// is written to `pid_file`.
+    pub fn new(
+        mode: SpawnMode,
+        driver: Driver,
+        rate: u32,
+        frames: u32,
+        realtime: bool,
+        pid_file: Option<PathBuf>,
+    ) -> io::Result<Self> {
         panic::set_hook(Box::new(|pi| {
             panic_kill(pi);
         }));
 
         trace!("process mananager new");
-        let jack_process = if process_is_running("jackd") || process_is_running("jackdbus") {
-            None
+        let start = match mode {
+            SpawnMode::Wait => false,
+            SpawnMode::SoftSpawn => !is_running(),
+            SpawnMode::ForceSpawn => {
+                stop_servers();
+                true
+            }
+        };
+
+        let (jack_process, driver) = if !start {
+            (None, None)
         } else {
-            // get the flag needed for realtime mode and a modifier for logging
-            let (r_flag, r_msg) = if realtime { ("-R", "") } else { ("-r", "out") };
-
-            info!(
-                "starting jackd at {}Hz @{} frames with{} realtime",
-                rate, frames, r_msg
-            );
-            let jack_proc = Command::new("jackd")
-                // This magic incantation launches jack with no input or output ports at all
-                .args(
-                    [
-                        r_flag,
-                        "-d",
-                        "dummy",
-                        "-C",
-                        "0",
-                        "-P",
-                        "0",
-                        "-r",
-                        &rate.to_string(),
-                        "-p",
-                        &frames.to_string(),
-                    ]
-                    .iter(),
-                )
-                //.stdout(Stdio::piped())
-                //.stderr(Stdio::piped())
-                .spawn()
-                .expect("Failed to start jack server");
-
-            // wait for a moment for the server to start else the client might start first
-            thread::sleep(Duration::from_millis(500));
-            Some(jack_proc)
+            match spawn(&driver, rate, frames, realtime)? {
+                Some(p) => (Some(p), Some(driver)),
+                None if driver != Driver::Dummy => {
+                    warn!(
+                        "jackd failed on {:?}, falling back to the dummy driver",
+                        driver
+                    );
+                    let p = spawn(&Driver::Dummy, rate, frames, realtime)?;
+                    (p, Some(Driver::Dummy))
+                }
+                None => (None, None),
+            }
         };
 
-        let _ = JACKCTL_SPAWNED_SERVER.set(jack_process.is_some()); // we don't actually care
+        if let Some(p) = &jack_process {
+            track(p.id(), pid_file.as_deref());
+        }
 
-        Self { jack_process }
+        Ok(Self {
+            jack_process,
+            driver,
+            pid_file,
+        })
+    }
+
+    /// Whether jackctl started this server
+    pub fn spawned(&self) -> bool {
+        self.jack_process.is_some()
+    }
+
+    /// The name of the card jackd runs on directly, if any
+    pub fn master(&self) -> Option<&str> {
+        match &self.driver {
+            Some(Driver::Alsa { name, .. }) => Some(name),
+            _ => None,
+        }
     }
 
+    /// Stop the server if we started it, asking nicely first
     pub fn end(&mut self) {
-        match &mut self.jack_process {
-            Some(p) => {
-                info!("stopping jack server");
-                let _ = p.kill();
-                let _ = p.wait();
+        if let Some(mut p) = self.jack_process.take() {
+            info!("stopping jack server");
+            match Process::new(p.id()).map(|server| server.terminate()) {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => warn!("Failed to stop jackd {}: {}", p.id(), e),
+                Err(e) => warn!("Failed to find jackd {}: {}", p.id(), e),
             }
-            None => (),
+
+            let start = Instant::now();
+            while let Ok(None) = p.try_wait() {
+                if start.elapsed() >= STOP_TIMEOUT {
+                    warn!("jackd {} didn't stop, killing it", p.id());
+                    let _ = p.kill();
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            let _ = p.wait();
+            untrack(self.pid_file.as_deref());
         }
-        self.jack_process = None;
     }
 
     pub fn stderr(&mut self) -> Option<ChildStderr> {
@@ -104,12 +283,106 @@ impl JackServer {
 
 impl Drop for JackServer {
     fn drop(&mut self) {
-        // FIXME: why does this never fire?
+        // The runtime lives until jackctl exits, and stops its server
+        // explicitly on shutdown.  This catches every other server.
         trace!("Dropping jack server");
         self.end();
     }
 }
 
+/// Spawn jackd and wait for it to come up
+///
+/// Returns `None` if it exited right away, for example because the
+/// card is busy, and an error if it can't be run at all.
+fn spawn(driver: &Driver, rate: u32, frames: u32, realtime: bool) -> io::Result<Option<Child>> {
+    // get the flag needed for realtime mode and a modifier for logging
+    let (r_flag, r_msg) = if realtime { ("-R", "") } else { ("-r", "out") };
+
+    info!(
+        "starting jackd at {}Hz @{} frames with{} realtime on {:?}",
+        rate, frames, r_msg, driver
+    );
+    let mut jack_proc = Command::new("jackd")
+        .arg(r_flag)
+        .args(driver.args(rate, frames))
+        //.stdout(Stdio::piped())
+        //.stderr(Stdio::piped())
+        .spawn()?;
+
+    // wait for a moment for the server to start else the client might start first
+    thread::sleep(Duration::from_millis(500));
+    match jack_proc.try_wait() {
+        Ok(Some(status)) => {
+            error!("jackd exited on start-up: {}", status);
+            Ok(None)
+        }
+        _ => Ok(Some(jack_proc)),
+    }
+}
+
+/// Whether a jack server of this user is running
+pub fn is_running() -> bool {
+    !servers().is_empty()
+}
+
+/// All running jack servers of this user
+///
+/// Servers of other users can't be connected to, and aren't ours to stop.
+fn servers() -> Vec<Process> {
+    process::processes()
+        .expect("failed to list processes")
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|p| matches!(p.name().as_deref(), Ok("jackd") | Ok("jackdbus")))
+        .filter(same_user)
+        .collect()
+}
+
+/// Whether a process runs as the same user as jackctl
+fn same_user(p: &Process) -> bool {
+    let uid = |p: &Process| p.procfs_status().map(|s| s.uid[0]);
+    match (uid(p), Process::current().and_then(|me| uid(&me))) {
+        (Ok(theirs), Ok(ours)) => theirs == ours,
+        _ => false,
+    }
+}
+
+/// Stop all running jack servers of this user, killing those that don't stop in time
+fn stop_servers() {
+    let servers = servers();
+    for server in &servers {
+        info!("Stopping the competing jack server {}", server.pid());
+        if let Err(e) = server.terminate() {
+            warn!("Failed to stop jack server {}: {}", server.pid(), e);
+        }
+    }
+
+    let start = Instant::now();
+    while is_running() && start.elapsed() < STOP_TIMEOUT {
+        thread::sleep(Duration::from_millis(100));
+    }
+    for server in servers.iter().filter(|s| s.is_running()) {
+        warn!("jack server {} didn't stop, killing it", server.pid());
+        let _ = server.kill();
+    }
+}
+
+/// Stop a process we don't own, killing it if it doesn't stop in time
+fn stop_process(server: &Process) {
+    if let Err(e) = server.terminate() {
+        warn!("Failed to stop process {}: {}", server.pid(), e);
+    }
+
+    let start = Instant::now();
+    while server.is_running() && start.elapsed() < STOP_TIMEOUT {
+        thread::sleep(Duration::from_millis(50));
+    }
+    if server.is_running() {
+        warn!("Process {} didn't stop, killing it", server.pid());
+        let _ = server.kill();
+    }
+}
+
 fn process_is_running(name: &str) -> bool {
     for process in process::processes()
         .expect("failed to list processes")
@@ -134,11 +407,13 @@ fn process_is_running(name: &str) -> bool {
 #[allow(unused)]
 #[cfg(test)]
 mod tests {
+    use crate::test_dir::TestDir;
     use jack::{Client, PortFlags};
+    use once_cell::sync::OnceCell;
 
     // this ensures only one of these tests runs at once;
     use std::sync::{Mutex, MutexGuard};
-    static SERVER_MUTEX: super::OnceCell<Mutex<()>> = super::OnceCell::new();
+    static SERVER_MUTEX: OnceCell<Mutex<()>> = OnceCell::new();
 
     fn setup_test<'a>() -> MutexGuard<'a, ()> {
         let _ = SERVER_MUTEX.set(Mutex::new(())); // or don't we expect this to fail a lot;
@@ -163,7 +438,15 @@ mod tests {
             panic!("Ensure jack server is off before running tests");
         }
 
-        super::JackServer::new(44100, 512, false)
+        super::JackServer::new(
+            super::SpawnMode::SoftSpawn,
+            super::Driver::Dummy,
+            44100,
+            512,
+            false,
+            None,
+        )
+        .unwrap()
     }
 
     #[test]
@@ -210,6 +493,32 @@ mod tests {
         assert_eq!(client.buffer_size(), 512);
     }
 
+    #[test]
+    fn stale_pid_file_spares_other_processes() {
+        let dir = TestDir::new("pid");
+        let path = dir.join(super::PID_FILE);
+        std::fs::write(&path, format!("{}\n", std::process::id())).unwrap();
+
+        // The PID is this test, which must survive
+        assert_eq!(super::init(Some(&*dir)), Some(path.clone()));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn alsa_driver_args() {
+        let driver = super::Driver::Alsa {
+            name: "USB Audio".into(),
+            card: 2,
+            n_periods: 3,
+            in_channels: None,
+            out_channels: Some(4),
+        };
+        assert_eq!(
+            driver.args(48000, 256).join(" "),
+            "-d alsa -d hw:2 -n 3 -o 4 -r 48000 -p 256"
+        );
+    }
+
     #[test]
     fn check_no_dummy_ports() {
         let mutex = setup_test();
@@ -0,0 +1,158 @@
+//! Launch and supervise JACK client programs
+//!
+//! Programs are started from the command line stored in their
+//! `Client` settings.  The runtime polls every program it started and
+//! reports when one exits, so the model can re-spawn it.
+
+use crate::settings::Client;
+use async_std::{
+    channel::{bounded, Receiver, Sender},
+    future::timeout,
+    task,
+};
+use std::{
+    collections::BTreeMap,
+    io,
+    process::{Child, Command},
+    time::{Duration, Instant},
+};
+
+/// How often running programs are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug)]
+pub enum LauncherCmd {
+    /// Start the program of a client, unless it's already running
+    Launch(Client),
+    /// Stop supervising programs, they are left running
+    Shutdown,
+}
+
+#[derive(Clone, Debug)]
+pub enum LauncherEvent {
+    /// A program was started
+    Started { name: String, pid: u32 },
+    /// A program could not be started
+    Failed { name: String, error: String },
+    /// A program we started has exited
+    Exited {
+        name: String,
+        success: bool,
+        uptime: Duration,
+    },
+}
+
+/// An easily clonable handle to the launcher runtime
+#[derive(Clone, Debug)]
+pub struct LauncherHandle {
+    /// Send commands to the launcher runtime
+    cmd_tx: Sender<LauncherCmd>,
+    /// Receive events from the launcher runtime
+    event_rx: Receiver<LauncherEvent>,
+}
+
+impl LauncherHandle {
+    pub fn new() -> Self {
+        let (cmd_tx, cmd_rx) = bounded(128);
+        let (event_tx, event_rx) = bounded(128);
+        task::spawn(run(cmd_rx, event_tx));
+        Self { cmd_tx, event_rx }
+    }
+
+    pub async fn send_cmd(&self, cmd: LauncherCmd) {
+        if let Err(_) = self.cmd_tx.send(cmd).await {
+            error!("Failed to send CMD to launcher runtime!");
+        }
+    }
+
+    pub async fn next_event(&self) -> Option<LauncherEvent> {
+        self.event_rx.recv().await.ok()
+    }
+
+    pub fn close(&self) {
+        self.cmd_tx.close();
+        self.event_rx.close();
+    }
+}
+
+struct Running {
+    child: Child,
+    started: Instant,
+}
+
+async fn run(cmd_rx: Receiver<LauncherCmd>, event_tx: Sender<LauncherEvent>) {
+    let mut running: BTreeMap<String, Running> = BTreeMap::new();
+    let mut events = vec![];
+
+    loop {
+        match timeout(POLL_INTERVAL, cmd_rx.recv()).await {
+            Ok(Ok(LauncherCmd::Launch(client))) if running.contains_key(&client.name) => {
+                debug!("Program for client {} is already running", client.name);
+            }
+            Ok(Ok(LauncherCmd::Launch(client))) => match spawn(&client) {
+                Ok(child) => {
+                    info!("Launched {} ({:?})", client.name, client.command);
+                    events.push(LauncherEvent::Started {
+                        name: client.name.clone(),
+                        pid: child.id(),
+                    });
+                    let started = Instant::now();
+                    running.insert(client.name, Running { child, started });
+                }
+                Err(e) => {
+                    error!("Failed to launch {}: {}", client.name, e);
+                    events.push(LauncherEvent::Failed {
+                        name: client.name,
+                        error: e.to_string(),
+                    });
+                }
+            },
+            Ok(Ok(LauncherCmd::Shutdown)) | Ok(Err(_)) => break,
+            // Nothing to do, check on our programs
+            Err(_) => {}
+        }
+
+        for (name, r) in running.iter_mut() {
+            match r.child.try_wait() {
+                Ok(Some(status)) => {
+                    info!("Program for client {} exited: {}", name, status);
+                    events.push(LauncherEvent::Exited {
+                        name: name.clone(),
+                        success: status.success(),
+                        uptime: r.started.elapsed(),
+                    });
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to check on program for {}: {}", name, e),
+            }
+        }
+
+        for ev in events.drain(..) {
+            if let LauncherEvent::Exited { ref name, .. } = ev {
+                running.remove(name);
+            }
+            if let Err(_) = event_tx.send(ev).await {
+                return;
+            }
+        }
+    }
+
+    info!(
+        "Launcher shutting down, leaving {} programs running",
+        running.len()
+    );
+}
+
+fn spawn(client: &Client) -> io::Result<Child> {
+    let (program, args) = client
+        .command
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no command line set"))?;
+
+    let mut cmd = Command::new(program);
+    cmd.args(args).envs(&client.env);
+    if let Some(ref dir) = client.working_dir {
+        cmd.current_dir(dir);
+    }
+    cmd.spawn()
+}
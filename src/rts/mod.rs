@@ -1,2 +1,6 @@
+pub mod control;
 pub mod hardware;
 pub mod jack;
+pub mod launcher;
+pub mod reserve;
+pub mod watcher;
@@ -0,0 +1,465 @@
+//! Sound card reservation via `org.freedesktop.ReserveDevice1`
+//!
+//! Before jack opens a card, the bus name
+//! `org.freedesktop.ReserveDevice1.Audio<N>` has to be ours.  If
+//! PulseAudio or PipeWire holds it, we ask them to let go through
+//! `RequestRelease`.  Others can ask us the same, and we give up a
+//! card to anyone with a higher priority.
+//!
+//! See <http://git.0pointer.de/?p=reserve.git;a=blob;f=reserve.txt>
+
+use crate::cb_channel::{self, ReturningReceiver, ReturningSender};
+use crate::model::card::CardId;
+use async_std::{
+    channel::{unbounded, Receiver, Sender, TryRecvError},
+    task,
+};
+use dbus::{
+    arg::{RefArg, Variant},
+    blocking::{stdintf::org_freedesktop_dbus::RequestNameReply, LocalConnection},
+    channel::{MatchingReceiver, Sender as _},
+    message::{MatchRule, MessageType},
+    Message, MethodErr,
+};
+use std::{
+    cell::RefCell, collections::BTreeMap, collections::HashMap, rc::Rc, thread, time::Duration,
+};
+
+const INTERFACE: &str = "org.freedesktop.ReserveDevice1";
+const INTROSPECTION: &str = include_str!("../../ReserveDevice1.xml");
+
+/// How long to wait for bus traffic before checking for commands
+const PROCESS_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// How long the current owner has to release a device
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn bus_name(card: CardId) -> String {
+    format!("org.freedesktop.ReserveDevice1.Audio{}", card)
+}
+
+fn object_path(card: CardId) -> String {
+    format!("/org/freedesktop/ReserveDevice1/Audio{}", card)
+}
+
+#[derive(Clone, Debug)]
+pub enum ReserveCmd {
+    /// Reserve a card for jack
+    Acquire {
+        card: CardId,
+        device_name: String,
+        priority: i32,
+    },
+    /// Give a card back
+    Release(CardId),
+}
+
+#[derive(Clone, Debug)]
+pub enum ReserveEvent {
+    /// Another program took a card from us, it must not be used anymore
+    Lost(CardId),
+}
+
+/// An easily clonable handle to the reservation runtime
+#[derive(Clone, Debug)]
+pub struct ReserveHandle {
+    cmd_tx: ReturningSender<ReserveCmd, Result<(), String>>,
+    event_rx: Receiver<ReserveEvent>,
+}
+
+impl ReserveHandle {
+    /// Start the runtime on a connection made by `connect`
+    ///
+    /// Without a bus there is nobody to reserve cards from, so every
+    /// card is granted.
+    pub fn start<F>(connect: F) -> Self
+    where
+        F: FnOnce() -> Result<LocalConnection, dbus::Error> + Send + 'static,
+    {
+        let (cmd_tx, cmd_rx) = cb_channel::bounded(16);
+        let (event_tx, event_rx) = unbounded();
+
+        thread::spawn(move || match connect() {
+            Ok(conn) => {
+                if let Err(e) = run(conn, cmd_rx, event_tx) {
+                    error!("Device reservation failed: {}", e);
+                }
+            }
+            Err(e) => {
+                warn!(
+                    "No D-Bus for device reservation ({}), cards won't be reserved",
+                    e
+                );
+                task::block_on(async {
+                    while let Ok((_, reply)) = cmd_rx.recv().await {
+                        let _ = reply.reply(Ok(())).await;
+                    }
+                });
+            }
+        });
+
+        Self { cmd_tx, event_rx }
+    }
+
+    /// Reserve a card, asking its current owner to release it
+    pub async fn acquire(
+        &self,
+        card: CardId,
+        device_name: String,
+        priority: i32,
+    ) -> Result<(), String> {
+        let cmd = ReserveCmd::Acquire {
+            card,
+            device_name,
+            priority,
+        };
+        match self.cmd_tx.send(cmd).await {
+            Ok(res) => res,
+            Err(_) => Err("reservation runtime is gone".into()),
+        }
+    }
+
+    /// Give up the reservation of a card
+    pub async fn release(&self, card: CardId) {
+        if let Err(_) = self.cmd_tx.send(ReserveCmd::Release(card)).await {
+            error!("Failed to send CMD to reservation runtime!");
+        }
+    }
+
+    pub async fn next_event(&self) -> Option<ReserveEvent> {
+        self.event_rx.recv().await.ok()
+    }
+
+    pub fn close(&self) {
+        self.cmd_tx.close();
+        self.event_rx.close();
+    }
+}
+
+/// A card we currently hold
+#[derive(Debug)]
+struct Device {
+    device_name: String,
+    priority: i32,
+}
+
+#[derive(Debug)]
+struct State {
+    devices: BTreeMap<CardId, Device>,
+    event_tx: Sender<ReserveEvent>,
+}
+
+impl State {
+    fn card_for_path(&self, path: &str) -> Option<CardId> {
+        self.devices
+            .keys()
+            .find(|card| object_path(**card) == path)
+            .cloned()
+    }
+
+    fn card_for_name(&self, name: &str) -> Option<CardId> {
+        self.devices
+            .keys()
+            .find(|card| bus_name(**card) == name)
+            .cloned()
+    }
+
+    /// Forget a device that was taken from us
+    fn lost(&mut self, card: CardId) {
+        if self.devices.remove(&card).is_some() {
+            info!("Card {} was taken over by another program", card);
+            let _ = self.event_tx.try_send(ReserveEvent::Lost(card));
+        }
+    }
+}
+
+fn run(
+    conn: LocalConnection,
+    cmd_rx: ReturningReceiver<ReserveCmd, Result<(), String>>,
+    event_tx: Sender<ReserveEvent>,
+) -> Result<(), dbus::Error> {
+    let state = Rc::new(RefCell::new(State {
+        devices: BTreeMap::new(),
+        event_tx,
+    }));
+
+    // Somebody with `REPLACE_EXISTING` can take our names
+    conn.add_match_no_cb(
+        "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',member='NameLost'",
+    )?;
+
+    let s = state.clone();
+    conn.start_receive(
+        MatchRule::new(),
+        Box::new(move |msg, c| {
+            match msg.msg_type() {
+                MessageType::MethodCall => {
+                    let reply = handle_call(&msg, c, &s).unwrap_or_else(|e| e.to_message(&msg));
+                    let _ = c.send(reply);
+                }
+                MessageType::Signal if msg.member().as_deref() == Some("NameLost") => {
+                    if let Ok(name) = msg.read1::<&str>() {
+                        let card = s.borrow().card_for_name(name);
+                        if let Some(card) = card {
+                            s.borrow_mut().lost(card);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            true
+        }),
+    );
+
+    loop {
+        conn.process(PROCESS_TIMEOUT)?;
+
+        loop {
+            let (cmd, reply) = match cmd_rx.try_recv() {
+                Ok(req) => req,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Closed) => return Ok(()),
+            };
+
+            let res = match cmd {
+                ReserveCmd::Acquire {
+                    card,
+                    device_name,
+                    priority,
+                } => acquire(&conn, &state, card, device_name, priority),
+                ReserveCmd::Release(card) => {
+                    release(&conn, &state, card);
+                    Ok(())
+                }
+            };
+            let _ = task::block_on(reply.reply(res));
+        }
+    }
+}
+
+fn acquire(
+    conn: &LocalConnection,
+    state: &Rc<RefCell<State>>,
+    card: CardId,
+    device_name: String,
+    priority: i32,
+) -> Result<(), String> {
+    let name = bus_name(card);
+    let allow_replacement = priority < i32::MAX;
+
+    // Be ready to answer `RequestRelease` as soon as the name is ours
+    state.borrow_mut().devices.insert(
+        card,
+        Device {
+            device_name,
+            priority,
+        },
+    );
+
+    let res = request(conn, card, allow_replacement, priority);
+    match res {
+        Ok(()) => info!("Reserved {}", name),
+        Err(_) => {
+            state.borrow_mut().devices.remove(&card);
+        }
+    }
+    res
+}
+
+fn request(
+    conn: &LocalConnection,
+    card: CardId,
+    allow_replacement: bool,
+    priority: i32,
+) -> Result<(), String> {
+    let name = bus_name(card);
+    let name = name.as_str();
+    let err = |e: dbus::Error| format!("Failed to reserve {}: {}", name, e);
+
+    match conn
+        .request_name(name, allow_replacement, false, true)
+        .map_err(err)?
+    {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => return Ok(()),
+        _ => {}
+    }
+
+    let proxy = conn.with_proxy(name, object_path(card), RELEASE_TIMEOUT);
+    let (released,): (bool,) = proxy
+        .method_call(INTERFACE, "RequestRelease", (priority,))
+        .map_err(err)?;
+    if !released {
+        return Err(format!(
+            "{} is held by a program that won't release it",
+            name
+        ));
+    }
+
+    match conn
+        .request_name(name, allow_replacement, true, true)
+        .map_err(err)?
+    {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => Ok(()),
+        reply => Err(format!("Failed to reserve {}: {:?}", name, reply)),
+    }
+}
+
+fn release(conn: &LocalConnection, state: &Rc<RefCell<State>>, card: CardId) {
+    if state.borrow_mut().devices.remove(&card).is_some() {
+        let name = bus_name(card);
+        match conn.release_name(name.as_str()) {
+            Ok(_) => info!("Released {}", name),
+            Err(e) => error!("Failed to release {}: {}", name, e),
+        }
+    }
+}
+
+/// Answer method calls on our device objects
+fn handle_call(
+    msg: &Message,
+    conn: &LocalConnection,
+    state: &Rc<RefCell<State>>,
+) -> Result<Message, MethodErr> {
+    let path = msg.path().map(|p| p.to_string()).unwrap_or_default();
+    let card = state
+        .borrow()
+        .card_for_path(&path)
+        .ok_or_else(|| MethodErr::no_path(&path))?;
+    let member = msg.member().map(|m| m.to_string()).unwrap_or_default();
+
+    match (msg.interface().as_deref(), member.as_str()) {
+        (Some(INTERFACE), "RequestRelease") => {
+            let priority: i32 = msg.read1()?;
+            let ours = state.borrow().devices[&card].priority;
+            let release = priority > ours;
+
+            if release {
+                info!(
+                    "Releasing card {} for priority {} > {}",
+                    card, priority, ours
+                );
+                let _ = conn.release_name(bus_name(card).as_str());
+                state.borrow_mut().lost(card);
+            } else {
+                debug!("Not releasing card {} for priority {}", card, priority);
+            }
+            Ok(msg.method_return().append1(release))
+        }
+        (Some("org.freedesktop.DBus.Properties"), "Get") => {
+            let (_, prop): (&str, &str) = msg.read2()?;
+            let props = properties(&state.borrow().devices[&card], card);
+            match props.get(prop) {
+                Some(value) => Ok(msg.method_return().append1(Variant(value.0.box_clone()))),
+                None => Err(MethodErr::no_property(&prop)),
+            }
+        }
+        (Some("org.freedesktop.DBus.Properties"), "GetAll") => {
+            let props = properties(&state.borrow().devices[&card], card);
+            Ok(msg.method_return().append1(props))
+        }
+        (Some("org.freedesktop.DBus.Introspectable"), "Introspect") => {
+            Ok(msg.method_return().append1(INTROSPECTION))
+        }
+        _ => Err(MethodErr::no_method(&member)),
+    }
+}
+
+fn properties(device: &Device, card: CardId) -> HashMap<String, Variant<Box<dyn RefArg>>> {
+    let mut props: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+    props.insert("Priority".into(), Variant(Box::new(device.priority)));
+    props.insert(
+        "ApplicationName".into(),
+        Variant(Box::new("jackctl".to_owned())),
+    );
+    props.insert(
+        "ApplicationDeviceName".into(),
+        Variant(Box::new(format!("hw:{} ({})", card, device.device_name))),
+    );
+    props
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rts::control::bus::tests::{open, TestBus};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    /// Play PulseAudio: hold a card and release it for priorities above 0
+    fn stand_in(address: String, card: CardId, released: Arc<AtomicBool>) {
+        thread::spawn(move || {
+            let conn = open(&address).unwrap();
+            let name = bus_name(card);
+            conn.request_name(name.as_str(), true, false, true).unwrap();
+
+            let n = name.clone();
+            let r = released.clone();
+            conn.start_receive(
+                MatchRule::new_method_call(),
+                Box::new(move |msg, c| {
+                    if msg.member().as_deref() == Some("RequestRelease") {
+                        let priority: i32 = msg.read1().unwrap();
+                        let release = priority > 0;
+                        if release {
+                            c.release_name(n.as_str()).unwrap();
+                            r.store(true, Ordering::SeqCst);
+                        }
+                        let _ = c.send(msg.method_return().append1(release));
+                    }
+                    true
+                }),
+            );
+
+            loop {
+                if conn.process(Duration::from_millis(50)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn reserve_and_release_cards() {
+        let bus = match TestBus::start() {
+            Some(bus) => bus,
+            None => return eprintln!("No dbus-daemon available, skipping"),
+        };
+
+        let released = Arc::new(AtomicBool::new(false));
+        stand_in(bus.address.clone(), 3, released.clone());
+        let probe = bus.connect().unwrap();
+        bus.wait_for(&probe, &bus_name(3));
+
+        let address = bus.address.clone();
+        let handle = ReserveHandle::start(move || open(&address));
+
+        task::block_on(async {
+            // Too low a priority doesn't get the card
+            assert!(handle.acquire(3, "USB".into(), 0).await.is_err());
+            assert!(!released.load(Ordering::SeqCst));
+
+            handle.acquire(3, "USB".into(), 10).await.unwrap();
+            assert!(released.load(Ordering::SeqCst));
+        });
+
+        // Others can only take the card with a higher priority
+        let proxy = probe.with_proxy(bus_name(3), object_path(3), Duration::from_secs(1));
+        let (ok,): (bool,) = proxy
+            .method_call(INTERFACE, "RequestRelease", (5,))
+            .unwrap();
+        assert!(!ok);
+        let (ok,): (bool,) = proxy
+            .method_call(INTERFACE, "RequestRelease", (20,))
+            .unwrap();
+        assert!(ok);
+
+        task::block_on(async {
+            assert!(matches!(
+                handle.next_event().await,
+                Some(ReserveEvent::Lost(3))
+            ));
+        });
+    }
+}
@@ -0,0 +1,67 @@
+//! Watch the settings directory for edits made by other programs
+//!
+//! The watcher only reports which file changed, the model decides
+//! whether (and how) to reload it.
+
+use async_std::{
+    channel::{unbounded, Receiver},
+    task,
+};
+use inotify::{Inotify, WatchMask};
+use std::{path::Path, thread};
+
+#[derive(Clone, Debug)]
+pub struct SettingsWatcher {
+    rx: Receiver<String>,
+}
+
+impl SettingsWatcher {
+    /// Watch `dir` from a background thread
+    pub fn start(dir: &Path) -> Self {
+        let (tx, rx) = unbounded();
+
+        // Editors either rewrite a file or move a new one in its place
+        let inotify = Inotify::init().and_then(|mut inotify| {
+            inotify.add_watch(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+            Ok(inotify)
+        });
+        let mut inotify = match inotify {
+            Ok(inotify) => inotify,
+            Err(e) => {
+                error!("Can't watch {} for changes: {}", dir.display(), e);
+                return Self { rx };
+            }
+        };
+
+        thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            loop {
+                let events = match inotify.read_events_blocking(&mut buffer) {
+                    Ok(events) => events,
+                    Err(e) => {
+                        error!("Failed to read settings changes: {}", e);
+                        return;
+                    }
+                };
+
+                for name in events.filter_map(|ev| ev.name?.to_str().map(Into::into)) {
+                    if task::block_on(tx.send(name)).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Self { rx }
+    }
+
+    /// Wait for the name of the next changed file
+    ///
+    /// If the directory can't be watched this never returns.
+    pub async fn next_event(&self) -> String {
+        match self.rx.recv().await {
+            Ok(name) => name,
+            Err(_) => futures::future::pending().await,
+        }
+    }
+}
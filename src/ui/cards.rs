@@ -0,0 +1,146 @@
+//! A window to set how remembered sound cards are run
+//!
+//! Empty fields are picked automatically when the card is started.
+use super::{utils, UiRuntime};
+use crate::{
+    model::events::UiEvent,
+    settings::{Settings, SoundCard},
+};
+use gtk::prelude::*;
+use gtk::{
+    Align, Box, Button, CheckButton, Entry, Grid, Inhibit, Label, Orientation, Window, WindowType,
+};
+use std::sync::Arc;
+
+pub(super) struct CardsWindow {
+    window: Window,
+    list: Box,
+    settings: Arc<Settings>,
+    rt: UiRuntime,
+}
+
+impl CardsWindow {
+    pub fn new(settings: Arc<Settings>, rt: UiRuntime) -> Arc<Self> {
+        let window = Window::new(WindowType::Toplevel);
+        window.set_title("Sound Cards");
+        window.set_default_size(480, 400);
+        window.connect_delete_event(|w, _| {
+            w.hide();
+            Inhibit(true)
+        });
+
+        let list = Box::new(Orientation::Vertical, 10);
+        utils::margin(&list, 10);
+        let scroll = utils::wrap_scroll(&list);
+        scroll.set_vexpand(true);
+        window.add(&scroll);
+
+        Arc::new(Self {
+            window,
+            list,
+            settings,
+            rt,
+        })
+    }
+
+    pub fn show(&self) {
+        self.refresh();
+        self.window.show_all();
+    }
+
+    /// Redraw the list of known cards
+    pub fn refresh(&self) {
+        for child in self.list.get_children() {
+            self.list.remove(&child);
+        }
+
+        let cards = self.settings.r().cards();
+        if cards.cards().next().is_none() {
+            let l = Label::new(Some("No sound cards seen yet"));
+            utils::margin(&l, 10);
+            self.list.pack_start(&l, false, false, 0);
+        }
+
+        for card in cards.cards() {
+            let master = cards.master() == Some(card.name.as_str());
+            self.list
+                .pack_start(&self.card_row(card, master), false, false, 0);
+        }
+
+        self.list.show_all();
+    }
+
+    fn card_row(&self, card: &SoundCard, master: bool) -> Grid {
+        let grid = Grid::new();
+        grid.set_row_spacing(5);
+        grid.set_column_spacing(5);
+
+        let name = Label::new(None);
+        name.set_markup(&format!("<b>{}</b>", glib::markup_escape_text(&card.name)));
+        name.set_halign(Align::Start);
+        grid.attach(&name, 0, 0, 2, 1);
+
+        let use_card = CheckButton::with_label("Use this card");
+        use_card.set_active(card._use);
+        grid.attach(&use_card, 1, 1, 1, 1);
+
+        let entry = |row, label: &str, text: Option<String>| {
+            let l = Label::new(Some(label));
+            l.set_halign(Align::End);
+            let e = Entry::new();
+            e.set_text(&text.unwrap_or_default());
+            e.set_placeholder_text(Some("automatic"));
+            e.set_hexpand(true);
+            grid.attach(&l, 0, row, 1, 1);
+            grid.attach(&e, 1, row, 1, 1);
+            e
+        };
+
+        let number = |n: Option<u32>| n.map(|n| n.to_string());
+        let rate = entry(2, "Sample rate", number(card.rate));
+        let in_channels = entry(3, "Input channels", number(card.in_channels));
+        let out_channels = entry(4, "Output channels", number(card.out_channels));
+        let n_periods = entry(5, "Periods", number(card.n_periods));
+        let resample_q = entry(6, "Resample quality", number(card.resample_q));
+        let client_name = entry(7, "JACK client name", card.client_name.clone());
+
+        let run_jack = CheckButton::with_label("Run JACK on this card (after a restart)");
+        run_jack.set_active(master);
+        grid.attach(&run_jack, 1, 8, 1, 1);
+
+        let save = Button::with_label("Save");
+        save.set_halign(Align::End);
+        grid.attach(&save, 1, 9, 1, 1);
+
+        let rt = self.rt.clone();
+        let settings = self.settings.clone();
+        let card_name = card.name.clone();
+        save.connect_clicked(move |_| {
+            // Anything that isn't a number is left automatic
+            let number = |e: &Entry| e.get_text().trim().parse().ok();
+            let client_name = client_name.get_text().trim().to_owned();
+            rt.sender().send(UiEvent::UpdateCard(SoundCard {
+                name: card_name.clone(),
+                _use: use_card.get_active(),
+                rate: number(&rate),
+                in_channels: number(&in_channels),
+                out_channels: number(&out_channels),
+                n_periods: number(&n_periods),
+                resample_q: number(&resample_q),
+                client_name: if client_name.is_empty() {
+                    None
+                } else {
+                    Some(client_name)
+                },
+            }));
+
+            let master = settings.r().cards().master() == Some(card_name.as_str());
+            if run_jack.get_active() != master {
+                let name = Some(card_name.clone()).filter(|_| run_jack.get_active());
+                rt.sender().send(UiEvent::SetMasterCard(name));
+            }
+        });
+
+        grid
+    }
+}
@@ -0,0 +1,106 @@
+//! A UI sink for running jackctl without a display
+//!
+//! Instead of drawing anything this sink answers card questions from
+//! the `CardSettings` policy and logs every other command it gets.
+
+use super::{UiHandle, UiRuntime};
+use crate::{
+    model::events::{UiCmd, UiEvent},
+    settings::Settings,
+};
+use async_std::{
+    channel::{bounded, Receiver, Sender},
+    task,
+};
+use gio::prelude::*;
+use std::sync::Arc;
+
+/// Handle to the headless UI sink
+pub struct Headless {
+    done: Receiver<()>,
+    /// Keeps the application id claimed while we run
+    _app: gio::Application,
+}
+
+impl Headless {
+    /// Block the calling thread until the model asks us to terminate
+    ///
+    /// Runs the GLib main loop meanwhile, so that jackctl started a
+    /// second time gets an answer.
+    pub fn wait(self) {
+        let main_loop = glib::MainLoop::new(None, false);
+        let quit = main_loop.clone();
+        let done = self.done;
+        task::spawn(async move {
+            let _ = done.recv().await;
+            // Quit from inside the loop, it may not run yet
+            glib::MainContext::default().invoke(move || quit.quit());
+        });
+        main_loop.run();
+    }
+}
+
+async fn run(rt: UiRuntime, settings: Arc<Settings>, done: Sender<()>) {
+    while let Ok(cmd) = rt.rx_cmd.recv().await {
+        match cmd {
+            UiCmd::AskCard(card) => {
+                let usage = settings.r().cards().unattended_usage();
+                info!(
+                    "No user to ask about card '{}', {}",
+                    card.name,
+                    if usage {
+                        "activating it"
+                    } else {
+                        "ignoring it"
+                    }
+                );
+
+                let ev = UiEvent::CardUsage {
+                    card,
+                    usage,
+                    store: false,
+                };
+                if let Err(_) = rt.tx_event.send(ev).await {
+                    error!("Failed to answer card question!");
+                }
+            }
+            UiCmd::YouDontHaveToGoHomeButYouCantStayHere => {
+                info!("Headless UI shutting down");
+                break;
+            }
+            UiCmd::IncrementXRun => warn!("JACK reported an XRun"),
+            UiCmd::JackSettings(s) => trace!("{:?}", s),
+            UiCmd::AddCard(card) => info!("Card '{}' is now active", card.name),
+            UiCmd::DelCard(id) => info!("Card {} was removed", id),
+            UiCmd::SettingsError { file, error } => error!("Can't load {}: {}", file, error),
+            UiCmd::SettingsUnsaved { error } => error!("Can't save the settings: {}", error),
+            UiCmd::SettingsRecovered { file, restored, .. } => match restored {
+                Some(backup) => warn!("{} was unreadable, restored it from {}", file, backup),
+                None => warn!("{} was unreadable, reset it to the defaults", file),
+            },
+            cmd => debug!("Headless UI: {:?}", cmd),
+        }
+    }
+
+    let _ = done.send(()).await;
+}
+
+/// Claim the application id for a headless jackctl
+///
+/// Returns `None` when jackctl is already running.
+pub fn claim_headless() -> Option<gio::Application> {
+    let app = gio::Application::new(Some(super::APP_ID), gio::ApplicationFlags::empty());
+    app.connect_activate(|_| info!("jackctl was started again, but runs headless"));
+    if !super::claim_instance(&app) {
+        return None;
+    }
+    Some(app)
+}
+
+/// Create the headless UI sink on a claimed application
+pub fn create_headless(app: gio::Application, settings: Arc<Settings>) -> (Headless, UiHandle) {
+    let (rt, handle) = UiRuntime::new();
+    let (tx, done) = bounded(1);
+    task::spawn(run(rt, settings, tx));
+    (Headless { done, _app: app }, handle)
+}
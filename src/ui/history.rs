@@ -0,0 +1,100 @@
+//! The connection history page, with undo and redo buttons
+use super::{pages::Pages, utils, UiRuntime};
+use crate::model::{events::UiEvent, history::HistoryEntry};
+use async_std::sync::RwLock;
+use gtk::prelude::*;
+use gtk::{Align, Button, Orientation, Separator};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub(super) struct History {
+    entries: RwLock<Vec<HistoryEntry>>,
+    can_undo: AtomicBool,
+    can_redo: AtomicBool,
+    dirty: AtomicBool,
+    rt: UiRuntime,
+}
+
+impl History {
+    pub fn new(rt: UiRuntime) -> Self {
+        Self {
+            entries: Default::default(),
+            can_undo: AtomicBool::new(false),
+            can_redo: AtomicBool::new(false),
+            dirty: AtomicBool::new(true),
+            rt,
+        }
+    }
+
+    /// Replace the displayed history
+    pub async fn update(&self, entries: Vec<HistoryEntry>, can_undo: bool, can_redo: bool) {
+        *self.entries.write().await = entries;
+        self.can_undo.store(can_undo, Ordering::Relaxed);
+        self.can_redo.store(can_redo, Ordering::Relaxed);
+        self.dirty.fetch_or(true, Ordering::Relaxed);
+    }
+
+    fn button(&self, label: &str, tooltip: &str, sensitive: bool, ev: UiEvent) -> Button {
+        let b = Button::with_label(label);
+        b.set_tooltip_text(Some(tooltip));
+        b.set_sensitive(sensitive);
+        utils::margin(&b, 5);
+
+        let rt = self.rt.clone();
+        b.connect_clicked(move |_| rt.sender().send(ev.clone()));
+        b
+    }
+
+    /// Redraw this page if it's dirty
+    pub async fn draw(&self, pages: &Pages) {
+        if !self.dirty.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let grid = utils::grid();
+        grid.set_valign(Align::Start);
+
+        let undo = self.button(
+            "Undo",
+            "Undo the last connection change (Ctrl+Z)",
+            self.can_undo.load(Ordering::Relaxed),
+            UiEvent::Undo,
+        );
+        let redo = self.button(
+            "Redo",
+            "Redo the last undone change (Ctrl+Shift+Z)",
+            self.can_redo.load(Ordering::Relaxed),
+            UiEvent::Redo,
+        );
+        grid.attach(&undo, 0, 0, 1, 1);
+        grid.attach(&redo, 1, 0, 1, 1);
+        grid.attach(&Separator::new(Orientation::Horizontal), 0, 1, 2, 1);
+
+        let entries = self.entries.read().await;
+        if entries.is_empty() {
+            let l = utils::grid_label("No connection changes yet", false);
+            l.set_halign(Align::Center);
+            grid.attach(&l, 0, 2, 2, 1);
+        } else {
+            // Show the newest change first
+            for (row, entry) in entries.iter().rev().enumerate() {
+                let text = format!(
+                    "{} {}{}",
+                    if entry.connect {
+                        "Connected"
+                    } else {
+                        "Disconnected"
+                    },
+                    entry.label,
+                    if entry.undoable { "" } else { " (external)" }
+                );
+                let l = utils::grid_label(&text, false);
+                l.set_halign(Align::Start);
+                l.set_sensitive(entry.undoable);
+                grid.attach(&l, 0, row as i32 + 2, 2, 1);
+            }
+        }
+
+        self.dirty.fetch_and(false, Ordering::Relaxed);
+        pages.insert_scrolled("History", &grid);
+    }
+}
@@ -2,14 +2,21 @@
 
 mod about;
 mod card_query;
+mod cards;
+mod headless;
+mod history;
 mod matrix;
 mod mixer;
 mod pages;
+mod programs;
+mod rules;
+mod scenes;
 mod settings;
 mod tray;
 mod utils;
 mod window;
 
+pub use headless::{claim_headless, create_headless, Headless};
 use tray::TrayState;
 use window::MainWindow;
 
@@ -42,6 +49,15 @@ impl UiHandle {
     }
 }
 
+#[cfg(test)]
+impl UiHandle {
+    /// Create a handle plus the channel ends a test needs to play the UI
+    pub(crate) fn test_pair() -> (Self, Receiver<UiCmd>, Sender<UiEvent>) {
+        let (rt, handle) = UiRuntime::new();
+        (handle, rt.rx_cmd, rt.tx_event)
+    }
+}
+
 #[derive(Clone)]
 struct EventSender(Sender<UiEvent>);
 
@@ -121,23 +137,50 @@ impl UiRuntime {
     }
 }
 
-fn on_activate(app: &Application) {
-    trace!("On Activate()")
+/// The id jackctl registers on the session bus
+pub const APP_ID: &str = "jackctl.segfault";
+
+/// Register `app` under `APP_ID`, returning `false` if another
+/// jackctl already owns it
+///
+/// The other instance is asked to activate (show its window) first.
+fn claim_instance<A: IsA<gio::Application>>(app: &A) -> bool {
+    let app = app.upcast_ref::<gio::Application>();
+    if let Err(e) = app.register(None::<&gio::Cancellable>) {
+        crate::log::oops(format!("Failed to register application: {}", e), 1);
+    }
+
+    if !app.get_is_remote() {
+        return true;
+    }
+
+    app.activate();
+    if let Some(conn) = app.get_dbus_connection() {
+        let _ = conn.flush_sync(None::<&gio::Cancellable>);
+    }
+    false
+}
+
+fn on_activate(win: &gtk::Window) {
+    trace!("On Activate()");
+    win.present();
 }
 
-pub fn create_ui(settings: Arc<Settings>) -> (Arc<MainWindow>, Application, UiHandle, TrayState) {
+/// Start GTK and claim the application id
+///
+/// Returns `None` when jackctl is already running, after asking the
+/// running instance to show its window.
+pub fn claim_ui() -> Option<Application> {
     // Load the compiled resource bundle
     let resource_data = glib::Bytes::from(&RESOURCES_BUNDLE[..]);
     let res = gio::Resource::from_data(&resource_data).unwrap();
     gio::resources_register(&res);
 
     let app = ApplicationBuilder::new()
-        .application_id("jackctl.segfault")
+        .application_id(APP_ID)
         .resource_base_path("/net/jackctl/Jackctl")
         .build();
 
-    app.connect_activate(|app| on_activate(app));
-
     if gtk::init().is_err() {
         crate::log::oops(
             "Failed to start GTK, please ensure all dependancies are installed",
@@ -145,8 +188,22 @@ pub fn create_ui(settings: Arc<Settings>) -> (Arc<MainWindow>, Application, UiHa
         );
     }
 
+    if !claim_instance(&app) {
+        return None;
+    }
+    Some(app)
+}
+
+/// Create the main window, tray icon and UI runtime on a claimed application
+pub fn create_ui(
+    app: &Application,
+    settings: Arc<Settings>,
+) -> (Arc<MainWindow>, UiHandle, TrayState) {
     let (rt, handle) = UiRuntime::new();
-    let win = window::create(&app, settings, rt.clone());
+    let win = window::create(app, settings, rt.clone());
+    let inner = win.get_inner();
+    app.connect_activate(move |_| on_activate(&inner));
+
     let tray = TrayState::new(rt, win.get_inner());
-    (win, app, handle, tray)
+    (win, handle, tray)
 }
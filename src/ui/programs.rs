@@ -0,0 +1,161 @@
+//! A window to set how JACK clients are launched
+//!
+//! Command lines and environment variables are split on whitespace,
+//! quoting is not supported.
+use super::{utils, UiRuntime};
+use crate::{
+    model::events::UiEvent,
+    settings::{Client, Settings},
+};
+use gtk::prelude::*;
+use gtk::{
+    Align, Box, Button, CheckButton, Entry, Grid, Inhibit, Label, Orientation, Window, WindowType,
+};
+use std::sync::Arc;
+
+pub(super) struct ProgramsWindow {
+    window: Window,
+    list: Box,
+    settings: Arc<Settings>,
+    rt: UiRuntime,
+}
+
+impl ProgramsWindow {
+    pub fn new(settings: Arc<Settings>, rt: UiRuntime) -> Arc<Self> {
+        let window = Window::new(WindowType::Toplevel);
+        window.set_title("Programs");
+        window.set_default_size(520, 400);
+        window.connect_delete_event(|w, _| {
+            w.hide();
+            Inhibit(true)
+        });
+
+        let list = Box::new(Orientation::Vertical, 10);
+        utils::margin(&list, 10);
+        let scroll = utils::wrap_scroll(&list);
+        scroll.set_vexpand(true);
+        window.add(&scroll);
+
+        Arc::new(Self {
+            window,
+            list,
+            settings,
+            rt,
+        })
+    }
+
+    pub fn show(&self) {
+        self.refresh();
+        self.window.show_all();
+    }
+
+    /// Redraw the list of known clients
+    pub fn refresh(&self) {
+        for child in self.list.get_children() {
+            self.list.remove(&child);
+        }
+
+        let clients = self.settings.r().clients();
+        if clients.clients.is_empty() {
+            let l = Label::new(Some("No JACK clients seen yet"));
+            utils::margin(&l, 10);
+            self.list.pack_start(&l, false, false, 0);
+        }
+
+        for client in clients.clients.values() {
+            self.list
+                .pack_start(&self.client_row(client), false, false, 0);
+        }
+
+        self.list.show_all();
+    }
+
+    fn client_row(&self, client: &Client) -> Grid {
+        let grid = Grid::new();
+        grid.set_row_spacing(5);
+        grid.set_column_spacing(5);
+
+        let name = Label::new(None);
+        name.set_markup(&format!(
+            "<b>{}</b>",
+            glib::markup_escape_text(&client.name)
+        ));
+        name.set_halign(Align::Start);
+        grid.attach(&name, 0, 0, 2, 1);
+
+        let entry = |row, label: &str, text: String| {
+            let l = Label::new(Some(label));
+            l.set_halign(Align::End);
+            let e = Entry::new();
+            e.set_text(&text);
+            e.set_hexpand(true);
+            grid.attach(&l, 0, row, 1, 1);
+            grid.attach(&e, 1, row, 1, 1);
+            e
+        };
+
+        let command = entry(1, "Command", client.command.join(" "));
+        let dir = entry(
+            2,
+            "Working directory",
+            client
+                .working_dir
+                .as_ref()
+                .map(|d| d.display().to_string())
+                .unwrap_or_default(),
+        );
+        let env = entry(
+            3,
+            "Environment",
+            client
+                .env
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+
+        let respawn = CheckButton::with_label("Re-spawn when it crashes");
+        respawn.set_active(client.respawn);
+        grid.attach(&respawn, 1, 4, 1, 1);
+
+        let reconnect = CheckButton::with_label("Restore its connections when it comes back");
+        reconnect.set_active(client.reconnect);
+        grid.attach(&reconnect, 1, 5, 1, 1);
+
+        let save = Button::with_label("Save");
+        save.set_halign(Align::End);
+        grid.attach(&save, 1, 6, 1, 1);
+
+        let rt = self.rt.clone();
+        let client_name = client.name.clone();
+        save.connect_clicked(move |_| {
+            let dir = dir.get_text().trim().to_owned();
+            rt.sender().send(UiEvent::UpdateClient(Client {
+                name: client_name.clone(),
+                command: command
+                    .get_text()
+                    .split_whitespace()
+                    .map(Into::into)
+                    .collect(),
+                working_dir: if dir.is_empty() {
+                    None
+                } else {
+                    Some(dir.into())
+                },
+                env: env
+                    .get_text()
+                    .split_whitespace()
+                    .filter_map(|kv| {
+                        let mut split = kv.splitn(2, '=');
+                        Some((split.next()?.into(), split.next()?.into()))
+                    })
+                    .collect(),
+                respawn: respawn.get_active(),
+                reconnect: reconnect.get_active(),
+            }));
+        });
+
+        grid
+    }
+}
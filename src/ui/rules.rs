@@ -0,0 +1,209 @@
+//! A window to edit auto-connection rules
+use super::{utils, UiRuntime};
+use crate::{
+    model::events::UiEvent,
+    settings::{Rule, RuleDirection, RulePortType, Settings},
+};
+use gtk::prelude::*;
+use gtk::{
+    Adjustment, Align, Box, Button, CheckButton, ComboBoxText, Entry, EntryIconPosition, IconSize,
+    Inhibit, Label, Orientation, SpinButton, Window, WindowType,
+};
+use regex::Regex;
+use std::{cell::RefCell, sync::Arc};
+
+/// The widgets editing a single rule
+struct RuleRow {
+    row: Box,
+    enabled: CheckButton,
+    pattern: Entry,
+    target: Entry,
+    priority: SpinButton,
+    port_type: ComboBoxText,
+    direction: ComboBoxText,
+}
+
+impl RuleRow {
+    fn new(rule: &Rule) -> Self {
+        let row = Box::new(Orientation::Horizontal, 5);
+
+        let enabled = CheckButton::new();
+        enabled.set_active(rule.enabled);
+        enabled.set_tooltip_text(Some("Enabled"));
+
+        let pattern = Entry::new();
+        pattern.set_placeholder_text(Some("^Firefox:output_(\\d)$"));
+        pattern.set_text(&rule.pattern);
+        pattern.set_hexpand(true);
+        pattern.connect_changed(|e| {
+            let error = Regex::new(&e.get_text()).err().map(|e| e.to_string());
+            e.set_icon_from_icon_name(
+                EntryIconPosition::Secondary,
+                error.as_ref().map(|_| "dialog-error"),
+            );
+            e.set_icon_tooltip_text(EntryIconPosition::Secondary, error.as_deref());
+        });
+
+        let target = Entry::new();
+        target.set_placeholder_text(Some("system:playback_$1"));
+        target.set_text(&rule.target);
+        target.set_hexpand(true);
+
+        let adj = Adjustment::new(rule.priority as f64, -100.0, 100.0, 1.0, 10.0, 0.0);
+        let priority = SpinButton::new(Some(&adj), 1.0, 0);
+        priority.set_tooltip_text(Some("Priority"));
+
+        let port_type = ComboBoxText::new();
+        port_type.append(Some("any"), "Any");
+        port_type.append(Some("audio"), "Audio");
+        port_type.append(Some("midi"), "MIDI");
+        port_type.set_active_id(Some(match rule.port_type {
+            RulePortType::Any => "any",
+            RulePortType::Audio => "audio",
+            RulePortType::Midi => "midi",
+        }));
+
+        let direction = ComboBoxText::new();
+        direction.append(Some("output"), "Outputs");
+        direction.append(Some("input"), "Inputs");
+        direction.set_active_id(Some(match rule.direction {
+            RuleDirection::Output => "output",
+            RuleDirection::Input => "input",
+        }));
+
+        row.pack_start(&enabled, false, false, 0);
+        row.pack_start(&direction, false, false, 0);
+        row.pack_start(&pattern, true, true, 0);
+        row.pack_start(&Label::new(Some("→")), false, false, 0);
+        row.pack_start(&target, true, true, 0);
+        row.pack_start(&port_type, false, false, 0);
+        row.pack_start(&priority, false, false, 0);
+
+        Self {
+            row,
+            enabled,
+            pattern,
+            target,
+            priority,
+            port_type,
+            direction,
+        }
+    }
+
+    fn rule(&self) -> Rule {
+        Rule {
+            pattern: self.pattern.get_text().to_string(),
+            target: self.target.get_text().to_string(),
+            priority: self.priority.get_value_as_int(),
+            enabled: self.enabled.get_active(),
+            port_type: match self.port_type.get_active_id().as_deref() {
+                Some("audio") => RulePortType::Audio,
+                Some("midi") => RulePortType::Midi,
+                _ => RulePortType::Any,
+            },
+            direction: match self.direction.get_active_id().as_deref() {
+                Some("input") => RuleDirection::Input,
+                _ => RuleDirection::Output,
+            },
+        }
+    }
+}
+
+pub(super) struct RulesWindow {
+    window: Window,
+    list: Box,
+    rows: RefCell<Vec<RuleRow>>,
+    settings: Arc<Settings>,
+}
+
+impl RulesWindow {
+    pub fn new(settings: Arc<Settings>, rt: UiRuntime) -> Arc<Self> {
+        let window = Window::new(WindowType::Toplevel);
+        window.set_title("Auto-connection rules");
+        window.set_default_size(700, 300);
+        window.connect_delete_event(|w, _| {
+            w.hide();
+            Inhibit(true)
+        });
+
+        let vbox = Box::new(Orientation::Vertical, 5);
+        utils::margin(&vbox, 10);
+
+        let help = Label::new(Some(
+            "Ports matching a pattern are connected to the target when they appear. \
+             Use $1, $2, ... in the target to insert captured groups.",
+        ));
+        help.set_line_wrap(true);
+        help.set_halign(Align::Start);
+        vbox.pack_start(&help, false, false, 0);
+
+        let list = Box::new(Orientation::Vertical, 5);
+        let scroll = utils::wrap_scroll(&list);
+        scroll.set_vexpand(true);
+        vbox.pack_start(&scroll, true, true, 0);
+
+        let buttons = Box::new(Orientation::Horizontal, 5);
+        let add = Button::with_label("Add rule");
+        let save = Button::with_label("Save");
+        buttons.pack_start(&add, false, false, 0);
+        buttons.pack_end(&save, false, false, 0);
+        vbox.pack_start(&buttons, false, false, 0);
+
+        window.add(&vbox);
+
+        let this = Arc::new(Self {
+            window,
+            list,
+            rows: RefCell::new(vec![]),
+            settings,
+        });
+
+        let this_clone = this.clone();
+        add.connect_clicked(move |_| this_clone.add_row(&Rule::default()));
+
+        let this_clone = this.clone();
+        save.connect_clicked(move |_| {
+            let rules = this_clone.rows.borrow().iter().map(RuleRow::rule).collect();
+            rt.sender().send(UiEvent::SetRules(rules));
+            this_clone.window.hide();
+        });
+
+        this
+    }
+
+    pub fn show(self: &Arc<Self>) {
+        self.refresh();
+        self.window.show_all();
+    }
+
+    /// Rebuild the rows from the stored rules
+    pub fn refresh(self: &Arc<Self>) {
+        for r in self.rows.borrow_mut().drain(..) {
+            self.list.remove(&r.row);
+        }
+
+        let rules = self.settings.r().app().rules.clone();
+        for rule in rules.iter() {
+            self.add_row(rule);
+        }
+    }
+
+    fn add_row(self: &Arc<Self>, rule: &Rule) {
+        let row = RuleRow::new(rule);
+
+        let remove = Button::from_icon_name(Some("list-remove"), IconSize::Button);
+        remove.set_tooltip_text(Some("Remove rule"));
+        row.row.pack_start(&remove, false, false, 0);
+
+        let this = self.clone();
+        let row_box = row.row.clone();
+        remove.connect_clicked(move |_| {
+            this.list.remove(&row_box);
+            this.rows.borrow_mut().retain(|r| r.row != row_box);
+        });
+
+        self.list.pack_start(&row.row, false, false, 0);
+        row.row.show_all();
+        self.rows.borrow_mut().push(row);
+    }
+}
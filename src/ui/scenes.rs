@@ -0,0 +1,140 @@
+//! A window to save, recall and delete routing scenes
+use super::{utils, UiRuntime};
+use crate::{model::events::UiEvent, settings::Settings};
+use gtk::prelude::*;
+use gtk::{
+    Align, Box, Button, ButtonsType, DialogFlags, Entry, Inhibit, Label, MessageDialog,
+    MessageType, Orientation, Window, WindowType,
+};
+use std::sync::Arc;
+
+pub(super) struct ScenesWindow {
+    window: Window,
+    list: Box,
+    settings: Arc<Settings>,
+    rt: UiRuntime,
+}
+
+impl ScenesWindow {
+    pub fn new(settings: Arc<Settings>, rt: UiRuntime) -> Arc<Self> {
+        let window = Window::new(WindowType::Toplevel);
+        window.set_title("Scenes");
+        window.set_default_size(380, 300);
+        window.connect_delete_event(|w, _| {
+            w.hide();
+            Inhibit(true)
+        });
+
+        let vbox = Box::new(Orientation::Vertical, 5);
+        utils::margin(&vbox, 10);
+
+        // Saving a new scene
+        let save_box = Box::new(Orientation::Horizontal, 5);
+        let entry = Entry::new();
+        entry.set_placeholder_text(Some("Scene name"));
+        entry.set_hexpand(true);
+        let save = Button::with_label("Save current connections");
+        save_box.pack_start(&entry, true, true, 0);
+        save_box.pack_start(&save, false, false, 0);
+        vbox.pack_start(&save_box, false, false, 0);
+
+        let rtt = rt.clone();
+        save.connect_clicked(move |_| {
+            let name = entry.get_text().trim().to_owned();
+            if !name.is_empty() {
+                rtt.sender().send(UiEvent::SaveScene(name));
+                entry.set_text("");
+            }
+        });
+
+        // List of saved scenes
+        let list = Box::new(Orientation::Vertical, 5);
+        let scroll = utils::wrap_scroll(&list);
+        scroll.set_vexpand(true);
+        vbox.pack_start(&scroll, true, true, 0);
+
+        window.add(&vbox);
+
+        Arc::new(Self {
+            window,
+            list,
+            settings,
+            rt,
+        })
+    }
+
+    pub fn show(&self) {
+        self.refresh();
+        self.window.show_all();
+    }
+
+    /// Redraw the list of saved scenes
+    pub fn refresh(&self) {
+        for child in self.list.get_children() {
+            self.list.remove(&child);
+        }
+
+        let scenes = self.settings.r().scenes();
+        if scenes.scenes.is_empty() {
+            let l = Label::new(Some("No scenes saved yet"));
+            utils::margin(&l, 10);
+            self.list.pack_start(&l, false, false, 0);
+        }
+
+        for (name, scene) in scenes.scenes.iter() {
+            let row = Box::new(Orientation::Horizontal, 5);
+            utils::margin(&row, 5);
+
+            let l = Label::new(Some(&format!(
+                "{} ({} connections)",
+                name,
+                scene.connections.len()
+            )));
+            l.set_halign(Align::Start);
+            row.pack_start(&l, true, true, 0);
+
+            let recall = Button::with_label("Recall");
+            let rt = self.rt.clone();
+            let n = name.clone();
+            recall.connect_clicked(move |_| rt.sender().send(UiEvent::RecallScene(n.clone())));
+            row.pack_start(&recall, false, false, 0);
+
+            let delete = Button::with_label("Delete");
+            let rt = self.rt.clone();
+            let n = name.clone();
+            delete.connect_clicked(move |_| rt.sender().send(UiEvent::DeleteScene(n.clone())));
+            row.pack_start(&delete, false, false, 0);
+
+            self.list.pack_start(&row, false, false, 0);
+        }
+
+        self.list.show_all();
+    }
+
+    /// Tell the user which connections of a scene could not be made
+    pub fn report(&self, name: &str, missing: &[(String, String)]) {
+        if missing.is_empty() {
+            return;
+        }
+
+        let lines: Vec<_> = missing
+            .iter()
+            .map(|(output, input)| format!("{} → {}", output, input))
+            .collect();
+        let text = format!(
+            "Scene '{}' was recalled, but these connections have missing ports:\n\n{}",
+            name,
+            lines.join("\n")
+        );
+
+        let dialog = MessageDialog::new(
+            Some(&self.window),
+            DialogFlags::MODAL,
+            MessageType::Warning,
+            ButtonsType::Ok,
+            &text,
+        );
+        dialog.connect_response(|d, _| d.close());
+        dialog.show_all();
+    }
+}
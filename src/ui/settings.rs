@@ -34,6 +34,7 @@ impl SettingsWindow {
         let latency_view = utils::get_object(&builder, "jackSettingsLatencyDisplay");
 
         let save: Button = utils::get_object(&builder, "settingsSave");
+        let apply: Button = utils::get_object(&builder, "settingsApply");
 
         let this = Arc::new(SettingsWindow {
             window,
@@ -58,37 +59,54 @@ impl SettingsWindow {
             .connect_value_changed(move |_| this_clone.update_latency());
 
         let settings_window = this.clone();
+        let rt = runtime.clone();
         save.connect_clicked(move |_| {
             info!("Saving Settings");
             settings_window.window.hide();
+            rt.sender().send(settings_window.update());
+        });
 
-            let period_size = settings_window.period_size.get_value().round() as u32;
-            let sample_rate = settings_window.sample_rate.get_value().round() as u32;
-            let n_periods = settings_window.n_periods.get_value().round() as u32;
-            let realtime = settings_window.realtime_button.get_active();
-            let resample_q = settings_window.resample_q.get_value().round() as u32;
-
-            let event = UiEvent::UpdateSettings(UiSettingsUpdate {
-                period_size,
-                sample_rate,
-                n_periods,
-                realtime,
-                resample_q,
-            });
-
-            runtime.sender().send(event);
+        let settings_window = this.clone();
+        apply.connect_clicked(move |_| {
+            info!("Applying Settings");
+            settings_window.window.hide();
+            runtime.sender().send(settings_window.update());
+            runtime.sender().send(UiEvent::RestartJack);
         });
 
         this
     }
 
+    /// The settings currently shown
+    fn update(&self) -> UiEvent {
+        UiEvent::UpdateSettings(UiSettingsUpdate {
+            period_size: self.period_size.get_value().round() as u32,
+            sample_rate: self.sample_rate.get_value().round() as u32,
+            n_periods: self.n_periods.get_value().round() as u32,
+            realtime: self.realtime_button.get_active(),
+            resample_q: self.resample_q.get_value().round() as u32,
+        })
+    }
+
     pub fn show(&self) {
+        self.load();
+        self.window.show_all();
+    }
+
+    /// Show values that were changed elsewhere, if the window is open
+    pub fn refresh(&self) {
+        if self.window.is_visible() {
+            self.load();
+        }
+    }
+
+    /// Update settings menu from file
+    fn load(&self) {
         let app_settings = self.settings.r().app();
 
         let jack_settings = &app_settings.jack;
         jack_settings.sample_rate;
 
-        // update settings menu from file
         self.period_size.set_value(jack_settings.period_size as f64);
         self.sample_rate.set_value(jack_settings.sample_rate as f64);
         self.n_periods.set_value(jack_settings.n_periods as f64);
@@ -96,8 +114,6 @@ impl SettingsWindow {
         self.realtime_button.set_active(jack_settings.realtime);
 
         self.update_latency();
-
-        self.window.show_all();
     }
 
     pub fn update_latency(&self) {
@@ -1,24 +1,30 @@
+use super::cards::CardsWindow;
+use super::programs::ProgramsWindow;
+use super::rules::RulesWindow;
+use super::scenes::ScenesWindow;
 use super::settings::SettingsWindow;
 use super::Questionaire;
 use crate::{
     model::{
         card::Card,
-        events::{JackSettings, UiCmd, UiEvent},
+        events::{JackSettings, ServerStatus, UiCmd, UiEvent},
         port::{Port, PortType},
     },
     settings::Settings,
     ui::{
-        about::About, card_query::CardQuery, matrix::Matrix, mixer::Mixer, pages::Pages, utils,
-        UiRuntime,
+        about::About, card_query::CardQuery, history::History, matrix::Matrix, mixer::Mixer,
+        pages::Pages, utils, UiRuntime,
     },
 };
 use async_std::task::block_on;
 use atomptr::AtomPtr;
+use gdk::{keys::constants as keys, ModifierType};
 use gio::ApplicationExt;
 use glib::Continue;
 use gtk::{
-    Application, Builder, Button, ButtonExt, GtkWindowExt, Label, LabelExt, LevelBar, LevelBarExt,
-    ModelButton, WidgetExt, Window,
+    Application, Builder, Button, ButtonExt, ButtonsType, DialogExt, DialogFlags, GtkWindowExt,
+    HeaderBar, HeaderBarExt, Inhibit, Label, LabelExt, LevelBar, LevelBarExt, MessageDialog,
+    MessageType, ModelButton, WidgetExt, Window,
 };
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
@@ -37,8 +43,13 @@ pub struct MainWindow {
     audio_matrix: Matrix,
     midi_matrix: Matrix,
     mixer: Mixer,
+    history: History,
     cards: CardQuestionaire,
     settings_window: Arc<SettingsWindow>,
+    scenes_window: Arc<ScenesWindow>,
+    programs_window: Arc<ProgramsWindow>,
+    rules_window: Arc<RulesWindow>,
+    cards_window: Arc<CardsWindow>,
 }
 
 impl MainWindow {
@@ -48,24 +59,45 @@ impl MainWindow {
         builder: &Builder,
         rt: UiRuntime,
     ) -> Arc<Self> {
-        let inner = utils::get_object(builder, "maindialog");
+        let inner: Window = utils::get_object(builder, "maindialog");
         let labels = Labels::new(builder, &rt);
         let pages = Pages::new(
             builder,
-            vec!["Audio Matrix", "MIDI Matrix", "Mixer", "Setup"],
+            vec!["Audio Matrix", "MIDI Matrix", "Mixer", "History", "Setup"],
         );
 
+        // Ctrl+Z undoes, Ctrl+Shift+Z and Ctrl+Y redo
+        let rtt = rt.clone();
+        inner.connect_key_press_event(move |_, key| {
+            let state = key.get_state();
+            let ctrl = state.contains(ModifierType::CONTROL_MASK);
+            let shift = state.contains(ModifierType::SHIFT_MASK);
+            let ev = match key.get_keyval() {
+                k if ctrl && !shift && k == keys::z => UiEvent::Undo,
+                k if ctrl && shift && k == keys::Z => UiEvent::Redo,
+                k if ctrl && k == keys::y => UiEvent::Redo,
+                _ => return Inhibit(false),
+            };
+            rtt.sender().send(ev);
+            Inhibit(true)
+        });
+
         let quit: ModelButton = utils::get_object(&builder, "quit.mainmenu");
         let rtt = rt.clone();
         quit.connect_clicked(move |_| rtt.sender().send(UiEvent::Shutdown));
 
         let settings_clone = settings.clone();
         let rtt = rt.clone();
+        let scenes_window = ScenesWindow::new(settings.clone(), rt.clone());
+        let programs_window = ProgramsWindow::new(settings.clone(), rt.clone());
+        let rules_window = RulesWindow::new(settings.clone(), rt.clone());
+        let cards_window = CardsWindow::new(settings.clone(), rt.clone());
 
         let this = MainWindow {
             audio_matrix: Matrix::new(rt.clone(), "Audio Matrix"),
             midi_matrix: Matrix::new(rt.clone(), "MIDI Matrix"),
             mixer: Mixer::new(rt.clone()),
+            history: History::new(rt.clone()),
             rt,
             inner,
             labels,
@@ -74,6 +106,10 @@ impl MainWindow {
             app: app.clone(),
             cards: Default::default(),
             settings_window: SettingsWindow::new(settings_clone, rtt),
+            scenes_window,
+            programs_window,
+            rules_window,
+            cards_window,
         };
 
         // hook up the main dialog
@@ -90,6 +126,22 @@ impl MainWindow {
             arc_clone.settings_window.show();
         });
 
+        let arc_clone = arc.clone();
+        let scenes_button: ModelButton = utils::get_object(&builder, "scenes.mainmenu");
+        scenes_button.connect_clicked(move |_| arc_clone.scenes_window.show());
+
+        let arc_clone = arc.clone();
+        let programs_button: ModelButton = utils::get_object(&builder, "programs.mainmenu");
+        programs_button.connect_clicked(move |_| arc_clone.programs_window.show());
+
+        let arc_clone = arc.clone();
+        let rules_button: ModelButton = utils::get_object(&builder, "rules.mainmenu");
+        rules_button.connect_clicked(move |_| arc_clone.rules_window.show());
+
+        let arc_clone = arc.clone();
+        let cards_button: ModelButton = utils::get_object(&builder, "cards.mainmenu");
+        cards_button.connect_clicked(move |_| arc_clone.cards_window.show());
+
         arc
     }
 
@@ -104,22 +156,22 @@ impl MainWindow {
         // Setup about screen
         About::new(&builder).button(&builder);
 
-        let this = Arc::clone(self);
-        app.connect_startup(move |app| {
-            this.inner.set_application(Some(app));
-            block_on(async { this.setup_ui(app, &builder).await });
-        });
+        // The application was registered (and started up) before
+        // the window was built, so set it up right away
+        self.inner.set_application(Some(app));
+        block_on(async { self.setup_ui(app, &builder).await });
 
         self.inner.show_all();
     }
 
-    /// This function is called when Gtk application starts up
+    /// This function is called once the Gtk application has started up
     ///
     /// Don't call it from outside this type!
     async fn setup_ui(&self, app: &Application, builder: &Builder) {
         // ==^-^== Initially draw all UI elements ==^-^==
         self.audio_matrix.draw(&self.settings, &self.pages).await;
         self.midi_matrix.draw(&self.settings, &self.pages).await;
+        self.history.draw(&self.pages).await;
         self.pages.show_all();
     }
 
@@ -143,6 +195,7 @@ impl MainWindow {
             self.audio_matrix.draw(&self.settings, &self.pages).await;
             self.midi_matrix.draw(&self.settings, &self.pages).await;
             self.mixer.draw(&self.pages).await;
+            self.history.draw(&self.pages).await;
             self.pages.show_all();
         });
 
@@ -190,6 +243,7 @@ impl MainWindow {
                 self.labels.update_rate(sample_rate);
                 self.labels.update_cpu(cpu_percentage);
             }
+            UiCmd::ServerStatus(status) => self.labels.update_server(&status),
             UiCmd::AskCard(card) => {
                 trace!("Ask the user whether we should use {:?}", card);
                 match **self.cards.get_ref() {
@@ -220,6 +274,77 @@ impl MainWindow {
             UiCmd::DelCard(id) => {
                 self.mixer.del_card(id).await;
             }
+            UiCmd::SettingsChanged => {
+                self.settings_window.refresh();
+                self.rules_window.refresh();
+            }
+            UiCmd::SettingsError { file, error } => {
+                let text = format!(
+                    "{} was changed, but can't be loaded.  Your previous settings \
+                     stay active and the file won't be overwritten until it's fixed.\n\n{}",
+                    file, error
+                );
+                let dialog = MessageDialog::new(
+                    Some(&self.inner),
+                    DialogFlags::MODAL,
+                    MessageType::Error,
+                    ButtonsType::Ok,
+                    &text,
+                );
+                dialog.connect_response(|d, _| d.close());
+                dialog.show_all();
+            }
+            UiCmd::SettingsUnsaved { error } => {
+                let text = format!(
+                    "Your settings can't be saved.  Changes stay active until \
+                     jackctl quits.\n\n{}",
+                    error
+                );
+                let dialog = MessageDialog::new(
+                    Some(&self.inner),
+                    DialogFlags::MODAL,
+                    MessageType::Error,
+                    ButtonsType::Ok,
+                    &text,
+                );
+                dialog.connect_response(|d, _| d.close());
+                dialog.show_all();
+            }
+            UiCmd::SettingsRecovered {
+                file,
+                error,
+                restored,
+            } => {
+                let outcome = match restored {
+                    Some(backup) => format!("The last good backup, {}, was used instead.", backup),
+                    None => "No backup could be loaded, so the defaults were used.".into(),
+                };
+                let text = format!(
+                    "{} could not be loaded and was moved to {}.broken.1.  {}\n\n{}",
+                    file, file, outcome, error
+                );
+                let dialog = MessageDialog::new(
+                    Some(&self.inner),
+                    DialogFlags::MODAL,
+                    MessageType::Warning,
+                    ButtonsType::Ok,
+                    &text,
+                );
+                dialog.connect_response(|d, _| d.close());
+                dialog.show_all();
+            }
+            UiCmd::ScenesChanged => self.scenes_window.refresh(),
+            UiCmd::ClientsChanged => self.programs_window.refresh(),
+            UiCmd::SceneRecalled { name, missing } => {
+                self.scenes_window.report(&name, &missing);
+            }
+            UiCmd::History {
+                entries,
+                can_undo,
+                can_redo,
+            } => {
+                self.history.update(entries, can_undo, can_redo).await;
+            }
             UiCmd::YouDontHaveToGoHomeButYouCantStayHere => {
                 self.app.quit();
             }
@@ -246,6 +371,9 @@ struct Labels {
     perf_rate: Label,
     perf_frames: Label,
     perf_latency: Label,
+
+    // Server status display
+    header: HeaderBar,
 }
 
 impl Labels {
@@ -264,6 +392,9 @@ impl Labels {
             perf_rate: utils::get_object(&builder, "samplerate.performance.maindialog"),
             perf_frames: utils::get_object(&builder, "wordsize.performance.maindialog"),
             perf_latency: utils::get_object(&builder, "latency.performance.maindialog"),
+
+            // jack server status
+            header: utils::get_object(&builder, "header.maindialog"),
         });
 
         // Setup XRuns logic
@@ -322,6 +453,12 @@ impl Labels {
         self.perf_latency
             .set_markup(&format!("{}ms", latency.trunc()));
     }
+
+    fn update_server(self: &Arc<Self>, status: &ServerStatus) {
+        let text = status.to_string();
+        self.header.set_subtitle(Some(&text));
+        self.header.set_tooltip_text(Some(&text));
+    }
 }
 
 pub(super) fn create(app: &Application, settings: Arc<Settings>, rt: UiRuntime) -> Arc<MainWindow> {